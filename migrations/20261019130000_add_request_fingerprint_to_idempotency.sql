@@ -0,0 +1,3 @@
+-- Hash of the request payload, so that a key can't be reused for a different request.
+-- Nullable: rows saved before this migration don't have one.
+ALTER TABLE idempotency ADD COLUMN request_fingerprint TEXT NULL;
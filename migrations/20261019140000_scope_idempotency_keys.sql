@@ -0,0 +1,8 @@
+-- Idempotency keys are no longer tied to a user: anonymous endpoints scope them
+-- by client instead. Existing keys keep their user as scope.
+ALTER TABLE idempotency ADD COLUMN scope TEXT NULL;
+UPDATE idempotency SET scope = 'user:' || user_id::text;
+ALTER TABLE idempotency ALTER COLUMN scope SET NOT NULL;
+ALTER TABLE idempotency DROP CONSTRAINT idempotency_pkey;
+ALTER TABLE idempotency ADD PRIMARY KEY (scope, idempotency_key);
+ALTER TABLE idempotency DROP COLUMN user_id;
@@ -0,0 +1,2 @@
+-- X-Request-Id of the request that enqueued the task.
+ALTER TABLE issue_delivery_queue ADD COLUMN request_id TEXT NULL;
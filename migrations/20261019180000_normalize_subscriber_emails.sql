@@ -0,0 +1,42 @@
+-- Emails are stored normalized, see SubscriberEmail: the domain is lowercase.
+-- Existing rows predate that, normalize them and drop the duplicates it reveals.
+-- Internationalized domains were already punycode encoded by the validator.
+CREATE FUNCTION pg_temp.normalize_email(email TEXT) RETURNS TEXT AS $$
+    SELECT regexp_replace(email, '@[^@]*$', '') || lower(substring(email FROM '@[^@]*$'))
+$$ LANGUAGE SQL IMMUTABLE;
+
+-- Keep one subscriber per normalized email: confirmed first, then the oldest.
+CREATE TEMPORARY TABLE duplicate_subscriptions ON COMMIT DROP AS
+SELECT id FROM (
+    SELECT
+        id,
+        row_number() OVER (
+            PARTITION BY pg_temp.normalize_email(email)
+            ORDER BY status = 'confirmed' DESC, subscribed_at, id
+        ) AS rank
+    FROM subscriptions
+) ranked
+WHERE rank > 1;
+
+DELETE FROM subscription_tokens
+WHERE subscriber_id IN (SELECT id FROM duplicate_subscriptions);
+DELETE FROM subscriptions
+WHERE id IN (SELECT id FROM duplicate_subscriptions);
+UPDATE subscriptions
+SET email = pg_temp.normalize_email(email)
+WHERE email <> pg_temp.normalize_email(email);
+
+-- Same for pending deliveries, so nobody gets an issue twice.
+DELETE FROM issue_delivery_queue q
+USING issue_delivery_queue other
+WHERE q.newsletter_issue_id = other.newsletter_issue_id
+    AND pg_temp.normalize_email(q.subscriber_email) = pg_temp.normalize_email(other.subscriber_email)
+    AND q.subscriber_email > other.subscriber_email;
+UPDATE issue_delivery_queue
+SET subscriber_email = pg_temp.normalize_email(subscriber_email)
+WHERE subscriber_email <> pg_temp.normalize_email(subscriber_email);
+
+-- The existing UNIQUE (email) now holds on the normalized form, make sure nothing
+-- writes an address that isn't.
+ALTER TABLE subscriptions ADD CONSTRAINT subscriptions_email_domain_is_lowercase
+    CHECK (substring(email FROM '@[^@]*$') = lower(substring(email FROM '@[^@]*$')));
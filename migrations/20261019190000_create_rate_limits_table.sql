@@ -0,0 +1,9 @@
+-- Fixed window counters, shared by all instances.
+CREATE TABLE rate_limits (
+    scope TEXT NOT NULL,
+    key TEXT NOT NULL,
+    hits INT NOT NULL,
+    window_ends_at timestamptz NOT NULL,
+    PRIMARY KEY (scope, key)
+);
+CREATE INDEX rate_limits_window_ends_at ON rate_limits (window_ends_at);
@@ -0,0 +1,2 @@
+-- Confirmation links expire, see subscriptions.confirmation_token_ttl_seconds.
+ALTER TABLE subscription_tokens ADD COLUMN created_at timestamptz NOT NULL DEFAULT now();
@@ -0,0 +1,11 @@
+-- One row per logged in session, so that users can see where they're logged in and
+-- revoke sessions. The session state references its row by session_id.
+CREATE TABLE user_sessions (
+    session_id uuid PRIMARY KEY,
+    user_id uuid NOT NULL REFERENCES users (user_id) ON DELETE CASCADE,
+    created_at timestamptz NOT NULL,
+    last_seen_at timestamptz NOT NULL,
+    ip_address TEXT,
+    user_agent TEXT
+);
+CREATE INDEX user_sessions_user_id ON user_sessions (user_id);
@@ -0,0 +1,8 @@
+-- Session states, when postgres is the session store instead of redis.
+-- Keys are stored hashed so that a database dump doesn't let anyone take over a session.
+CREATE TABLE sessions (
+    key_hash TEXT PRIMARY KEY,
+    state TEXT NOT NULL,
+    expires_at timestamptz NOT NULL
+);
+CREATE INDEX sessions_expires_at ON sessions (expires_at);
@@ -0,0 +1,8 @@
+-- Hashes of the passwords users had before their current one, so that they can't go back
+-- to a recent one when changing it.
+CREATE TABLE password_history (
+    user_id uuid NOT NULL REFERENCES users (user_id) ON DELETE CASCADE,
+    password_hash TEXT NOT NULL,
+    created_at timestamptz NOT NULL
+);
+CREATE INDEX password_history_user_id_created_at ON password_history (user_id, created_at);
@@ -0,0 +1,13 @@
+-- Who did what, from where and when: logins, password changes, publishes, ...
+-- Rows outlive their actor, failed logins have none.
+CREATE TABLE audit_log (
+    id BIGSERIAL PRIMARY KEY,
+    occurred_at timestamptz NOT NULL,
+    actor_id uuid REFERENCES users (user_id) ON DELETE SET NULL,
+    action TEXT NOT NULL,
+    target TEXT,
+    ip_address TEXT,
+    request_id TEXT
+);
+CREATE INDEX audit_log_occurred_at ON audit_log (occurred_at);
+CREATE INDEX audit_log_action ON audit_log (action, occurred_at);
@@ -0,0 +1,13 @@
+-- Tokens for programmatic access, sent as `Authorization: Bearer <token>`.
+-- Only a hash of the token is stored, it's shown once to the admin who created it.
+CREATE TABLE api_tokens (
+    token_id uuid PRIMARY KEY,
+    user_id uuid NOT NULL REFERENCES users (user_id) ON DELETE CASCADE,
+    name TEXT NOT NULL,
+    token_hash TEXT NOT NULL UNIQUE,
+    scopes TEXT[] NOT NULL,
+    created_at timestamptz NOT NULL,
+    expires_at timestamptz NOT NULL,
+    last_used_at timestamptz
+);
+CREATE INDEX api_tokens_user_id ON api_tokens (user_id);
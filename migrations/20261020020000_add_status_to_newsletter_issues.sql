@@ -0,0 +1,25 @@
+-- Issues can be drafted and scheduled before they're published, see IssueStatus for
+-- the legal transitions. Existing issues were all published on creation.
+CREATE TYPE issue_status AS ENUM ('draft', 'scheduled', 'published');
+
+ALTER TABLE newsletter_issues
+    ALTER COLUMN published_at DROP NOT NULL,
+    ALTER COLUMN published_at TYPE timestamptz USING published_at::timestamptz,
+    ADD COLUMN status issue_status NOT NULL DEFAULT 'published',
+    ADD COLUMN created_at timestamptz,
+    ADD COLUMN updated_at timestamptz,
+    ADD COLUMN scheduled_for timestamptz,
+    -- Confirmed subscribers when the issue was published, unknown for older issues.
+    ADD COLUMN recipient_count BIGINT;
+
+UPDATE newsletter_issues
+SET created_at = published_at, updated_at = published_at;
+
+ALTER TABLE newsletter_issues
+    ALTER COLUMN status DROP DEFAULT,
+    ALTER COLUMN created_at SET NOT NULL,
+    ALTER COLUMN updated_at SET NOT NULL;
+
+CREATE INDEX newsletter_issues_created_at ON newsletter_issues (created_at);
+CREATE INDEX newsletter_issues_scheduled_for ON newsletter_issues (scheduled_for)
+    WHERE status = 'scheduled';
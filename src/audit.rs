@@ -0,0 +1,230 @@
+//! The audit log: who did what, from where and when, for the actions worth answering for
+//! later on. Rows are written by the handlers and listed on /admin/audit.
+use actix_web::HttpRequest;
+use anyhow::Context;
+use chrono::{DateTime, NaiveDate, Utc};
+use sqlx::{PgExecutor, PgPool};
+use uuid::Uuid;
+
+use crate::authentication::SessionMetadata;
+use crate::request_id::current_request_id;
+
+/// What an audit log row records, stored as its `as_str` name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuditAction {
+    LogIn,
+    FailedLogIn,
+    LogOut,
+    PasswordChange,
+    NewsletterPublish,
+    NewsletterSchedule,
+    SessionRevoke,
+    OtherSessionsRevoke,
+    LogFilterChange,
+    ApiTokenCreate,
+    ApiTokenRevoke,
+}
+
+impl AuditAction {
+    pub const ALL: [AuditAction; 11] = [
+        AuditAction::LogIn,
+        AuditAction::FailedLogIn,
+        AuditAction::LogOut,
+        AuditAction::PasswordChange,
+        AuditAction::NewsletterPublish,
+        AuditAction::NewsletterSchedule,
+        AuditAction::SessionRevoke,
+        AuditAction::OtherSessionsRevoke,
+        AuditAction::LogFilterChange,
+        AuditAction::ApiTokenCreate,
+        AuditAction::ApiTokenRevoke,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AuditAction::LogIn => "login",
+            AuditAction::FailedLogIn => "failed_login",
+            AuditAction::LogOut => "logout",
+            AuditAction::PasswordChange => "password_change",
+            AuditAction::NewsletterPublish => "newsletter_publish",
+            AuditAction::NewsletterSchedule => "newsletter_schedule",
+            AuditAction::SessionRevoke => "session_revoke",
+            AuditAction::OtherSessionsRevoke => "other_sessions_revoke",
+            AuditAction::LogFilterChange => "log_filter_change",
+            AuditAction::ApiTokenCreate => "api_token_create",
+            AuditAction::ApiTokenRevoke => "api_token_revoke",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|action| action.as_str() == s)
+    }
+}
+
+/// Records that `actor` did `action` to `target`, along with the IP address and the id of
+/// `request`. Pass a transaction to record the event along with the change it describes.
+#[tracing::instrument(name = "Record an audit event", skip(executor, request))]
+pub async fn record_audit_event(
+    executor: impl PgExecutor<'_>,
+    request: &HttpRequest,
+    actor: Option<Uuid>,
+    action: AuditAction,
+    target: Option<&str>,
+) -> Result<(), anyhow::Error> {
+    let ip_address = SessionMetadata::from_request(request).ip_address;
+    let request_id = current_request_id().map(|id| id.to_string());
+    sqlx::query!(
+        r#"
+        INSERT INTO audit_log (occurred_at, actor_id, action, target, ip_address, request_id)
+        VALUES (now(), $1, $2, $3, $4, $5)
+        "#,
+        actor,
+        action.as_str(),
+        target,
+        ip_address,
+        request_id,
+    )
+    .execute(executor)
+    .await
+    .context("Failed to record an audit event.")?;
+    Ok(())
+}
+
+/// Restricts the audit events listed, all of them by default.
+#[derive(Debug, Default, Clone)]
+pub struct AuditLogFilter {
+    pub action: Option<AuditAction>,
+    // Username of the actor.
+    pub actor: Option<String>,
+    // Both days included, in UTC.
+    pub since: Option<NaiveDate>,
+    pub until: Option<NaiveDate>,
+}
+
+/// A row of the audit log, with the actor's username.
+pub struct AuditEvent {
+    pub id: i64,
+    pub occurred_at: DateTime<Utc>,
+    pub actor: Option<String>,
+    pub action: String,
+    pub target: Option<String>,
+    pub ip_address: Option<String>,
+    pub request_id: Option<String>,
+}
+
+/// Returns at most `limit` events matching `filter`, the most recent first. With `before`,
+/// the `occurred_at` and `id` of an event, only those listed after it.
+#[tracing::instrument(name = "List audit events", skip(pool))]
+pub async fn list_audit_events(
+    pool: &PgPool,
+    filter: &AuditLogFilter,
+    before: Option<(DateTime<Utc>, i64)>,
+    limit: i64,
+) -> Result<Vec<AuditEvent>, anyhow::Error> {
+    let since = filter.since.map(start_of_day);
+    let until = filter
+        .until
+        .and_then(|day| day.succ_opt())
+        .map(start_of_day);
+    let events = sqlx::query_as!(
+        AuditEvent,
+        r#"
+        SELECT a.id, a.occurred_at, u.username AS "actor?", a.action, a.target,
+            a.ip_address, a.request_id
+        FROM audit_log a
+        LEFT JOIN users u ON u.user_id = a.actor_id
+        WHERE ($1::text IS NULL OR a.action = $1)
+            AND ($2::text IS NULL OR u.username = $2)
+            AND ($3::timestamptz IS NULL OR a.occurred_at >= $3)
+            AND ($4::timestamptz IS NULL OR a.occurred_at < $4)
+            AND ($5::timestamptz IS NULL OR (a.occurred_at, a.id) < ($5, $6))
+        ORDER BY a.occurred_at DESC, a.id DESC
+        LIMIT $7
+        "#,
+        filter.action.map(|action| action.as_str()),
+        filter.actor,
+        since,
+        until,
+        before.map(|(occurred_at, _)| occurred_at),
+        before.map(|(_, id)| id),
+        limit,
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to list audit events.")?;
+    Ok(events)
+}
+
+fn start_of_day(day: NaiveDate) -> DateTime<Utc> {
+    day.and_time(chrono::NaiveTime::MIN).and_utc()
+}
+
+/// First line of the CSV export.
+pub const AUDIT_CSV_HEADER: &str = "occurred_at,actor,action,target,ip_address,request_id\r\n";
+
+/// Renders `events` as CSV lines, to follow `AUDIT_CSV_HEADER`.
+pub fn audit_events_to_csv(events: &[AuditEvent]) -> String {
+    let mut csv = String::new();
+    for event in events {
+        let fields = [
+            event.occurred_at.to_rfc3339(),
+            event.actor.clone().unwrap_or_default(),
+            event.action.clone(),
+            event.target.clone().unwrap_or_default(),
+            event.ip_address.clone().unwrap_or_default(),
+            event.request_id.clone().unwrap_or_default(),
+        ];
+        let fields: Vec<String> = fields.iter().map(|field| csv_field(field)).collect();
+        csv.push_str(&fields.join(","));
+        csv.push_str("\r\n");
+    }
+    csv
+}
+
+/// Quotes `field` if needed. Fields a spreadsheet would take for a formula, e.g. a username
+/// typed in the login form, are prefixed with a quote so they're only ever read as text.
+fn csv_field(field: &str) -> String {
+    let field = if field.starts_with(['=', '+', '-', '@', '\t', '\r']) {
+        format!("'{}", field)
+    } else {
+        field.to_owned()
+    };
+    if field.contains([',', '"', '\r', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{csv_field, AuditAction};
+
+    #[test]
+    fn actions_round_trip_through_their_names() {
+        for action in AuditAction::ALL {
+            assert_eq!(AuditAction::parse(action.as_str()), Some(action));
+        }
+        assert_eq!(AuditAction::parse("unknown"), None);
+    }
+
+    #[test]
+    fn plain_fields_are_left_alone() {
+        assert_eq!(csv_field("admin"), "admin");
+        assert_eq!(csv_field(""), "");
+    }
+
+    #[test]
+    fn fields_with_separators_or_quotes_are_quoted() {
+        assert_eq!(csv_field("a,b"), "\"a,b\"");
+        assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+        assert_eq!(csv_field("two\nlines"), "\"two\nlines\"");
+    }
+
+    #[test]
+    fn formulas_are_defused() {
+        assert_eq!(csv_field("=HYPERLINK(1)"), "'=HYPERLINK(1)");
+        assert_eq!(csv_field("=1,2"), "\"'=1,2\"");
+        assert_eq!(csv_field("@SUM(A1)"), "'@SUM(A1)");
+    }
+}
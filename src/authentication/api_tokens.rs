@@ -0,0 +1,247 @@
+use actix_web::http::header::{HeaderValue, WWW_AUTHENTICATE};
+use actix_web::HttpResponse;
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use rand::distributions::Alphanumeric;
+use rand::rngs::OsRng;
+use rand::Rng;
+use secrecy::{ExposeSecret, Secret};
+use sha2::{Digest, Sha256};
+use sqlx::{PgExecutor, PgPool};
+use uuid::Uuid;
+
+use crate::request_id::current_request_id;
+
+/// Tokens start with it, for secret scanners to recognise them in leaked code or logs.
+const TOKEN_PREFIX: &str = "zp_";
+
+/// What an API token lets its bearer do, stored as its `as_str` name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ApiScope {
+    IssuesRead,
+    // Drafting and editing issues, not sending them.
+    IssuesWrite,
+    NewslettersPublish,
+}
+
+impl ApiScope {
+    pub const ALL: [ApiScope; 3] = [
+        ApiScope::IssuesRead,
+        ApiScope::IssuesWrite,
+        ApiScope::NewslettersPublish,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ApiScope::IssuesRead => "issues:read",
+            ApiScope::IssuesWrite => "issues:write",
+            ApiScope::NewslettersPublish => "newsletters:publish",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|scope| scope.as_str() == s)
+    }
+}
+
+/// The API token a request was authenticated with, added to its extensions along with
+/// the `UserId` of the token's owner.
+#[derive(Clone, Debug)]
+pub struct ApiToken {
+    pub token_id: Uuid,
+    pub user_id: Uuid,
+    pub scopes: Vec<ApiScope>,
+}
+
+impl ApiToken {
+    /// Rejects the request with a 403 unless the token was granted `scope`.
+    pub fn require_scope(&self, scope: ApiScope) -> Result<(), actix_web::Error> {
+        if self.scopes.contains(&scope) {
+            return Ok(());
+        }
+        let challenge = format!(
+            r#"Bearer error="insufficient_scope", scope="{}""#,
+            scope.as_str()
+        );
+        let message = format!("The API token lacks the {} scope.", scope.as_str());
+        let response = HttpResponse::Forbidden()
+            .insert_header((
+                WWW_AUTHENTICATE,
+                HeaderValue::from_str(&challenge).expect("Scopes are valid header values."),
+            ))
+            .json(serde_json::json!({
+                "error": {
+                    "code": "insufficient_scope",
+                    "message": &message,
+                    "request_id": current_request_id().map(|id| id.to_string()),
+                }
+            }));
+        let e = anyhow::anyhow!(message);
+        Err(actix_web::error::InternalError::from_response(e, response).into())
+    }
+}
+
+/// An API token of a user, as listed on /admin/api_tokens.
+pub struct ApiTokenRecord {
+    pub token_id: Uuid,
+    pub name: String,
+    pub scopes: Vec<String>,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    pub last_used_at: Option<DateTime<Utc>>,
+}
+
+/// Creates an API token for `user_id` and returns its id and the token itself, which
+/// can't be recovered afterwards.
+#[tracing::instrument(name = "Create an API token", skip(executor))]
+pub async fn create_api_token(
+    executor: impl PgExecutor<'_>,
+    user_id: Uuid,
+    name: &str,
+    scopes: &[ApiScope],
+    expires_at: DateTime<Utc>,
+) -> Result<(Uuid, Secret<String>), anyhow::Error> {
+    let token_id = Uuid::new_v4();
+    let token = generate_token();
+    let scopes: Vec<String> = scopes.iter().map(|s| s.as_str().to_owned()).collect();
+    sqlx::query!(
+        r#"
+        INSERT INTO api_tokens
+            (token_id, user_id, name, token_hash, scopes, created_at, expires_at)
+        VALUES ($1, $2, $3, $4, $5, now(), $6)
+        "#,
+        token_id,
+        user_id,
+        name,
+        hash_token(&token),
+        &scopes,
+        expires_at,
+    )
+    .execute(executor)
+    .await
+    .context("Failed to create an API token.")?;
+    Ok((token_id, token))
+}
+
+/// Looks up an unexpired token and marks it as used now. `None` if it's unknown, expired
+/// or revoked.
+#[tracing::instrument(name = "Authenticate an API token", skip_all)]
+pub async fn authenticate_api_token(
+    pool: &PgPool,
+    token: &Secret<String>,
+) -> Result<Option<ApiToken>, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"
+        UPDATE api_tokens
+        SET last_used_at = now()
+        WHERE token_hash = $1 AND expires_at > now()
+        RETURNING token_id, user_id, scopes
+        "#,
+        hash_token(token),
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to look up an API token.")?;
+    Ok(row.map(|row| ApiToken {
+        token_id: row.token_id,
+        user_id: row.user_id,
+        // Scopes that have since been removed from the code grant nothing.
+        scopes: row
+            .scopes
+            .iter()
+            .filter_map(|s| ApiScope::parse(s))
+            .collect(),
+    }))
+}
+
+/// Returns the API tokens of `user_id`, expired ones included, the newest first.
+#[tracing::instrument(name = "List API tokens", skip(pool))]
+pub async fn list_api_tokens(
+    pool: &PgPool,
+    user_id: Uuid,
+) -> Result<Vec<ApiTokenRecord>, anyhow::Error> {
+    let tokens = sqlx::query_as!(
+        ApiTokenRecord,
+        r#"
+        SELECT token_id, name, scopes, created_at, expires_at, last_used_at
+        FROM api_tokens
+        WHERE user_id = $1
+        ORDER BY created_at DESC
+        "#,
+        user_id,
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to list API tokens.")?;
+    Ok(tokens)
+}
+
+/// Revokes one of the API tokens of `user_id`. Returns false if there's no such token.
+#[tracing::instrument(name = "Revoke an API token", skip(executor))]
+pub async fn revoke_api_token(
+    executor: impl PgExecutor<'_>,
+    user_id: Uuid,
+    token_id: Uuid,
+) -> Result<bool, anyhow::Error> {
+    let n_deleted = sqlx::query!(
+        "DELETE FROM api_tokens WHERE token_id = $1 AND user_id = $2",
+        token_id,
+        user_id,
+    )
+    .execute(executor)
+    .await
+    .context("Failed to revoke an API token.")?
+    .rows_affected();
+    Ok(n_deleted == 1)
+}
+
+/// Revokes all of the API tokens of `user_id`, e.g. when their password changes.
+/// Returns the number of revoked tokens.
+#[tracing::instrument(name = "Revoke all API tokens", skip(executor))]
+pub async fn revoke_all_api_tokens(
+    executor: impl PgExecutor<'_>,
+    user_id: Uuid,
+) -> Result<u64, anyhow::Error> {
+    let n_deleted = sqlx::query!("DELETE FROM api_tokens WHERE user_id = $1", user_id)
+        .execute(executor)
+        .await
+        .context("Failed to revoke the API tokens.")?
+        .rows_affected();
+    Ok(n_deleted)
+}
+
+/// The prefix and 48 alphanumeric characters from the OS' RNG.
+fn generate_token() -> Secret<String> {
+    let random: String = std::iter::repeat_with(|| OsRng.sample(Alphanumeric))
+        .map(char::from)
+        .take(48)
+        .collect();
+    Secret::new(format!("{}{}", TOKEN_PREFIX, random))
+}
+
+/// Tokens are random enough for a plain hash: a database dump doesn't give them away.
+fn hash_token(token: &Secret<String>) -> String {
+    hex::encode(Sha256::digest(token.expose_secret().as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{generate_token, hash_token, ApiScope, TOKEN_PREFIX};
+    use secrecy::ExposeSecret;
+
+    #[test]
+    fn scopes_round_trip_through_their_names() {
+        for scope in ApiScope::ALL {
+            assert_eq!(ApiScope::parse(scope.as_str()), Some(scope));
+        }
+        assert_eq!(ApiScope::parse("admin"), None);
+    }
+
+    #[test]
+    fn tokens_are_prefixed_and_unique() {
+        let (a, b) = (generate_token(), generate_token());
+        assert!(a.expose_secret().starts_with(TOKEN_PREFIX));
+        assert_eq!(a.expose_secret().len(), TOKEN_PREFIX.len() + 48);
+        assert_ne!(hash_token(&a), hash_token(&b));
+    }
+}
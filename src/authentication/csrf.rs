@@ -0,0 +1,67 @@
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::error::InternalError;
+use actix_web::http::header::HeaderName;
+use actix_web::http::Method;
+use actix_web::{web, FromRequest, HttpMessage, HttpResponse};
+use actix_web_lab::middleware::Next;
+use subtle::ConstantTimeEq;
+
+use crate::session_state::TypedSession;
+use crate::utils::{bytes_to_payload, e500};
+
+pub const CSRF_TOKEN_HEADER: HeaderName = HeaderName::from_static("x-csrf-token");
+pub const CSRF_TOKEN_FIELD: &str = "csrf_token";
+
+/// Middleware rejecting state changing requests that don't carry the session's
+/// CSRF token with a 403.
+///
+/// The token is read from the `X-CSRF-Token` header, or from the `csrf_token` field
+/// of a url-encoded form. Safe methods (GET, HEAD, OPTIONS) are let through.
+pub async fn reject_invalid_csrf_tokens(
+    mut req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    if matches!(*req.method(), Method::GET | Method::HEAD | Method::OPTIONS) {
+        return next.call(req).await;
+    }
+    let session = {
+        let (http_request, payload) = req.parts_mut();
+        TypedSession::from_request(http_request, payload).await
+    }?;
+    let expected = session.get_csrf_token().map_err(e500)?;
+
+    let provided = match req.headers().get(CSRF_TOKEN_HEADER) {
+        Some(value) => value.to_str().ok().map(str::to_owned),
+        None => {
+            // The handler still needs the body, put it back once read.
+            let body = req.extract::<web::Bytes>().await?;
+            req.set_payload(bytes_to_payload(body.clone()));
+            find_form_field(&req, &body)
+        }
+    };
+
+    match (expected, provided) {
+        (Some(expected), Some(provided))
+            if bool::from(expected.as_bytes().ct_eq(provided.as_bytes())) =>
+        {
+            next.call(req).await
+        }
+        _ => {
+            let response = HttpResponse::Forbidden()
+                .body("The form has expired, please reload the page and try again.");
+            let e = anyhow::anyhow!("Missing or invalid CSRF token.");
+            Err(InternalError::from_response(e, response).into())
+        }
+    }
+}
+
+fn find_form_field(req: &ServiceRequest, body: &[u8]) -> Option<String> {
+    if req.content_type() != "application/x-www-form-urlencoded" {
+        return None;
+    }
+    serde_urlencoded::from_bytes::<Vec<(String, String)>>(body)
+        .ok()?
+        .into_iter()
+        .find_map(|(name, value)| (name == CSRF_TOKEN_FIELD).then_some(value))
+}
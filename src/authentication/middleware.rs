@@ -1,12 +1,20 @@
 use std::ops::Deref;
 
+use super::{authenticate_api_token, has_expired, touch_session};
+use crate::configuration::SessionSettings;
+use crate::request_id::current_request_id;
 use crate::session_state::TypedSession;
 use crate::utils::{e500, see_other};
 use actix_web::body::MessageBody;
 use actix_web::dev::{ServiceRequest, ServiceResponse};
 use actix_web::error::InternalError;
-use actix_web::{FromRequest, HttpMessage};
+use actix_web::http::header::{AUTHORIZATION, WWW_AUTHENTICATE};
+use actix_web::{web, FromRequest, HttpMessage, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
 use actix_web_lab::middleware::Next;
+use chrono::Utc;
+use secrecy::Secret;
+use sqlx::PgPool;
 use uuid::Uuid;
 
 #[derive(Copy, Clone, Debug)]
@@ -26,25 +34,123 @@ impl Deref for UserId {
     }
 }
 
+/// Middleware redirecting to /login the requests of anonymous users, and of
+/// sessions that have been revoked or have timed out.
 pub async fn reject_anonymous_users(
     mut req: ServiceRequest,
-    next: Next<impl MessageBody>,
+    next: Next<impl MessageBody + 'static>,
 ) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
     let session = {
         let (http_request, payload) = req.parts_mut();
         TypedSession::from_request(http_request, payload).await
     }?;
 
-    match session.get_user_id().map_err(e500)? {
-        Some(user_id) => {
-            req.extensions_mut().insert(UserId(user_id));
-            next.call(req).await
-        }
-        None => {
-            // User not logged in, they must log in.
-            let response = see_other("/login");
-            let e = anyhow::anyhow!("The user is not logged in.");
-            Err(InternalError::from_response(e, response).into())
-        }
+    let Some(user_id) = session.get_user_id().map_err(e500)? else {
+        // User not logged in, they must log in.
+        let response = see_other("/login");
+        let e = anyhow::anyhow!("The user is not logged in.");
+        return Err(InternalError::from_response(e, response).into());
+    };
+
+    let (Some(pool), Some(settings)) = (
+        req.app_data::<web::Data<PgPool>>(),
+        req.app_data::<web::Data<SessionSettings>>(),
+    ) else {
+        let e = anyhow::anyhow!("The authentication middleware is missing its app data.");
+        return Err(e500(e));
+    };
+
+    let now = Utc::now();
+    if has_expired(&session, settings, now).map_err(e500)? {
+        let response = end_session(
+            req,
+            session,
+            "Your session has expired, please log in again.",
+        );
+        return Ok(response.map_into_right_body());
+    }
+
+    // Sessions opened before sessions were recorded have no id, they must log in again.
+    let is_active = match session.get_session_id().map_err(e500)? {
+        Some(session_id) => touch_session(pool, user_id, session_id)
+            .await
+            .map_err(e500)?,
+        None => false,
+    };
+    if !is_active {
+        let response = end_session(req, session, "Your session has ended, please log in again.");
+        return Ok(response.map_into_right_body());
     }
+    // Sliding expiry: every request pushes the idle timeout back.
+    session.insert_last_seen_at(now).map_err(e500)?;
+
+    req.extensions_mut().insert(UserId(user_id));
+    next.call(req)
+        .await
+        .map(ServiceResponse::map_into_left_body)
+}
+
+/// Logs the session out and redirects to /login, telling the user why.
+/// Returned as a response rather than an error: errors skip the outer middlewares,
+/// and with them the flash message.
+fn end_session(req: ServiceRequest, session: TypedSession, message: &str) -> ServiceResponse {
+    session.log_out();
+    FlashMessage::info(message).send();
+    req.into_response(see_other("/login"))
+}
+
+/// Middleware rejecting with a 401 the requests without a valid `Authorization: Bearer`
+/// API token. The counterpart of `reject_anonymous_users` for programmatic clients: it
+/// adds the same `UserId`, along with the `ApiToken` for handlers to check its scopes.
+pub async fn reject_invalid_api_tokens(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let Some(token) = bearer_token(&req) else {
+        return Err(unauthorized(
+            "Bearer",
+            "missing_token",
+            "Send an API token as `Authorization: Bearer <token>`.",
+        ));
+    };
+    let Some(pool) = req.app_data::<web::Data<PgPool>>() else {
+        let e = anyhow::anyhow!("The API token middleware is missing its app data.");
+        return Err(e500(e));
+    };
+    let Some(api_token) = authenticate_api_token(pool, &token).await.map_err(e500)? else {
+        return Err(unauthorized(
+            r#"Bearer error="invalid_token""#,
+            "invalid_token",
+            "The API token is unknown, expired or revoked.",
+        ));
+    };
+    req.extensions_mut().insert(UserId(api_token.user_id));
+    req.extensions_mut().insert(api_token);
+    next.call(req).await
+}
+
+fn bearer_token(req: &ServiceRequest) -> Option<Secret<String>> {
+    let header = req.headers().get(AUTHORIZATION)?.to_str().ok()?;
+    let (scheme, token) = header.split_once(' ')?;
+    let token = token.trim();
+    (scheme.eq_ignore_ascii_case("Bearer") && !token.is_empty())
+        .then(|| Secret::new(token.to_owned()))
+}
+
+/// A 401 with the same JSON body as the /api routes' errors.
+fn unauthorized(
+    challenge: &'static str,
+    code: &'static str,
+    message: &'static str,
+) -> actix_web::Error {
+    let response = HttpResponse::Unauthorized()
+        .insert_header((WWW_AUTHENTICATE, challenge))
+        .json(serde_json::json!({
+            "error": {
+                "code": code,
+                "message": message,
+                "request_id": current_request_id().map(|id| id.to_string()),
+            }
+        }));
+    InternalError::from_response(anyhow::anyhow!(message), response).into()
 }
@@ -1,6 +1,24 @@
+mod api_tokens;
+mod csrf;
 mod middleware;
 mod password;
+mod password_policy;
+mod sessions;
+mod timeouts;
 
-pub use middleware::reject_anonymous_users;
+pub use api_tokens::{
+    authenticate_api_token, create_api_token, list_api_tokens, revoke_all_api_tokens,
+    revoke_api_token, ApiScope, ApiToken, ApiTokenRecord,
+};
+pub use csrf::{reject_invalid_csrf_tokens, CSRF_TOKEN_FIELD, CSRF_TOKEN_HEADER};
 pub use middleware::UserId;
-pub use password::{change_password, validate_credentials, AuthError, Credentials};
+pub use middleware::{reject_anonymous_users, reject_invalid_api_tokens};
+pub use password::{
+    change_password, is_recent_password, validate_credentials, AuthError, Credentials,
+};
+pub use password_policy::PasswordPolicy;
+pub use sessions::{
+    list_sessions, record_session, revoke_other_sessions, revoke_session, touch_session,
+    SessionMetadata, SessionRecord,
+};
+pub use timeouts::{has_expired, needs_reauthentication};
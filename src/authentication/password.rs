@@ -1,9 +1,11 @@
+use crate::configuration::PasswordHashingSettings;
+use crate::metrics::METRICS;
 use crate::telemetry::spawn_blocking_with_tracing;
 use anyhow::Context;
 use argon2::password_hash::SaltString;
-use argon2::{Algorithm, Argon2, Params, PasswordHash, PasswordHasher, PasswordVerifier};
+use argon2::{Algorithm, Argon2, Params, PasswordHash, PasswordHasher, PasswordVerifier, Version};
 use secrecy::{ExposeSecret, Secret};
-use sqlx::PgPool;
+use sqlx::{PgPool, Postgres, Transaction};
 
 #[derive(thiserror::Error, Debug)]
 pub enum AuthError {
@@ -18,17 +20,22 @@ pub struct Credentials {
     pub password: Secret<String>,
 }
 
-/// TODO: comment
-#[tracing::instrument(name = "Validate credentials", skip(credentials, pool))]
+/// Checks the credentials and returns the id of their user.
+///
+/// Stored hashes that don't follow the current hashing policy, e.g. older parameters, are
+/// replaced with a fresh hash of the password on the way.
+#[tracing::instrument(name = "Validate credentials", skip(credentials, pool, hashing))]
 pub async fn validate_credentials(
     credentials: Credentials,
     pool: &PgPool,
+    hashing: &PasswordHashingSettings,
 ) -> Result<uuid::Uuid, AuthError> {
     let mut user_id = None;
+    // Verified for unknown usernames too, so that they take as long as known ones.
     let mut expected_password_hash = Secret::new(
-        "$argon2id$v=19$m=15000,t=2,p=1$\
-        gZiV/M1gPc22ElAH/Jh1Hw$\
-        CWOrkoo7oJBQ/iyh7uJ0LO2aLEfrHwTWllSAxT0zRno"
+        "$argon2id$v=19$m=19456,t=2,p=1$\
+        MkVK4IH6Sl38fmANixJ52g$\
+        toVTnV8Qi+ZdJTQTAB4CFPPLABFrXBxtZcEQwY15ASY"
             .to_string(),
     );
 
@@ -39,15 +46,74 @@ pub async fn validate_credentials(
         expected_password_hash = stored_password_hash;
     }
 
+    let stored_password_hash = expected_password_hash.clone();
+    let password = credentials.password.clone();
     spawn_blocking_with_tracing(move || {
         verify_password_hash(expected_password_hash, credentials.password)
     })
     .await
     .context("Failed to spawn a blocking task.")??;
 
-    user_id
+    let user_id = user_id
         .ok_or_else(|| anyhow::anyhow!("Unknown username."))
-        .map_err(AuthError::InvalidCredentials)
+        .map_err(AuthError::InvalidCredentials)?;
+
+    if needs_rehash(&stored_password_hash, hashing) {
+        // The user is logged in regardless, we'll try again on their next login.
+        if let Err(e) =
+            upgrade_password_hash(user_id, &stored_password_hash, password, pool, hashing).await
+        {
+            tracing::warn!(error.cause_chain = ?e, "Failed to upgrade a password hash.");
+        }
+    }
+    Ok(user_id)
+}
+
+/// Whether the stored hash was computed with another algorithm or other parameters
+/// than the current policy.
+fn needs_rehash(password_hash: &Secret<String>, hashing: &PasswordHashingSettings) -> bool {
+    let Ok(password_hash) = PasswordHash::new(password_hash.expose_secret()) else {
+        return true;
+    };
+    let Ok(params) = Params::try_from(&password_hash) else {
+        return true;
+    };
+    password_hash.algorithm != Algorithm::Argon2id.ident()
+        || password_hash.version != Some(Version::V0x13.into())
+        || params.m_cost() != hashing.memory_kib
+        || params.t_cost() != hashing.iterations
+        || params.p_cost() != hashing.parallelism
+}
+
+/// Replaces the stored hash with one following the current policy.
+/// Left alone if the password changed in the meantime.
+#[tracing::instrument(name = "Upgrade password hash", skip_all, fields(user_id=%user_id))]
+async fn upgrade_password_hash(
+    user_id: uuid::Uuid,
+    stored_password_hash: &Secret<String>,
+    password: Secret<String>,
+    pool: &PgPool,
+    hashing: &PasswordHashingSettings,
+) -> Result<(), anyhow::Error> {
+    let hashing = hashing.clone();
+    let password_hash =
+        spawn_blocking_with_tracing(move || compute_password_hash(password, &hashing))
+            .await?
+            .context("Failed to hash password")?;
+    sqlx::query!(
+        r#"
+            UPDATE users
+            SET password_hash = $1
+            WHERE user_id = $2 AND password_hash = $3
+        "#,
+        password_hash.expose_secret(),
+        user_id,
+        stored_password_hash.expose_secret(),
+    )
+    .execute(pool)
+    .await
+    .context("Failed to store the upgraded password hash.")?;
+    Ok(())
 }
 
 #[tracing::instrument(
@@ -58,6 +124,7 @@ fn verify_password_hash(
     expected_password_hash: Secret<String>,
     password_candidate: Secret<String>,
 ) -> Result<(), AuthError> {
+    let _timer = METRICS.password_verification_duration_seconds.start_timer();
     let expected_password_hash = PasswordHash::new(expected_password_hash.expose_secret())
         .context("Failed to parse hash in PHC string format")?;
 
@@ -91,15 +158,93 @@ async fn get_stored_credentials(
     Ok(row)
 }
 
-#[tracing::instrument(name = "Change password", skip(password, pool))]
+/// Whether `password` is one of the user's `history_size` latest passwords, the current
+/// one included.
+#[tracing::instrument(name = "Check password history", skip(password, pool))]
+pub async fn is_recent_password(
+    user_id: uuid::Uuid,
+    password: &Secret<String>,
+    pool: &PgPool,
+    history_size: i64,
+) -> Result<bool, anyhow::Error> {
+    if history_size <= 0 {
+        return Ok(false);
+    }
+    let password_hashes: Vec<Secret<String>> = sqlx::query_scalar!(
+        r#"
+            SELECT password_hash AS "password_hash!" FROM users WHERE user_id = $1
+            UNION ALL
+            (
+                SELECT password_hash FROM password_history
+                WHERE user_id = $1
+                ORDER BY created_at DESC
+                LIMIT $2
+            )
+        "#,
+        user_id,
+        history_size - 1,
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to query the password history.")?
+    .into_iter()
+    .map(Secret::new)
+    .collect();
+
+    let password = password.clone();
+    spawn_blocking_with_tracing(move || {
+        password_hashes
+            .into_iter()
+            .any(|hash| verify_password_hash(hash, password.clone()).is_ok())
+    })
+    .await
+    .context("Failed to spawn a blocking task.")
+}
+
+/// Replaces the user's password, keeping the hash of the previous one in their history.
+/// Only the `history_size` latest passwords are kept, the new one included.
+/// Runs in `transaction`, for the caller to commit along with what goes with the change.
+#[tracing::instrument(name = "Change password", skip(password, transaction, hashing))]
 pub async fn change_password(
     user_id: uuid::Uuid,
     password: Secret<String>,
-    pool: &PgPool,
+    transaction: &mut Transaction<'_, Postgres>,
+    hashing: &PasswordHashingSettings,
+    history_size: i64,
 ) -> Result<(), anyhow::Error> {
-    let password_hash = spawn_blocking_with_tracing(move || compute_password_hash(password))
-        .await?
-        .context("Failed to hash password")?;
+    let hashing = hashing.clone();
+    let password_hash =
+        spawn_blocking_with_tracing(move || compute_password_hash(password, &hashing))
+            .await?
+            .context("Failed to hash password")?;
+    sqlx::query!(
+        r#"
+            INSERT INTO password_history (user_id, password_hash, created_at)
+            SELECT user_id, password_hash, now() FROM users WHERE user_id = $1
+        "#,
+        user_id
+    )
+    .execute(&mut **transaction)
+    .await
+    .context("Failed to record the previous password.")?;
+    // Deletes all but the `history_size - 1` latest previous passwords.
+    sqlx::query!(
+        r#"
+            DELETE FROM password_history
+            WHERE user_id = $1 AND created_at <= (
+                SELECT created_at FROM password_history
+                WHERE user_id = $1
+                ORDER BY created_at DESC
+                OFFSET $2
+                LIMIT 1
+            )
+        "#,
+        user_id,
+        (history_size - 1).max(0),
+    )
+    .execute(&mut **transaction)
+    .await
+    .context("Failed to prune the password history.")?;
     sqlx::query!(
         r#"
             UPDATE users
@@ -109,20 +254,19 @@ pub async fn change_password(
         password_hash.expose_secret(),
         user_id
     )
-    .execute(pool)
+    .execute(&mut **transaction)
     .await
     .context("Failed to change user's password in the database.")?;
     Ok(())
 }
 
-fn compute_password_hash(password: Secret<String>) -> Result<Secret<String>, anyhow::Error> {
+fn compute_password_hash(
+    password: Secret<String>,
+    hashing: &PasswordHashingSettings,
+) -> Result<Secret<String>, anyhow::Error> {
     let salt = SaltString::generate(&mut rand::thread_rng());
-    let password_hash = Argon2::new(
-        Algorithm::Argon2d,
-        argon2::Version::V0x13,
-        Params::new(15000, 2, 1, None).unwrap(),
-    )
-    .hash_password(password.expose_secret().as_bytes(), &salt)?
-    .to_string();
+    let password_hash = Argon2::new(Algorithm::Argon2id, Version::V0x13, hashing.params()?)
+        .hash_password(password.expose_secret().as_bytes(), &salt)?
+        .to_string();
     Ok(Secret::new(password_hash))
 }
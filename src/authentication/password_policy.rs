@@ -0,0 +1,198 @@
+use std::collections::HashSet;
+
+use secrecy::{ExposeSecret, Secret};
+
+use crate::configuration::PasswordPolicySettings;
+
+const BREACHED_PASSWORDS: &str = include_str!("breached_passwords.txt");
+
+/// Rules new passwords must follow, checked when users change their password.
+pub struct PasswordPolicy {
+    min_length: usize,
+    max_length: usize,
+    min_entropy_bits: f64,
+    history_size: i64,
+    breached_passwords: HashSet<String>,
+}
+
+impl PasswordPolicy {
+    pub fn from_settings(settings: &PasswordPolicySettings) -> Self {
+        Self {
+            min_length: settings.min_length,
+            max_length: settings.max_length,
+            min_entropy_bits: settings.min_entropy_bits,
+            history_size: settings.history_size,
+            breached_passwords: BREACHED_PASSWORDS
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                .map(str::to_lowercase)
+                .collect(),
+        }
+    }
+
+    /// How many of the user's latest passwords, the current one included, can't be reused.
+    pub fn history_size(&self) -> i64 {
+        self.history_size
+    }
+
+    /// Returns a message for the user if `password` is rejected.
+    pub fn check(&self, password: &Secret<String>, username: &str) -> Result<(), String> {
+        let password = password.expose_secret();
+        let length = password.chars().count();
+        if length < self.min_length {
+            return Err(format!(
+                "The new password must be at least {} characters long.",
+                self.min_length
+            ));
+        }
+        if length > self.max_length {
+            return Err(format!(
+                "The new password must be at most {} characters long.",
+                self.max_length
+            ));
+        }
+        let lowercase_password = password.to_lowercase();
+        if self.breached_passwords.contains(&lowercase_password) {
+            return Err(
+                "The new password appears in lists of breached passwords, please pick another one."
+                    .into(),
+            );
+        }
+        if !username.is_empty() && lowercase_password.contains(&username.to_lowercase()) {
+            return Err("The new password must not contain your username.".into());
+        }
+        let estimate = estimate_strength(password);
+        if estimate.entropy_bits < self.min_entropy_bits {
+            return Err(format!(
+                "The new password is too weak. {}",
+                estimate.feedback.join(" ")
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// A rough estimate of how hard a password is to guess.
+struct StrengthEstimate {
+    entropy_bits: f64,
+    // Suggestions to make the password stronger.
+    feedback: Vec<&'static str>,
+}
+
+/// Counts log2(pool size) bits per character, the pool being made of the character classes
+/// the password uses. Characters repeating or following on from the previous one, as in
+/// "aaa" or "abc", only count for a bit.
+fn estimate_strength(password: &str) -> StrengthEstimate {
+    let chars: Vec<char> = password.chars().collect();
+    let classes = [
+        (chars.iter().any(char::is_ascii_lowercase), 26),
+        (chars.iter().any(char::is_ascii_uppercase), 26),
+        (chars.iter().any(char::is_ascii_digit), 10),
+        (
+            chars.iter().any(|c| c.is_ascii_punctuation() || *c == ' '),
+            33,
+        ),
+        (chars.iter().any(|c| !c.is_ascii()), 100),
+    ];
+    let n_classes = classes.iter().filter(|(used, _)| *used).count();
+    let pool_size: u32 = classes
+        .iter()
+        .filter(|(used, _)| *used)
+        .map(|(_, size)| size)
+        .sum();
+    let bits_per_char = f64::from(pool_size.max(1)).log2();
+
+    let mut entropy_bits = 0.0;
+    let (mut has_repeats, mut has_sequences) = (false, false);
+    for (i, c) in chars.iter().enumerate() {
+        let previous = i.checked_sub(1).map(|i| chars[i]);
+        match previous {
+            Some(previous) if previous == *c => {
+                has_repeats = true;
+                entropy_bits += 1.0;
+            }
+            Some(previous) if (*c as i64 - previous as i64).abs() == 1 => {
+                has_sequences = true;
+                entropy_bits += 1.0;
+            }
+            _ => entropy_bits += bits_per_char,
+        }
+    }
+
+    let mut feedback = Vec::new();
+    if has_repeats {
+        feedback.push("Avoid repeated characters, like aaa.");
+    }
+    if has_sequences {
+        feedback.push("Avoid sequences, like abc or 123.");
+    }
+    if n_classes < 3 {
+        feedback.push("Mix in upper case letters, digits or symbols.");
+    }
+    feedback.push("Longer is stronger: a few unrelated words make a good password.");
+    StrengthEstimate {
+        entropy_bits,
+        feedback,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use secrecy::Secret;
+
+    use super::{estimate_strength, PasswordPolicy};
+    use crate::configuration::PasswordPolicySettings;
+
+    fn policy() -> PasswordPolicy {
+        PasswordPolicy::from_settings(&PasswordPolicySettings {
+            min_length: 12,
+            max_length: 128,
+            min_entropy_bits: 50.0,
+            history_size: 5,
+        })
+    }
+
+    fn check(password: &str) -> Result<(), String> {
+        policy().check(&Secret::new(password.to_owned()), "admin")
+    }
+
+    #[test]
+    fn random_looking_passwords_are_accepted() {
+        assert!(check("correct-Horse-7-battery").is_ok());
+        assert!(check("b3f1c8e2-4d7a-4e90-9c2b").is_ok());
+    }
+
+    #[test]
+    fn passwords_outside_the_length_bounds_are_rejected() {
+        assert!(check("Sh0rt!").unwrap_err().contains("at least 12"));
+        assert!(check(&"Ab1!".repeat(40))
+            .unwrap_err()
+            .contains("at most 128"));
+    }
+
+    #[test]
+    fn breached_passwords_are_rejected_whatever_their_case() {
+        assert!(check("Password1234").unwrap_err().contains("breached"));
+    }
+
+    #[test]
+    fn passwords_containing_the_username_are_rejected() {
+        assert!(check("my-Admin-pass-42").unwrap_err().contains("username"));
+    }
+
+    #[test]
+    fn repeats_and_sequences_are_weak_and_explained() {
+        let message = check("aaaaaabcdefghijk").unwrap_err();
+        assert!(message.contains("too weak"));
+        assert!(message.contains("repeated characters"));
+        assert!(message.contains("sequences"));
+    }
+
+    #[test]
+    fn more_character_classes_mean_more_entropy() {
+        let lowercase = estimate_strength("qzmxnwkr");
+        let mixed = estimate_strength("qZm4nW!r");
+        assert!(mixed.entropy_bits > lowercase.entropy_bits);
+    }
+}
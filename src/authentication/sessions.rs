@@ -0,0 +1,166 @@
+use std::time::Duration;
+
+use actix_web::http::header::USER_AGENT;
+use actix_web::HttpRequest;
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::{PgExecutor, PgPool};
+use uuid::Uuid;
+
+use crate::client_ip::client_ip;
+
+/// Where a session was opened from, recorded on login.
+pub struct SessionMetadata {
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+impl SessionMetadata {
+    pub fn from_request(request: &HttpRequest) -> Self {
+        let ip_address = client_ip(request).map(|ip| ip.to_string());
+        let user_agent = request
+            .headers()
+            .get(USER_AGENT)
+            .and_then(|h| h.to_str().ok())
+            .map(str::to_owned);
+        Self {
+            ip_address,
+            user_agent,
+        }
+    }
+}
+
+/// A logged in session of a user, as listed on /admin/sessions.
+pub struct SessionRecord {
+    pub session_id: Uuid,
+    pub created_at: DateTime<Utc>,
+    pub last_seen_at: DateTime<Utc>,
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+/// Records a new session of `user_id` and returns its id, to be stored in the session state.
+///
+/// The user's sessions not seen for longer than `session_ttl`, gone from the session store,
+/// are deleted on the way.
+#[tracing::instrument(name = "Record a new session", skip(pool, metadata))]
+pub async fn record_session(
+    pool: &PgPool,
+    user_id: Uuid,
+    metadata: &SessionMetadata,
+    session_ttl: Duration,
+) -> Result<Uuid, anyhow::Error> {
+    sqlx::query!(
+        r#"
+        DELETE FROM user_sessions
+        WHERE user_id = $1 AND last_seen_at <= now() - $2::interval
+        "#,
+        user_id,
+        session_ttl as Duration,
+    )
+    .execute(pool)
+    .await
+    .context("Failed to delete expired sessions.")?;
+
+    let session_id = Uuid::new_v4();
+    sqlx::query!(
+        r#"
+        INSERT INTO user_sessions
+            (session_id, user_id, created_at, last_seen_at, ip_address, user_agent)
+        VALUES ($1, $2, now(), now(), $3, $4)
+        "#,
+        session_id,
+        user_id,
+        metadata.ip_address,
+        metadata.user_agent,
+    )
+    .execute(pool)
+    .await
+    .context("Failed to record a new session.")?;
+    Ok(session_id)
+}
+
+/// Marks the session as seen now. Returns false if it was revoked.
+#[tracing::instrument(name = "Touch a session", skip(pool))]
+pub async fn touch_session(
+    pool: &PgPool,
+    user_id: Uuid,
+    session_id: Uuid,
+) -> Result<bool, anyhow::Error> {
+    let n_updated = sqlx::query!(
+        r#"
+        UPDATE user_sessions
+        SET last_seen_at = now()
+        WHERE session_id = $1 AND user_id = $2
+        "#,
+        session_id,
+        user_id,
+    )
+    .execute(pool)
+    .await
+    .context("Failed to touch a session.")?
+    .rows_affected();
+    Ok(n_updated == 1)
+}
+
+/// Returns the sessions of `user_id` seen within `session_ttl`, most recently used first.
+#[tracing::instrument(name = "List sessions", skip(pool))]
+pub async fn list_sessions(
+    pool: &PgPool,
+    user_id: Uuid,
+    session_ttl: Duration,
+) -> Result<Vec<SessionRecord>, anyhow::Error> {
+    let sessions = sqlx::query_as!(
+        SessionRecord,
+        r#"
+        SELECT session_id, created_at, last_seen_at, ip_address, user_agent
+        FROM user_sessions
+        WHERE user_id = $1 AND last_seen_at > now() - $2::interval
+        ORDER BY last_seen_at DESC
+        "#,
+        user_id,
+        session_ttl as Duration,
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to list sessions.")?;
+    Ok(sessions)
+}
+
+/// Revokes one of the sessions of `user_id`, it's logged out on its next request.
+#[tracing::instrument(name = "Revoke a session", skip(pool))]
+pub async fn revoke_session(
+    pool: &PgPool,
+    user_id: Uuid,
+    session_id: Uuid,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        "DELETE FROM user_sessions WHERE session_id = $1 AND user_id = $2",
+        session_id,
+        user_id,
+    )
+    .execute(pool)
+    .await
+    .context("Failed to revoke a session.")?;
+    Ok(())
+}
+
+/// Revokes all the sessions of `user_id` but `current_session_id`.
+/// Returns the number of revoked sessions.
+#[tracing::instrument(name = "Revoke other sessions", skip(executor))]
+pub async fn revoke_other_sessions(
+    executor: impl PgExecutor<'_>,
+    user_id: Uuid,
+    current_session_id: Uuid,
+) -> Result<u64, anyhow::Error> {
+    let n_revoked = sqlx::query!(
+        "DELETE FROM user_sessions WHERE user_id = $1 AND session_id <> $2",
+        user_id,
+        current_session_id,
+    )
+    .execute(executor)
+    .await
+    .context("Failed to revoke the other sessions.")?
+    .rows_affected();
+    Ok(n_revoked)
+}
@@ -0,0 +1,151 @@
+use chrono::{DateTime, Utc};
+
+use crate::configuration::SessionSettings;
+use crate::session_state::TypedSession;
+
+/// Whether the session is past its idle or absolute timeout.
+/// Sessions opened before timeouts were tracked have expired, they must log in again.
+pub fn has_expired(
+    session: &TypedSession,
+    settings: &SessionSettings,
+    now: DateTime<Utc>,
+) -> Result<bool, anyhow::Error> {
+    let (Some(logged_in_at), Some(last_seen_at)) =
+        (session.get_logged_in_at()?, session.get_last_seen_at()?)
+    else {
+        return Ok(true);
+    };
+    let remember_me = session.get_remember_me()?;
+    Ok(
+        is_older_than(logged_in_at, settings.absolute_timeout(remember_me), now)
+            || is_older_than(last_seen_at, settings.idle_timeout(remember_me), now),
+    )
+}
+
+/// Whether the user must type their password again before a sensitive action.
+pub fn needs_reauthentication(
+    session: &TypedSession,
+    settings: &SessionSettings,
+    now: DateTime<Utc>,
+) -> Result<bool, anyhow::Error> {
+    match session.get_authenticated_at()? {
+        Some(authenticated_at) => Ok(is_older_than(
+            authenticated_at,
+            settings.reauthentication_timeout(),
+            now,
+        )),
+        None => Ok(true),
+    }
+}
+
+fn is_older_than(time: DateTime<Utc>, timeout: std::time::Duration, now: DateTime<Utc>) -> bool {
+    // A timeout too large for chrono never runs out.
+    match chrono::Duration::from_std(timeout) {
+        Ok(timeout) => now - time > timeout,
+        Err(_) => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{has_expired, needs_reauthentication};
+    use crate::configuration::{SessionSettings, SessionStoreKind};
+    use crate::session_state::TypedSession;
+    use actix_web::test::TestRequest;
+    use actix_web::FromRequest;
+    use chrono::{DateTime, Duration, Utc};
+
+    fn settings() -> SessionSettings {
+        SessionSettings {
+            store: SessionStoreKind::Redis,
+            idle_timeout_seconds: 60,
+            absolute_timeout_seconds: 600,
+            remember_me_timeout_seconds: 6000,
+            reauthentication_subscriber_threshold: 1,
+            reauthentication_timeout_seconds: 30,
+        }
+    }
+
+    // Session times are stored to the millisecond, a round one survives the trip.
+    fn login_time() -> DateTime<Utc> {
+        DateTime::from_timestamp(1_700_000_000, 0).unwrap()
+    }
+
+    /// A session logged in at `logged_in_at`.
+    fn session(remember_me: bool, logged_in_at: DateTime<Utc>) -> TypedSession {
+        let request = TestRequest::default().to_http_request();
+        let session = TypedSession::extract(&request).into_inner().unwrap();
+        session.start(remember_me, logged_in_at).unwrap();
+        session
+    }
+
+    #[test]
+    fn idle_sessions_expire() {
+        let login = login_time();
+        let session = session(false, login);
+
+        assert!(!has_expired(&session, &settings(), login + Duration::seconds(60)).unwrap());
+        assert!(has_expired(&session, &settings(), login + Duration::seconds(61)).unwrap());
+    }
+
+    #[test]
+    fn activity_pushes_the_idle_timeout_back() {
+        let login = login_time();
+        let session = session(false, login);
+
+        session
+            .insert_last_seen_at(login + Duration::seconds(50))
+            .unwrap();
+
+        assert!(!has_expired(&session, &settings(), login + Duration::seconds(100)).unwrap());
+    }
+
+    #[test]
+    fn active_sessions_expire_after_the_absolute_timeout() {
+        let login = login_time();
+        let session = session(false, login);
+
+        session
+            .insert_last_seen_at(login + Duration::seconds(590))
+            .unwrap();
+
+        assert!(!has_expired(&session, &settings(), login + Duration::seconds(600)).unwrap());
+        assert!(has_expired(&session, &settings(), login + Duration::seconds(601)).unwrap());
+    }
+
+    #[test]
+    fn remember_me_extends_both_timeouts() {
+        let login = login_time();
+        let session = session(true, login);
+
+        session
+            .insert_last_seen_at(login + Duration::seconds(5000))
+            .unwrap();
+
+        assert!(!has_expired(&session, &settings(), login + Duration::seconds(6000)).unwrap());
+        assert!(has_expired(&session, &settings(), login + Duration::seconds(6001)).unwrap());
+    }
+
+    #[test]
+    fn sessions_without_timestamps_have_expired() {
+        let request = TestRequest::default().to_http_request();
+        let session = TypedSession::extract(&request).into_inner().unwrap();
+
+        assert!(has_expired(&session, &settings(), Utc::now()).unwrap());
+        assert!(needs_reauthentication(&session, &settings(), Utc::now()).unwrap());
+    }
+
+    #[test]
+    fn the_password_is_needed_again_after_the_reauthentication_timeout() {
+        let login = login_time();
+        let session = session(false, login);
+
+        let now = login + Duration::seconds(30);
+        assert!(!needs_reauthentication(&session, &settings(), now).unwrap());
+        let now = login + Duration::seconds(31);
+        assert!(needs_reauthentication(&session, &settings(), now).unwrap());
+
+        session.insert_authenticated_at(now).unwrap();
+        assert!(!needs_reauthentication(&session, &settings(), now).unwrap());
+    }
+}
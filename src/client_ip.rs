@@ -0,0 +1,143 @@
+//! Who sent a request, for rate limits, idempotency scopes, sessions and the audit log.
+use std::net::{IpAddr, SocketAddr};
+
+use actix_web::http::header::{HeaderName, FORWARDED};
+use actix_web::{web, HttpRequest};
+
+const X_FORWARDED_FOR: HeaderName = HeaderName::from_static("x-forwarded-for");
+
+/// Proxies in front of the application, the only peers whose `Forwarded` and
+/// `X-Forwarded-For` headers are believed.
+#[derive(Clone, Debug, Default)]
+pub struct TrustedProxies(pub Vec<IpAddr>);
+
+impl TrustedProxies {
+    fn contains(&self, ip: &IpAddr) -> bool {
+        self.0.contains(ip)
+    }
+}
+
+/// Address of the client that sent `request`.
+///
+/// That's the peer of the connection, clients can put whatever they like in forwarding
+/// headers. When the peer is a trusted proxy, the forwarded addresses are walked from the
+/// last, i.e. the one appended by that proxy, to the first that isn't a trusted proxy.
+pub fn client_ip(request: &HttpRequest) -> Option<IpAddr> {
+    let peer = request.peer_addr()?.ip();
+    let no_proxies = TrustedProxies::default();
+    let trusted_proxies = request
+        .app_data::<web::Data<TrustedProxies>>()
+        .map(|t| t.get_ref())
+        .unwrap_or(&no_proxies);
+    Some(resolve_client_ip(
+        peer,
+        &forwarded_for(request),
+        trusted_proxies,
+    ))
+}
+
+fn resolve_client_ip(
+    peer: IpAddr,
+    forwarded_for: &[Option<IpAddr>],
+    trusted_proxies: &TrustedProxies,
+) -> IpAddr {
+    let mut client = peer;
+    for forwarded in forwarded_for.iter().rev() {
+        if !trusted_proxies.contains(&client) {
+            break;
+        }
+        match forwarded {
+            Some(ip) => client = *ip,
+            // e.g. "unknown": we can't tell past this point.
+            None => break,
+        }
+    }
+    client
+}
+
+/// The `for` addresses of the `Forwarded` header, or `X-Forwarded-For` if there is none,
+/// in order. Entries that aren't IP addresses are None.
+fn forwarded_for(request: &HttpRequest) -> Vec<Option<IpAddr>> {
+    let headers = request.headers();
+    let forwarded: Vec<&str> = headers
+        .get_all(FORWARDED)
+        .filter_map(|h| h.to_str().ok())
+        .collect();
+    if !forwarded.is_empty() {
+        return forwarded
+            .iter()
+            .flat_map(|h| h.split(','))
+            .filter_map(|element| {
+                element.split(';').find_map(|pair| {
+                    let (name, value) = pair.trim().split_once('=')?;
+                    name.eq_ignore_ascii_case("for").then_some(value)
+                })
+            })
+            .map(parse_ip)
+            .collect();
+    }
+    headers
+        .get_all(X_FORWARDED_FOR)
+        .filter_map(|h| h.to_str().ok())
+        .flat_map(|h| h.split(','))
+        .map(parse_ip)
+        .collect()
+}
+
+/// Parses `192.0.2.1`, `"192.0.2.1:4711"`, `"[2001:db8::1]"` and the like.
+fn parse_ip(s: &str) -> Option<IpAddr> {
+    let s = s.trim().trim_matches('"');
+    s.parse::<IpAddr>()
+        .ok()
+        .or_else(|| s.parse::<SocketAddr>().ok().map(|a| a.ip()))
+        .or_else(|| s.strip_prefix('[')?.strip_suffix(']')?.parse().ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_ip, resolve_client_ip, TrustedProxies};
+    use std::net::IpAddr;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn forwarding_headers_are_ignored_without_a_trusted_proxy() {
+        let client = resolve_client_ip(
+            ip("203.0.113.7"),
+            &[Some(ip("198.51.100.1"))],
+            &TrustedProxies::default(),
+        );
+        assert_eq!(client, ip("203.0.113.7"));
+    }
+
+    #[test]
+    fn the_address_appended_by_a_trusted_proxy_is_used() {
+        let proxies = TrustedProxies(vec![ip("10.0.0.1"), ip("10.0.0.2")]);
+        // The client claimed to be 198.51.100.1, the proxies saw 203.0.113.7.
+        let forwarded = [
+            Some(ip("198.51.100.1")),
+            Some(ip("203.0.113.7")),
+            Some(ip("10.0.0.2")),
+        ];
+        let client = resolve_client_ip(ip("10.0.0.1"), &forwarded, &proxies);
+        assert_eq!(client, ip("203.0.113.7"));
+    }
+
+    #[test]
+    fn unknown_forwarded_addresses_stop_the_walk() {
+        let proxies = TrustedProxies(vec![ip("10.0.0.1")]);
+        let client = resolve_client_ip(ip("10.0.0.1"), &[None], &proxies);
+        assert_eq!(client, ip("10.0.0.1"));
+    }
+
+    #[test]
+    fn forwarded_values_are_parsed_with_or_without_ports() {
+        assert_eq!(parse_ip(" 192.0.2.1"), Some(ip("192.0.2.1")));
+        assert_eq!(parse_ip("\"192.0.2.1:4711\""), Some(ip("192.0.2.1")));
+        assert_eq!(parse_ip("\"[2001:db8::1]\""), Some(ip("2001:db8::1")));
+        assert_eq!(parse_ip("\"[2001:db8::1]:4711\""), Some(ip("2001:db8::1")));
+        assert_eq!(parse_ip("unknown"), None);
+    }
+}
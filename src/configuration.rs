@@ -1,11 +1,16 @@
 // Configurations
 use secrecy::{ExposeSecret, Secret};
-use serde_aux::field_attributes::deserialize_number_from_string;
+use serde_aux::field_attributes::{
+    deserialize_number_from_string, deserialize_option_number_from_string,
+};
 use sqlx::postgres::PgConnectOptions;
 use sqlx::postgres::PgSslMode;
 use sqlx::ConnectOptions;
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+use std::time::Duration;
 
-use crate::domain::SubscriberEmail;
+use crate::domain::{EmailNormalization, SubscriberEmail};
 use crate::email_client::EmailClient;
 
 #[derive(serde::Deserialize, Clone, Debug)]
@@ -13,7 +18,18 @@ pub struct Settings {
     pub database: DatabaseSettings,
     pub application: ApplicationSettings,
     pub email_client: EmailClientSettings,
-    // May embed a password so much be secret.
+    pub idempotency: IdempotencySettings,
+    pub metrics: MetricsSettings,
+    pub telemetry: TelemetrySettings,
+    pub health: HealthSettings,
+    pub subscriptions: SubscriptionSettings,
+    pub security_headers: SecurityHeadersSettings,
+    pub session: SessionSettings,
+    pub password_hashing: PasswordHashingSettings,
+    pub password_policy: PasswordPolicySettings,
+    // Set from APP_ENVIRONMENT by `get_configuration`.
+    pub environment: Environment,
+    // May embed a password so much be secret. Unused when sessions are stored in postgres.
     pub redis_uri: Secret<String>,
 }
 
@@ -34,6 +50,296 @@ impl EmailClientSettings {
     }
 }
 
+#[derive(serde::Deserialize, Clone, Debug)]
+pub struct IdempotencySettings {
+    // How long a saved response is replayed for, after that the key is fresh again.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub ttl_seconds: u64,
+    // How often expired keys are deleted from the database.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub cleanup_interval_seconds: u64,
+    // How long a retry waits for a concurrent request with the same key to finish.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub in_progress_timeout_milliseconds: u64,
+}
+
+impl IdempotencySettings {
+    pub fn ttl(&self) -> Duration {
+        Duration::from_secs(self.ttl_seconds)
+    }
+
+    pub fn cleanup_interval(&self) -> Duration {
+        Duration::from_secs(self.cleanup_interval_seconds)
+    }
+
+    pub fn in_progress_timeout(&self) -> Duration {
+        Duration::from_millis(self.in_progress_timeout_milliseconds)
+    }
+}
+
+#[derive(serde::Deserialize, Clone, Debug)]
+pub struct MetricsSettings {
+    // Serve /metrics on this port, to keep it off the public one. None serves it on the
+    // application's port, for everyone to see.
+    #[serde(default, deserialize_with = "deserialize_option_number_from_string")]
+    pub port: Option<u16>,
+}
+
+#[derive(serde::Deserialize, Clone, Debug)]
+pub struct HealthSettings {
+    // Also check the email provider on /health/ready. It's reported, but doesn't fail the probe.
+    pub check_email_provider: bool,
+    // How long a dependency gets to answer before it's considered down.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub timeout_milliseconds: u64,
+}
+
+impl HealthSettings {
+    pub fn timeout(&self) -> Duration {
+        Duration::from_millis(self.timeout_milliseconds)
+    }
+}
+
+#[derive(serde::Deserialize, Clone, Debug)]
+pub struct SubscriptionSettings {
+    // Treat Foo@example.com and foo@example.com as the same subscriber.
+    // Most providers ignore the case of the local part, but the RFC doesn't.
+    #[serde(default)]
+    pub fold_email_local_part_case: bool,
+    // How long confirmation links stay valid. Past that the address can subscribe again.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub confirmation_token_ttl_seconds: u64,
+    pub disposable_domains: DisposableDomainsSettings,
+    pub mx_lookup: MxLookupSettings,
+    pub bot_protection: BotProtectionSettings,
+}
+
+impl SubscriptionSettings {
+    pub fn email_normalization(&self) -> EmailNormalization {
+        EmailNormalization {
+            fold_local_part_case: self.fold_email_local_part_case,
+        }
+    }
+
+    pub fn confirmation_token_ttl(&self) -> Duration {
+        Duration::from_secs(self.confirmation_token_ttl_seconds)
+    }
+}
+
+#[derive(serde::Deserialize, Clone, Debug)]
+pub struct DisposableDomainsSettings {
+    // Domains to reject on top of the bundled list, one per line. Re-read periodically.
+    pub file: Option<PathBuf>,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub reload_interval_milliseconds: u64,
+}
+
+impl DisposableDomainsSettings {
+    pub fn reload_interval(&self) -> Duration {
+        Duration::from_millis(self.reload_interval_milliseconds)
+    }
+}
+
+#[derive(serde::Deserialize, Clone, Debug)]
+pub struct MxLookupSettings {
+    // Reject subscribers whose email domain has no mail server.
+    pub enabled: bool,
+    // e.g. 127.0.0.1:53 for a local stub resolver. The system's resolver is used if unset.
+    pub nameserver: Option<SocketAddr>,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub timeout_milliseconds: u64,
+}
+
+impl MxLookupSettings {
+    pub fn timeout(&self) -> Duration {
+        Duration::from_millis(self.timeout_milliseconds)
+    }
+}
+
+#[derive(serde::Deserialize, Clone, Debug)]
+pub struct BotProtectionSettings {
+    // Forms submitted sooner than this after being served are rejected, people don't type that fast.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub min_form_age_seconds: u64,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_form_age_seconds: u64,
+    // Subscription attempts per client address.
+    pub per_ip: RateLimitSettings,
+    // Confirmation emails per address, so that nobody gets mail-bombed through us.
+    pub per_email: RateLimitSettings,
+}
+
+impl BotProtectionSettings {
+    pub fn min_form_age(&self) -> chrono::Duration {
+        chrono::Duration::seconds(self.min_form_age_seconds as i64)
+    }
+
+    pub fn max_form_age(&self) -> chrono::Duration {
+        chrono::Duration::seconds(self.max_form_age_seconds as i64)
+    }
+}
+
+#[derive(serde::Deserialize, Clone, Debug)]
+pub struct RateLimitSettings {
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_hits: u32,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub window_seconds: u64,
+}
+
+impl RateLimitSettings {
+    pub fn window(&self) -> Duration {
+        Duration::from_secs(self.window_seconds)
+    }
+}
+
+#[derive(serde::Deserialize, Clone, Debug)]
+pub struct SecurityHeadersSettings {
+    // Content-Security-Policy of the public pages.
+    pub content_security_policy: String,
+    // Content-Security-Policy of the pages under /admin.
+    pub admin_content_security_policy: String,
+    // Content-Security-Policy of the newsletter issue preview, which renders the issue's own
+    // HTML. It may need remote images and inline styles, scripts stay blocked.
+    pub preview_content_security_policy: String,
+    pub referrer_policy: String,
+    // Strict-Transport-Security max-age. Only sent in production, local runs are plain HTTP.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub hsts_max_age_seconds: u64,
+}
+
+#[derive(serde::Deserialize, Clone, Debug)]
+pub struct SessionSettings {
+    // Where admin session states are kept.
+    pub store: SessionStoreKind,
+    // Sessions without a request for that long are logged out. Every request pushes it back.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub idle_timeout_seconds: u64,
+    // Sessions are logged out that long after login, active or not.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub absolute_timeout_seconds: u64,
+    // Both timeouts when "remember me" is ticked on login.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub remember_me_timeout_seconds: u64,
+    // Publishing to more confirmed subscribers than this asks for the password again...
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub reauthentication_subscriber_threshold: i64,
+    // ...unless it was entered that recently.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub reauthentication_timeout_seconds: u64,
+}
+
+impl SessionSettings {
+    pub fn idle_timeout(&self, remember_me: bool) -> Duration {
+        match remember_me {
+            true => Duration::from_secs(self.remember_me_timeout_seconds),
+            false => Duration::from_secs(self.idle_timeout_seconds),
+        }
+    }
+
+    pub fn absolute_timeout(&self, remember_me: bool) -> Duration {
+        match remember_me {
+            true => Duration::from_secs(self.remember_me_timeout_seconds),
+            false => Duration::from_secs(self.absolute_timeout_seconds),
+        }
+    }
+
+    /// How long session states and cookies are kept for, the longest a session can live.
+    /// The timeouts themselves are enforced by `reject_anonymous_users`.
+    pub fn state_ttl(&self) -> Duration {
+        Duration::from_secs(
+            self.idle_timeout_seconds
+                .max(self.absolute_timeout_seconds)
+                .max(self.remember_me_timeout_seconds),
+        )
+    }
+
+    pub fn reauthentication_timeout(&self) -> Duration {
+        Duration::from_secs(self.reauthentication_timeout_seconds)
+    }
+}
+
+#[derive(serde::Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum SessionStoreKind {
+    Redis,
+    // The `sessions` table, for deployments that don't want to run redis.
+    Postgres,
+}
+
+#[derive(serde::Deserialize, Clone, Debug)]
+pub struct PasswordHashingSettings {
+    // Argon2id parameters. Stored hashes with other ones are upgraded on the next login.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub memory_kib: u32,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub iterations: u32,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub parallelism: u32,
+}
+
+impl PasswordHashingSettings {
+    pub fn params(&self) -> Result<argon2::Params, argon2::Error> {
+        argon2::Params::new(self.memory_kib, self.iterations, self.parallelism, None)
+    }
+}
+
+#[derive(serde::Deserialize, Clone, Debug)]
+pub struct PasswordPolicySettings {
+    // In characters. The maximum keeps hashing cheap, argon2 hashes the whole password.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub min_length: usize,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_length: usize,
+    // Passwords estimated easier to guess are rejected, see `PasswordPolicy`.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub min_entropy_bits: f64,
+    // How many of the latest passwords, the current one included, can't be reused. 0 allows any.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub history_size: i64,
+}
+
+#[derive(serde::Deserialize, Clone, Debug, Default)]
+pub struct TelemetrySettings {
+    // OTLP/HTTP collector base url, e.g. http://localhost:4318. Spans aren't exported if unset.
+    #[serde(default)]
+    pub otlp_endpoint: Option<String>,
+    #[serde(default)]
+    pub format: LogFormat,
+    // Also write logs to rotated files, on top of stdout.
+    #[serde(default)]
+    pub log_file: Option<LogFileSettings>,
+}
+
+#[derive(serde::Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    // Multi-line, human readable.
+    Pretty,
+    // One line per event, human readable.
+    Compact,
+    // Bunyan JSON, for log aggregation.
+    #[default]
+    Json,
+}
+
+#[derive(serde::Deserialize, Clone, Debug)]
+pub struct LogFileSettings {
+    pub directory: String,
+    // Files are named <prefix>.<date>.
+    pub prefix: String,
+    pub rotation: LogRotation,
+}
+
+#[derive(serde::Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum LogRotation {
+    Minutely,
+    Hourly,
+    Daily,
+    Never,
+}
+
 #[derive(serde::Deserialize, Clone, Debug)]
 pub struct DatabaseSettings {
     pub username: String,
@@ -44,6 +350,8 @@ pub struct DatabaseSettings {
     pub database_name: String,
     // Determine if we use an encrypted connection or not.
     pub require_ssl: bool,
+    // Apply the embedded migrations when the application boots.
+    pub migrate_on_startup: bool,
 }
 
 #[derive(serde::Deserialize, Clone, Debug)]
@@ -53,6 +361,10 @@ pub struct ApplicationSettings {
     pub host: String,
     pub base_url: String,
     pub hmac_secret: Secret<String>,
+    // Proxies in front of the application, whose Forwarded/X-Forwarded-For headers are
+    // believed. Anybody else's are ignored, the client could have written them.
+    #[serde(default)]
+    pub trusted_proxies: Vec<IpAddr>,
 }
 
 impl DatabaseSettings {
@@ -70,6 +382,7 @@ impl DatabaseSettings {
     ///    host: "localhost".into(),
     ///    database_name: "db".into(),
     ///    require_ssl: true,
+    ///    migrate_on_startup: false,
     ///   };
     ///
     ///  
@@ -95,7 +408,7 @@ impl DatabaseSettings {
         PgConnectOptions::new()
             .host(&self.host)
             .username(&self.username)
-            .password(&self.password.expose_secret())
+            .password(self.password.expose_secret())
             .port(self.port)
             .ssl_mode(ssl_mode)
     }
@@ -128,6 +441,7 @@ pub fn get_configuration() -> Result<Settings, config::ConfigError> {
                 .prefix_separator("_")
                 .separator("__"),
         )
+        .set_override("environment", environment.as_str())?
         .build()?;
 
     // Try to convert the configuration values it read into
@@ -135,13 +449,15 @@ pub fn get_configuration() -> Result<Settings, config::ConfigError> {
     settings.try_deserialize::<Settings>()
 }
 
-enum Environment {
+#[derive(serde::Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Environment {
     Local,
     Production,
 }
 
 impl Environment {
-    fn as_str(&self) -> &'static str {
+    pub fn as_str(&self) -> &'static str {
         match self {
             Environment::Local => "local",
             Environment::Production => "production",
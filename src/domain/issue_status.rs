@@ -0,0 +1,88 @@
+use sqlx::postgres::{PgHasArrayType, PgTypeInfo};
+
+/// Where a newsletter issue is in its lifecycle, maps to the `issue_status` postgres enum.
+///
+/// Legal transitions:
+///  - draft -> scheduled, published
+///  - scheduled -> draft, i.e. unscheduling, scheduled, i.e. rescheduling, published
+///  - published is final, it has been sent out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, sqlx::Type)]
+#[sqlx(type_name = "issue_status", rename_all = "snake_case")]
+pub enum IssueStatus {
+    /// Being written, can be edited.
+    Draft,
+    /// Published by the scheduler at `scheduled_for`, can still be edited.
+    Scheduled,
+    /// Queued for delivery to the confirmed subscribers.
+    Published,
+}
+
+impl IssueStatus {
+    pub const ALL: [IssueStatus; 3] = [Self::Draft, Self::Scheduled, Self::Published];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            IssueStatus::Draft => "draft",
+            IssueStatus::Scheduled => "scheduled",
+            IssueStatus::Published => "published",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|status| status.as_str() == s)
+    }
+
+    /// Returns whether an issue in this status may be moved to `next`.
+    pub fn can_transition_to(self, next: IssueStatus) -> bool {
+        use IssueStatus::*;
+        matches!(
+            (self, next),
+            (Draft, Scheduled | Published) | (Scheduled, Draft | Scheduled | Published)
+        )
+    }
+
+    /// Whether the content of an issue in this status can still change.
+    pub fn is_editable(self) -> bool {
+        self != IssueStatus::Published
+    }
+
+    /// Returns the statuses an issue can become `next` from. Scheduling only updates issues
+    /// still in one of them, an issue published in the meantime is left alone.
+    pub fn sources_of(next: IssueStatus) -> Vec<IssueStatus> {
+        Self::ALL
+            .into_iter()
+            .filter(|s| s.can_transition_to(next))
+            .collect()
+    }
+}
+
+impl PgHasArrayType for IssueStatus {
+    fn array_type_info() -> PgTypeInfo {
+        PgTypeInfo::with_name("_issue_status")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::IssueStatus::{self, *};
+
+    #[test]
+    fn statuses_round_trip_through_their_names() {
+        for status in IssueStatus::ALL {
+            assert_eq!(IssueStatus::parse(status.as_str()), Some(status));
+        }
+    }
+
+    #[test]
+    fn published_is_final() {
+        for status in IssueStatus::ALL {
+            assert!(!Published.can_transition_to(status), "{:?}", status);
+        }
+        assert!(!Published.is_editable());
+    }
+
+    #[test]
+    fn drafts_and_scheduled_issues_can_be_published() {
+        assert_eq!(IssueStatus::sources_of(Published), vec![Draft, Scheduled]);
+    }
+}
@@ -1,7 +1,11 @@
+mod issue_status;
 mod new_subscriber;
 mod subscriber_email;
 mod subscriber_name;
+mod subscription_status;
 
+pub use issue_status::IssueStatus;
 pub use new_subscriber::NewSubscriber;
-pub use subscriber_email::SubscriberEmail;
+pub use subscriber_email::{EmailNormalization, SubscriberEmail};
 pub use subscriber_name::SubscriberName;
+pub use subscription_status::SubscriptionStatus;
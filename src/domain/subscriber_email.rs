@@ -1,20 +1,63 @@
-/// Represents a valid subscriber name.
+/// Longest address that fits in the SMTP forward-path (RFC 5321).
+const MAX_EMAIL_LENGTH: usize = 254;
+
+/// Represents a valid, normalized, subscriber email.
 ///
 /// Invariants:
-///  - Name is non empty
-///  - Name is < 256 graphemes
-///  - Name has no forbidden characters: '/()"<>\{}
-#[derive(Debug, Clone)]
+///  - Passes validator crates validate_email
+///  - At most 254 characters
+///  - Domain is lowercase ASCII, internationalized domains are punycode encoded
+///  - Domain has at least two labels, e.g. no `user@localhost`
+#[derive(Debug, Clone, PartialEq)]
 pub struct SubscriberEmail(String);
 
+/// How far to go when normalizing an address, on top of the domain.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct EmailNormalization {
+    /// Lowercase the local part too. The RFC says it's case sensitive, in practice
+    /// providers ignore the case and users don't care about it.
+    pub fold_local_part_case: bool,
+}
+
 impl SubscriberEmail {
-    /// Returns a new SubscriberEmail if the input satisfies all of the preconditions:
-    ///  - passed validator crates validate_email
+    /// Returns a new SubscriberEmail, normalized with the default normalization,
+    /// if the input satisfies all of the invariants.
     pub fn parse(s: String) -> Result<SubscriberEmail, String> {
-        if validator::validate_email(&s) {
-            return Ok(Self(s));
+        Self::parse_with(s, EmailNormalization::default())
+    }
+
+    /// Returns a new SubscriberEmail normalized according to `normalization`,
+    /// if the input satisfies all of the invariants once normalized.
+    pub fn parse_with(
+        s: String,
+        normalization: EmailNormalization,
+    ) -> Result<SubscriberEmail, String> {
+        let invalid = || format!("{} invalid email", s);
+        let (local_part, domain) = s.trim().rsplit_once('@').ok_or_else(invalid)?;
+        // Lowercases and punycode encodes.
+        let domain = idna::domain_to_ascii(domain).map_err(|_| invalid())?;
+        if !domain.contains('.') {
+            return Err(invalid());
+        }
+        let local_part = if normalization.fold_local_part_case {
+            local_part.to_lowercase()
+        } else {
+            local_part.to_owned()
+        };
+
+        let email = format!("{}@{}", local_part, domain);
+        if email.len() > MAX_EMAIL_LENGTH || !validator::validate_email(&email) {
+            return Err(invalid());
         }
-        return Err(format!("{} invalid email", s));
+        Ok(Self(email))
+    }
+
+    /// The part after the `@`.
+    pub fn domain(&self) -> &str {
+        self.0
+            .rsplit_once('@')
+            .map(|(_, domain)| domain)
+            .unwrap_or_default()
     }
 }
 
@@ -32,7 +75,7 @@ impl std::fmt::Display for SubscriberEmail {
 
 #[cfg(test)]
 mod tests {
-    use crate::domain::SubscriberEmail;
+    use crate::domain::{EmailNormalization, SubscriberEmail};
     use claims::{assert_err, assert_ok};
     use fake::faker::internet::en::SafeEmail;
     use fake::Fake;
@@ -67,6 +110,51 @@ mod tests {
         assert_ok!(SubscriberEmail::parse(email));
     }
 
+    #[test]
+    fn domain_is_lowercased() {
+        let email = SubscriberEmail::parse("Ursula@Example.COM".into()).unwrap();
+        assert_eq!(email.as_ref(), "Ursula@example.com");
+    }
+
+    #[test]
+    fn local_part_case_is_kept_by_default_and_folded_on_demand() {
+        let normalization = EmailNormalization {
+            fold_local_part_case: true,
+        };
+        let email = SubscriberEmail::parse_with("Ursula@Example.COM".into(), normalization);
+        assert_eq!(email.unwrap().as_ref(), "ursula@example.com");
+    }
+
+    #[test]
+    fn internationalized_domains_are_punycode_encoded() {
+        let email = SubscriberEmail::parse("ursula@BÜCHER.example".into()).unwrap();
+        assert_eq!(email.as_ref(), "ursula@xn--bcher-kva.example");
+    }
+
+    #[test]
+    fn surrounding_whitespace_is_trimmed() {
+        let email = SubscriberEmail::parse("  ursula@example.com ".into()).unwrap();
+        assert_eq!(email.as_ref(), "ursula@example.com");
+    }
+
+    #[test]
+    fn single_label_domain_is_rejected() {
+        assert_err!(SubscriberEmail::parse("ursula@localhost".into()));
+    }
+
+    #[test]
+    fn email_longer_than_254_characters_is_rejected() {
+        let domain = format!(
+            "{}.{}.{}.com",
+            "a".repeat(63),
+            "b".repeat(63),
+            "c".repeat(63)
+        );
+        let email = format!("{}@{}", "u".repeat(60), domain);
+        assert!(email.len() > 254);
+        assert_err!(SubscriberEmail::parse(email));
+    }
+
     #[derive(Debug, Clone)]
     struct ValidEmailFixture(pub String);
 
@@ -0,0 +1,96 @@
+use sqlx::postgres::{PgHasArrayType, PgTypeInfo};
+
+/// Where a subscriber is in its lifecycle, maps to the `subscription_status`
+/// postgres enum.
+///
+/// Legal transitions (staying in the same status is always legal):
+///  - pending -> confirmed, unsubscribed, bounced, complained
+///  - confirmed -> unsubscribed, bounced, complained
+///  - unsubscribed, bounced -> pending, i.e. subscribing again
+///  - complained is final, we never email them again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, sqlx::Type)]
+#[sqlx(type_name = "subscription_status", rename_all = "snake_case")]
+pub enum SubscriptionStatus {
+    /// Waiting for the confirmation link to be clicked.
+    Pending,
+    /// Receives newsletter issues.
+    Confirmed,
+    Unsubscribed,
+    /// Emails to the address bounced.
+    Bounced,
+    /// Reported one of our emails as spam.
+    Complained,
+}
+
+impl SubscriptionStatus {
+    pub const ALL: [SubscriptionStatus; 5] = [
+        Self::Pending,
+        Self::Confirmed,
+        Self::Unsubscribed,
+        Self::Bounced,
+        Self::Complained,
+    ];
+
+    /// Returns whether a subscriber in this status may be moved to `next`.
+    pub fn can_transition_to(self, next: SubscriptionStatus) -> bool {
+        use SubscriptionStatus::*;
+        self == next
+            || matches!(
+                (self, next),
+                (Pending, Confirmed | Unsubscribed | Bounced | Complained)
+                    | (Confirmed, Unsubscribed | Bounced | Complained)
+                    | (Unsubscribed | Bounced, Pending)
+            )
+    }
+
+    /// Returns the statuses a subscriber can move to `next` from. Confirmations and
+    /// re-subscriptions match on them in SQL, so a subscriber who unsubscribed or
+    /// complained in between keeps that status.
+    pub fn sources_of(next: SubscriptionStatus) -> Vec<SubscriptionStatus> {
+        Self::ALL
+            .into_iter()
+            .filter(|s| s.can_transition_to(next))
+            .collect()
+    }
+}
+
+impl PgHasArrayType for SubscriptionStatus {
+    fn array_type_info() -> PgTypeInfo {
+        PgTypeInfo::with_name("_subscription_status")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SubscriptionStatus::{self, *};
+
+    #[test]
+    fn staying_in_the_same_status_is_legal() {
+        for status in SubscriptionStatus::ALL {
+            assert!(status.can_transition_to(status), "{:?}", status);
+        }
+    }
+
+    #[test]
+    fn only_pending_subscribers_can_be_confirmed() {
+        assert_eq!(
+            SubscriptionStatus::sources_of(Confirmed),
+            vec![Pending, Confirmed]
+        );
+    }
+
+    #[test]
+    fn complained_is_final() {
+        for status in [Pending, Confirmed, Unsubscribed, Bounced] {
+            assert!(!Complained.can_transition_to(status), "{:?}", status);
+        }
+    }
+
+    #[test]
+    fn unsubscribed_and_bounced_subscribers_must_confirm_again() {
+        for status in [Unsubscribed, Bounced] {
+            assert!(status.can_transition_to(Pending));
+            assert!(!status.can_transition_to(Confirmed));
+        }
+    }
+}
@@ -1,4 +1,6 @@
 use crate::domain::SubscriberEmail;
+use crate::metrics::METRICS;
+use crate::request_id::{current_request_id, RequestId, REQUEST_ID_HEADER};
 use reqwest::Client;
 use secrecy::{ExposeSecret, Secret};
 use serde::{Deserialize, Serialize};
@@ -9,6 +11,8 @@ pub struct EmailClient {
     base_url: String,
     sender: SubscriberEmail,
     authorization_token: Secret<String>,
+    // Name of the email provider (the host of base_url), used to label metrics.
+    provider: String,
 }
 
 impl EmailClient {
@@ -17,11 +21,16 @@ impl EmailClient {
         sender: SubscriberEmail,
         authorization_token: Secret<String>,
     ) -> Self {
+        let provider = reqwest::Url::parse(&base_url)
+            .ok()
+            .and_then(|url| url.host_str().map(str::to_owned))
+            .unwrap_or_else(|| base_url.clone());
         Self {
             http_client: Client::new(),
             base_url,
             sender,
             authorization_token,
+            provider,
         }
     }
 }
@@ -37,6 +46,7 @@ impl EmailClient {
     ) -> Result<(), reqwest::Error> {
         // /v3/mail/send is the target for sending sendgrid API calls.
         let url = format!("{}/v3/mail/send", self.base_url);
+        let request_id = current_request_id();
         let request_body = SendEmailRequest {
             personalizations: vec![Personalization {
                 to: vec![To {
@@ -58,14 +68,41 @@ impl EmailClient {
                     value: text_content.to_string(),
                 },
             ],
+            custom_args: request_id.as_ref().map(|request_id| CustomArgs {
+                request_id: request_id.to_string(),
+            }),
         };
-        self.http_client
-            .post(&url)
-            .header(
-                "Authorization",
-                format!("Bearer {}", self.authorization_token.expose_secret()),
-            )
-            .json(&request_body)
+        let outcome = self.post(&url, &request_body, request_id).await;
+        let counter = match outcome {
+            Ok(()) => &METRICS.emails_sent_total,
+            Err(_) => &METRICS.emails_failed_total,
+        };
+        counter.with_label_values(&[&self.provider]).inc();
+        outcome
+    }
+
+    /// Checks that the email provider can be reached.
+    /// Any HTTP response will do, we don't want to spend an API call on it.
+    pub async fn ping(&self) -> Result<(), reqwest::Error> {
+        self.http_client.head(&self.base_url).send().await?;
+        Ok(())
+    }
+
+    async fn post(
+        &self,
+        url: &str,
+        request_body: &SendEmailRequest,
+        request_id: Option<RequestId>,
+    ) -> Result<(), reqwest::Error> {
+        let mut request = self.http_client.post(url).header(
+            "Authorization",
+            format!("Bearer {}", self.authorization_token.expose_secret()),
+        );
+        if let Some(request_id) = request_id {
+            request = request.header(REQUEST_ID_HEADER.as_str(), request_id.as_ref());
+        }
+        request
+            .json(request_body)
             .send()
             .await
             .map(|e| {
@@ -94,6 +131,14 @@ struct SendEmailRequest {
     from: From,
     subject: String,
     content: Vec<Content>,
+    // Echoed back by the provider in its events, to correlate them with our requests.
+    #[serde(rename = "custom_args", skip_serializing_if = "Option::is_none")]
+    custom_args: Option<CustomArgs>,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct CustomArgs {
+    request_id: String,
 }
 
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
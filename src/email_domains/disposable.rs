@@ -0,0 +1,118 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use anyhow::Context;
+
+const BUNDLED_LIST: &str = include_str!("disposable_domains.txt");
+
+/// Disposable email domains, i.e. throwaway inboxes we don't accept subscribers from.
+///
+/// The bundled list can be extended with the domains listed in a file, which is
+/// re-read periodically so that the list can be updated without a restart.
+#[derive(Clone)]
+pub struct DisposableDomains {
+    bundled: Arc<HashSet<String>>,
+    from_file: Arc<RwLock<HashSet<String>>>,
+}
+
+impl DisposableDomains {
+    /// Only the domains shipped with the binary.
+    pub fn bundled() -> Self {
+        Self {
+            bundled: Arc::new(parse_list(BUNDLED_LIST)),
+            from_file: Arc::default(),
+        }
+    }
+
+    /// Whether `domain`, or one of its parent domains, is disposable.
+    pub fn contains(&self, domain: &str) -> bool {
+        let from_file = self.from_file.read().unwrap();
+        let mut candidate = domain;
+        loop {
+            if self.bundled.contains(candidate) || from_file.contains(candidate) {
+                return true;
+            }
+            match candidate.split_once('.') {
+                Some((_, parent)) if parent.contains('.') => candidate = parent,
+                _ => return false,
+            }
+        }
+    }
+
+    /// Replaces the domains read from a file by the ones in `path`.
+    /// Returns whether the list changed.
+    pub fn reload_from(&self, path: &Path) -> Result<bool, anyhow::Error> {
+        let content = std::fs::read_to_string(path).with_context(|| {
+            format!(
+                "Failed to read the disposable domains from {}.",
+                path.display()
+            )
+        })?;
+        let domains = parse_list(&content);
+        let mut from_file = self.from_file.write().unwrap();
+        let changed = *from_file != domains;
+        *from_file = domains;
+        Ok(changed)
+    }
+
+    /// Re-reads `path` every `interval`. A file that can't be read keeps the
+    /// previous list in place.
+    pub async fn reload_periodically(self, path: PathBuf, interval: Duration) {
+        loop {
+            tokio::time::sleep(interval).await;
+            match self.reload_from(&path) {
+                Ok(true) => tracing::info!(
+                    path = %path.display(),
+                    "Reloaded the disposable email domains."
+                ),
+                Ok(false) => {}
+                Err(e) => tracing::warn!(
+                    error.cause_chain = ?e,
+                    error.message = %e,
+                    "Failed to reload the disposable email domains."
+                ),
+            }
+        }
+    }
+}
+
+/// One domain per line, `#` starts a comment.
+fn parse_list(content: &str) -> HashSet<String> {
+    content
+        .lines()
+        .map(|line| line.split('#').next().unwrap_or_default().trim())
+        .filter(|line| !line.is_empty())
+        .map(str::to_lowercase)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_list, DisposableDomains};
+
+    #[test]
+    fn bundled_domains_and_their_subdomains_are_disposable() {
+        let domains = DisposableDomains::bundled();
+        assert!(domains.contains("mailinator.com"));
+        assert!(domains.contains("inbox.mailinator.com"));
+    }
+
+    #[test]
+    fn other_domains_are_not_disposable() {
+        let domains = DisposableDomains::bundled();
+        assert!(!domains.contains("example.com"));
+        assert!(!domains.contains("com"));
+        assert!(!domains.contains("notmailinator.com"));
+    }
+
+    #[test]
+    fn comments_and_blank_lines_are_ignored() {
+        let domains = parse_list("# Comment\n\n Spam.Example # trailing comment\n");
+        assert_eq!(
+            domains.into_iter().collect::<Vec<_>>(),
+            vec!["spam.example"]
+        );
+    }
+}
@@ -0,0 +1,78 @@
+mod disposable;
+mod mx;
+
+pub use disposable::DisposableDomains;
+pub use mx::MxLookup;
+
+use crate::configuration::SubscriptionSettings;
+use crate::domain::SubscriberEmail;
+
+/// Checks that the domain of a subscriber's email is meant to, and can,
+/// receive emails.
+#[derive(Clone)]
+pub struct EmailDomainValidator {
+    disposable_domains: DisposableDomains,
+    mx_lookup: Option<MxLookup>,
+}
+
+impl EmailDomainValidator {
+    pub fn new(disposable_domains: DisposableDomains, mx_lookup: Option<MxLookup>) -> Self {
+        Self {
+            disposable_domains,
+            mx_lookup,
+        }
+    }
+
+    /// Loads the disposable domains and sets up the MX lookup, if enabled.
+    ///
+    /// If the disposable domains are extended with a file, a task re-reading it is
+    /// spawned on the current tokio runtime.
+    pub fn from_settings(settings: &SubscriptionSettings) -> Result<Self, anyhow::Error> {
+        let disposable_domains = DisposableDomains::bundled();
+        if let Some(path) = &settings.disposable_domains.file {
+            disposable_domains.reload_from(path)?;
+            tokio::spawn(
+                disposable_domains.clone().reload_periodically(
+                    path.clone(),
+                    settings.disposable_domains.reload_interval(),
+                ),
+            );
+        }
+        let mx_lookup = if settings.mx_lookup.enabled {
+            Some(MxLookup::new(&settings.mx_lookup)?)
+        } else {
+            None
+        };
+        Ok(Self::new(disposable_domains, mx_lookup))
+    }
+
+    /// Returns a message for the subscriber if their email's domain is rejected.
+    ///
+    /// Addresses are let through if the MX lookup fails, a flaky nameserver
+    /// shouldn't stop people from subscribing.
+    #[tracing::instrument(name = "Validate the email domain", skip_all, fields(domain = %email.domain()))]
+    pub async fn validate(&self, email: &SubscriberEmail) -> Result<(), String> {
+        let domain = email.domain();
+        if self.disposable_domains.contains(domain) {
+            return Err(format!(
+                "{} is a disposable email domain, please use a permanent address.",
+                domain
+            ));
+        }
+        let Some(mx_lookup) = &self.mx_lookup else {
+            return Ok(());
+        };
+        match mx_lookup.accepts_emails(domain).await {
+            Ok(true) => Ok(()),
+            Ok(false) => Err(format!("{} can't receive emails.", domain)),
+            Err(e) => {
+                tracing::warn!(
+                    error.cause_chain = ?e,
+                    error.message = %e,
+                    "Failed to look up the MX records, accepting the email."
+                );
+                Ok(())
+            }
+        }
+    }
+}
@@ -0,0 +1,72 @@
+use hickory_resolver::config::{NameServerConfigGroup, ResolverConfig, ResolverOpts};
+use hickory_resolver::error::{ResolveError, ResolveErrorKind};
+use hickory_resolver::proto::op::ResponseCode;
+use hickory_resolver::TokioAsyncResolver;
+
+use crate::configuration::MxLookupSettings;
+
+/// Looks up whether a domain accepts emails, according to its DNS records.
+#[derive(Clone)]
+pub struct MxLookup {
+    resolver: TokioAsyncResolver,
+}
+
+impl MxLookup {
+    /// Queries the configured nameserver, or the system's if there isn't one.
+    pub fn new(settings: &MxLookupSettings) -> Result<Self, anyhow::Error> {
+        let (config, mut options) = match settings.nameserver {
+            Some(nameserver) => (
+                ResolverConfig::from_parts(
+                    None,
+                    vec![],
+                    NameServerConfigGroup::from_ips_clear(
+                        &[nameserver.ip()],
+                        nameserver.port(),
+                        true,
+                    ),
+                ),
+                ResolverOpts::default(),
+            ),
+            None => hickory_resolver::system_conf::read_system_conf()?,
+        };
+        options.timeout = settings.timeout();
+        options.attempts = 1;
+        Ok(Self {
+            resolver: TokioAsyncResolver::tokio(config, options),
+        })
+    }
+
+    /// Whether `domain` can receive emails: it has an MX record that isn't a
+    /// "null MX" (RFC 7505), or no MX records but an address (implicit MX, RFC 5321).
+    ///
+    /// Errors if the nameserver couldn't tell, e.g. it timed out.
+    pub async fn accepts_emails(&self, domain: &str) -> Result<bool, ResolveError> {
+        // Fully qualified, so that the search domains aren't tried.
+        let domain = format!("{}.", domain.trim_end_matches('.'));
+        match self.resolver.mx_lookup(domain.as_str()).await {
+            Ok(mx) => return Ok(mx.iter().any(|mx| !mx.exchange().is_root())),
+            Err(e) if !is_no_records(&e) => return Err(e),
+            Err(e) if is_nxdomain(&e) => return Ok(false),
+            Err(_) => {}
+        }
+        match self.resolver.lookup_ip(domain.as_str()).await {
+            Ok(_) => Ok(true),
+            Err(e) if is_no_records(&e) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+fn is_no_records(e: &ResolveError) -> bool {
+    matches!(e.kind(), ResolveErrorKind::NoRecordsFound { .. })
+}
+
+fn is_nxdomain(e: &ResolveError) -> bool {
+    matches!(
+        e.kind(),
+        ResolveErrorKind::NoRecordsFound {
+            response_code: ResponseCode::NXDomain,
+            ..
+        }
+    )
+}
@@ -0,0 +1,143 @@
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use secrecy::ExposeSecret;
+use sha2::Sha256;
+
+use crate::configuration::BotProtectionSettings;
+use crate::startup::HmacSecret;
+
+/// Token embedded in the public forms, recording when the form was served.
+///
+/// `<unix timestamp>.<hex HMAC-SHA256 of the timestamp>`: bots posting without
+/// loading the form, or straight after loading it, don't get through.
+#[derive(Debug, Clone, Copy)]
+pub struct FormToken {
+    issued_at: DateTime<Utc>,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum FormTokenError {
+    #[error("The form is invalid, please reload the page and try again.")]
+    Invalid,
+    #[error("The form was submitted too quickly, please try again.")]
+    TooFresh,
+    #[error("The form has expired, please reload the page and try again.")]
+    Expired,
+}
+
+impl FormToken {
+    pub fn new(issued_at: DateTime<Utc>) -> Self {
+        Self { issued_at }
+    }
+
+    pub fn sign(&self, secret: &HmacSecret) -> String {
+        let timestamp = self.issued_at.timestamp().to_string();
+        let signature = hex::encode(mac(secret, &timestamp).finalize().into_bytes());
+        format!("{}.{}", timestamp, signature)
+    }
+
+    /// Checks the signature and that the form was served within the allowed window.
+    pub fn verify(
+        token: &str,
+        secret: &HmacSecret,
+        settings: &BotProtectionSettings,
+        now: DateTime<Utc>,
+    ) -> Result<Self, FormTokenError> {
+        let (timestamp, signature) = token.split_once('.').ok_or(FormTokenError::Invalid)?;
+        let signature = hex::decode(signature).map_err(|_| FormTokenError::Invalid)?;
+        mac(secret, timestamp)
+            .verify_slice(&signature)
+            .map_err(|_| FormTokenError::Invalid)?;
+        let issued_at = timestamp
+            .parse()
+            .ok()
+            .and_then(|timestamp| DateTime::from_timestamp(timestamp, 0))
+            .ok_or(FormTokenError::Invalid)?;
+
+        let age = now.signed_duration_since(issued_at);
+        if age < settings.min_form_age() {
+            return Err(FormTokenError::TooFresh);
+        }
+        if age > settings.max_form_age() {
+            return Err(FormTokenError::Expired);
+        }
+        Ok(Self { issued_at })
+    }
+}
+
+fn mac(secret: &HmacSecret, timestamp: &str) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.0.expose_secret().as_bytes())
+        .expect("HMAC can take keys of any size.");
+    mac.update(b"form_token:");
+    mac.update(timestamp.as_bytes());
+    mac
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Duration, Utc};
+    use claims::{assert_matches, assert_ok};
+    use secrecy::Secret;
+
+    use super::{FormToken, FormTokenError};
+    use crate::configuration::{BotProtectionSettings, RateLimitSettings};
+    use crate::startup::HmacSecret;
+
+    fn secret() -> HmacSecret {
+        HmacSecret(Secret::new("secret".into()))
+    }
+
+    fn settings() -> BotProtectionSettings {
+        let rate_limit = RateLimitSettings {
+            max_hits: 1,
+            window_seconds: 1,
+        };
+        BotProtectionSettings {
+            min_form_age_seconds: 3,
+            max_form_age_seconds: 3600,
+            per_ip: rate_limit.clone(),
+            per_email: rate_limit,
+        }
+    }
+
+    #[test]
+    fn a_token_is_valid_within_its_window() {
+        let now = Utc::now();
+        let token = FormToken::new(now - Duration::seconds(10)).sign(&secret());
+        assert_ok!(FormToken::verify(&token, &secret(), &settings(), now));
+    }
+
+    #[test]
+    fn tokens_submitted_too_quickly_or_too_late_are_rejected() {
+        let now = Utc::now();
+        let fresh = FormToken::new(now - Duration::seconds(1)).sign(&secret());
+        let expired = FormToken::new(now - Duration::hours(2)).sign(&secret());
+        assert_matches!(
+            FormToken::verify(&fresh, &secret(), &settings(), now),
+            Err(FormTokenError::TooFresh)
+        );
+        assert_matches!(
+            FormToken::verify(&expired, &secret(), &settings(), now),
+            Err(FormTokenError::Expired)
+        );
+    }
+
+    #[test]
+    fn tampered_tokens_are_rejected() {
+        let now = Utc::now();
+        let token = FormToken::new(now - Duration::seconds(10)).sign(&secret());
+        let (_, signature) = token.split_once('.').unwrap();
+        let tampered = format!("{}.{}", now.timestamp(), signature);
+        let other_secret = HmacSecret(Secret::new("other".into()));
+        for token in [tampered.as_str(), "", "123", "123.zz"] {
+            assert_matches!(
+                FormToken::verify(token, &secret(), &settings(), now),
+                Err(FormTokenError::Invalid)
+            );
+        }
+        assert_matches!(
+            FormToken::verify(&token, &other_secret, &settings(), now),
+            Err(FormTokenError::Invalid)
+        );
+    }
+}
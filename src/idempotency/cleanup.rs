@@ -0,0 +1,34 @@
+use std::time::Duration;
+
+use crate::{configuration::Settings, startup::get_connection_pool, utils::run_periodically};
+use sqlx::PgPool;
+
+/// Deletes all idempotency keys created more than `ttl` ago.
+/// Returns the number of deleted keys.
+#[tracing::instrument(skip(pool), fields(n_deleted_keys = tracing::field::Empty), err)]
+pub async fn delete_expired_keys(pool: &PgPool, ttl: Duration) -> Result<u64, anyhow::Error> {
+    let n_deleted_keys = sqlx::query!(
+        r#"
+            DELETE FROM idempotency
+            WHERE created_at <= now() - $1::interval
+        "#,
+        ttl as Duration,
+    )
+    .execute(pool)
+    .await?
+    .rows_affected();
+    tracing::Span::current().record("n_deleted_keys", n_deleted_keys);
+    Ok(n_deleted_keys)
+}
+
+/// Runs a loop that periodically deletes expired keys from the idempotency table.
+pub async fn run_cleanup_worker_until_stopped(
+    configuration: Settings,
+) -> Result<(), anyhow::Error> {
+    let connection_pool = get_connection_pool(&configuration.database);
+    let ttl = configuration.idempotency.ttl();
+    run_periodically(configuration.idempotency.cleanup_interval(), || {
+        delete_expired_keys(&connection_pool, ttl)
+    })
+    .await
+}
@@ -0,0 +1,54 @@
+use sha2::{Digest, Sha256};
+
+/// Hash of the payload of a request sent with an idempotency key.
+/// Used to detect a key being reused for a different request.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RequestFingerprint(String);
+
+impl RequestFingerprint {
+    /// Hashes the given payload fields, in order.
+    pub fn new(fields: &[&[u8]]) -> Self {
+        let mut hasher = Sha256::new();
+        for field in fields {
+            // Length-prefix each field so that ["ab", "c"] and ["a", "bc"] differ.
+            hasher.update((field.len() as u64).to_be_bytes());
+            hasher.update(field);
+        }
+        Self(hex::encode(hasher.finalize()))
+    }
+}
+
+impl AsRef<str> for RequestFingerprint {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RequestFingerprint;
+
+    #[test]
+    fn same_fields_have_the_same_fingerprint() {
+        assert_eq!(
+            RequestFingerprint::new(&[b"title", b"content"]),
+            RequestFingerprint::new(&[b"title", b"content"])
+        );
+    }
+
+    #[test]
+    fn different_fields_have_different_fingerprints() {
+        assert_ne!(
+            RequestFingerprint::new(&[b"title", b"content"]),
+            RequestFingerprint::new(&[b"title", b"other content"])
+        );
+    }
+
+    #[test]
+    fn field_boundaries_are_part_of_the_fingerprint() {
+        assert_ne!(
+            RequestFingerprint::new(&[b"ab", b"c"]),
+            RequestFingerprint::new(&[b"a", b"bc"])
+        );
+    }
+}
@@ -21,12 +21,12 @@ impl TryFrom<String> for IdempotencyKey {
 
 impl From<IdempotencyKey> for String {
     fn from(k: IdempotencyKey) -> Self {
-        return k.0;
+        k.0
     }
 }
 
 impl AsRef<str> for IdempotencyKey {
     fn as_ref(&self) -> &str {
-        return &self.0;
+        &self.0
     }
 }
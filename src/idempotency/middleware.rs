@@ -0,0 +1,245 @@
+use std::future::{ready, Future, Ready};
+use std::pin::Pin;
+use std::rc::Rc;
+
+use actix_web::body::{BoxBody, MessageBody};
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::header::{self, HeaderName};
+use actix_web::http::{Method, StatusCode};
+use actix_web::{web, HttpMessage};
+use sqlx::PgPool;
+
+use super::transaction::IdempotencyTransaction;
+use super::{
+    save_response, try_processing, IdempotencyError, IdempotencyKey, IdempotencyScope, NextAction,
+    RequestFingerprint,
+};
+use crate::authentication::UserId;
+use crate::client_ip::client_ip;
+use crate::configuration::IdempotencySettings;
+use crate::utils::{bytes_to_payload, e500};
+
+const IDEMPOTENCY_KEY_HEADER: HeaderName = HeaderName::from_static("idempotency-key");
+const IDEMPOTENCY_KEY_FIELD: &str = "idempotency_key";
+
+/// Middleware that makes the routes it wraps idempotent.
+///
+/// The key is read from the `Idempotency-Key` header, or from the `idempotency_key`
+/// field of a url-encoded form. Keys are scoped to the logged in user (`UserId`) if
+/// there is one, to the client's address and user agent otherwise.
+///
+/// The first request with a key runs the handler and its response is saved.
+/// Retries with the same key and payload get the saved response back without hitting
+/// the handler. Server errors, 401s and 403s aren't saved, so that the request can be
+/// retried.
+///
+/// The key is held in a transaction that handlers can write into, see
+/// `begin_transaction`: their writes are then committed along with the saved response.
+#[derive(Clone, Default)]
+pub struct Idempotency {
+    key_required: bool,
+    error_handler: Option<fn(IdempotencyError) -> actix_web::Error>,
+}
+
+impl Idempotency {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Rejects requests without a key with a 400, instead of passing them through.
+    pub fn require_key(mut self) -> Self {
+        self.key_required = true;
+        self
+    }
+
+    /// Renders the middleware's errors (missing key, key in use...) with `error_handler`
+    /// instead of as plain text.
+    pub fn error_handler(
+        mut self,
+        error_handler: fn(IdempotencyError) -> actix_web::Error,
+    ) -> Self {
+        self.error_handler = Some(error_handler);
+        self
+    }
+
+    fn reject(&self, req: ServiceRequest, e: IdempotencyError) -> ServiceResponse<BoxBody> {
+        match self.error_handler {
+            Some(error_handler) => req.error_response(error_handler(e)),
+            None => req.error_response(e),
+        }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for Idempotency
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<BoxBody>;
+    type Error = actix_web::Error;
+    type Transform = IdempotencyMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(IdempotencyMiddleware {
+            service: Rc::new(service),
+            config: self.clone(),
+        }))
+    }
+}
+
+pub struct IdempotencyMiddleware<S> {
+    service: Rc<S>,
+    config: Idempotency,
+}
+
+impl<S, B> Service<ServiceRequest> for IdempotencyMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<BoxBody>;
+    type Error = actix_web::Error;
+    #[allow(clippy::type_complexity)]
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+        let config = self.config.clone();
+        Box::pin(async move { idempotent(req, service, config).await })
+    }
+}
+
+#[tracing::instrument(
+    name = "Idempotent request",
+    skip_all,
+    fields(idempotency_key = tracing::field::Empty)
+)]
+async fn idempotent<S, B>(
+    mut req: ServiceRequest,
+    service: Rc<S>,
+    config: Idempotency,
+) -> Result<ServiceResponse<BoxBody>, actix_web::Error>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+    B: MessageBody + 'static,
+{
+    // Reads are idempotent already.
+    if matches!(*req.method(), Method::GET | Method::HEAD | Method::OPTIONS) {
+        return Ok(service.call(req).await?.map_into_boxed_body());
+    }
+
+    // Buffer the body: we need it to find the key and fingerprint the request,
+    // then put it back for the handler.
+    let body = req.extract::<web::Bytes>().await?;
+    req.set_payload(bytes_to_payload(body.clone()));
+
+    let idempotency_key = match find_idempotency_key(&req, &body) {
+        Some(key) => key,
+        None if config.key_required => {
+            let e = IdempotencyError::InvalidKey(anyhow::anyhow!("Missing idempotency key."));
+            return Ok(config.reject(req, e));
+        }
+        None => return Ok(service.call(req).await?.map_into_boxed_body()),
+    };
+    let idempotency_key: IdempotencyKey = match idempotency_key.try_into() {
+        Ok(key) => key,
+        Err(e) => return Ok(config.reject(req, IdempotencyError::InvalidKey(e))),
+    };
+    tracing::Span::current().record(
+        "idempotency_key",
+        tracing::field::display(idempotency_key.as_ref()),
+    );
+
+    let scope = scope(&req);
+    let request_fingerprint = RequestFingerprint::new(&[
+        req.method().as_str().as_bytes(),
+        req.uri().to_string().as_bytes(),
+        &body,
+    ]);
+    let (pool, settings) = match (
+        req.app_data::<web::Data<PgPool>>(),
+        req.app_data::<web::Data<IdempotencySettings>>(),
+    ) {
+        (Some(pool), Some(settings)) => (pool.clone(), settings.clone()),
+        _ => {
+            let e = anyhow::anyhow!("The idempotency middleware is missing its app data.");
+            return Err(e500(e));
+        }
+    };
+
+    let transaction = match try_processing(
+        &pool,
+        &idempotency_key,
+        &scope,
+        &request_fingerprint,
+        &settings,
+    )
+    .await
+    {
+        Ok(NextAction::StartProcessing(transaction)) => transaction,
+        Ok(NextAction::ReturnSavedResponse(saved_response)) => {
+            return Ok(req.into_response(saved_response));
+        }
+        Err(e) => return Ok(config.reject(req, e)),
+    };
+
+    let transaction = IdempotencyTransaction::new(transaction);
+    req.extensions_mut().insert(transaction.clone());
+    let response = service.call(req).await?;
+    // Dropping the transaction releases the key for a retry, along with the handler's
+    // writes. The handler may have dropped it already, e.g. bailing out on an error.
+    let Some(transaction) = transaction.take() else {
+        return Ok(response.map_into_boxed_body());
+    };
+    // Neither are server errors and authorization failures: a retry runs again once the
+    // server recovers, or with a token that has the missing scope.
+    let status = response.status();
+    if status.is_server_error()
+        || matches!(status, StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN)
+    {
+        return Ok(response.map_into_boxed_body());
+    }
+    let (req, response) = response.into_parts();
+    let response = save_response(
+        transaction,
+        &idempotency_key,
+        &scope,
+        response.map_into_boxed_body(),
+    )
+    .await
+    .map_err(e500)?;
+    Ok(ServiceResponse::new(req, response))
+}
+
+/// Idempotency key from the header, or the form field as a fallback.
+fn find_idempotency_key(req: &ServiceRequest, body: &[u8]) -> Option<String> {
+    if let Some(value) = req.headers().get(IDEMPOTENCY_KEY_HEADER) {
+        return value.to_str().ok().map(str::to_owned);
+    }
+    if req.content_type() != "application/x-www-form-urlencoded" {
+        return None;
+    }
+    serde_urlencoded::from_bytes::<Vec<(String, String)>>(body)
+        .ok()?
+        .into_iter()
+        .find_map(|(name, value)| (name == IDEMPOTENCY_KEY_FIELD).then_some(value))
+}
+
+fn scope(req: &ServiceRequest) -> IdempotencyScope {
+    if let Some(user_id) = req.extensions().get::<UserId>() {
+        return IdempotencyScope::user(**user_id);
+    }
+    let address = client_ip(req.request())
+        .map(|ip| ip.to_string())
+        .unwrap_or_default();
+    let user_agent = req
+        .headers()
+        .get(header::USER_AGENT)
+        .and_then(|h| h.to_str().ok())
+        .unwrap_or_default();
+    IdempotencyScope::client(&address, user_agent)
+}
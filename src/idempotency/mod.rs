@@ -1,5 +1,15 @@
+mod cleanup;
+mod fingerprint;
 mod key;
+mod middleware;
 mod persistence;
+mod scope;
+mod transaction;
+pub use cleanup::{delete_expired_keys, run_cleanup_worker_until_stopped};
+pub use fingerprint::RequestFingerprint;
 pub use key::IdempotencyKey;
+pub use middleware::Idempotency;
 pub use persistence::save_response;
-pub use persistence::{try_processing, NextAction};
+pub use persistence::{try_processing, IdempotencyError, NextAction};
+pub use scope::IdempotencyScope;
+pub use transaction::{begin_transaction, commit_transaction};
@@ -1,43 +1,64 @@
-use super::IdempotencyKey;
-use actix_web::{body::to_bytes, http::StatusCode, HttpResponse};
+use super::{IdempotencyKey, IdempotencyScope, RequestFingerprint};
+use crate::configuration::IdempotencySettings;
+use crate::routes::error_chain_fmt;
+use actix_web::http::header::{HeaderName, RETRY_AFTER};
+use actix_web::{body::to_bytes, http::StatusCode, HttpResponse, ResponseError};
+use anyhow::Context;
 use sqlx::Executor;
 use sqlx::{postgres::PgHasArrayType, PgPool, Postgres, Transaction};
-use uuid::Uuid;
+use std::time::Duration;
 
-/// Returns the cached response for <user_id, idempotency_key> if it exsists.
+/// A row of the idempotency table, as seen by a request retrying a key.
+struct SavedEntry {
+    request_fingerprint: Option<String>,
+    response_status_code: Option<i16>,
+    response_headers: Option<Vec<HeaderPairRecord>>,
+    response_body: Option<Vec<u8>>,
+}
+
+/// Returns the entry for <scope, idempotency_key> if it exsists.
 /// databases errors are propagated.
-async fn get_saved_response(
+async fn get_saved_entry(
     pool: &PgPool,
     idempotency_key: &IdempotencyKey,
-    user_id: Uuid,
-) -> Result<Option<HttpResponse>, anyhow::Error> {
-    let saved_response = sqlx::query!(
+    scope: &IdempotencyScope,
+) -> Result<Option<SavedEntry>, anyhow::Error> {
+    let saved_entry = sqlx::query_as!(
+        SavedEntry,
         r#"
-        SELECT 
-            response_status_code as "response_status_code!",
-            response_headers as "response_headers!: Vec<HeaderPairRecord>",
-            response_body as "response_body!"
+        SELECT
+            request_fingerprint,
+            response_status_code,
+            response_headers as "response_headers: Vec<HeaderPairRecord>",
+            response_body
         FROM idempotency
         WHERE
-            user_id = $1 AND
+            scope = $1 AND
             idempotency_key = $2
         "#,
-        user_id,
+        scope.as_ref(),
         idempotency_key.as_ref()
     )
     .fetch_optional(pool)
     .await?;
+    Ok(saved_entry)
+}
 
-    if let Some(r) = saved_response {
-        let status_code = StatusCode::from_u16(r.response_status_code.try_into()?)?;
-        let mut response = HttpResponse::build(status_code);
-        for HeaderPairRecord { name, value } in r.response_headers {
-            response.append_header((name, value));
-        }
-        Ok(Some(response.body(r.response_body)))
-    } else {
-        Ok(None)
+/// Rebuilds the saved response, None if the entry doesn't have one (yet).
+fn saved_response(entry: SavedEntry) -> Result<Option<HttpResponse>, anyhow::Error> {
+    let (Some(status_code), Some(headers), Some(body)) = (
+        entry.response_status_code,
+        entry.response_headers,
+        entry.response_body,
+    ) else {
+        return Ok(None);
+    };
+    let status_code = StatusCode::from_u16(status_code.try_into()?)?;
+    let mut response = HttpResponse::build(status_code);
+    for HeaderPairRecord { name, value } in headers {
+        response.append_header((name, value));
     }
+    Ok(Some(response.body(body)))
 }
 
 // Support for the header_pair_ pg type.
@@ -54,11 +75,11 @@ impl PgHasArrayType for HeaderPairRecord {
     }
 }
 
-/// Saves a reply protection response for <user_id, idempotency_key> in DB.
+/// Saves a reply protection response for <scope, idempotency_key> in DB.
 pub async fn save_response(
     mut transaction: Transaction<'static, Postgres>,
     idempotency_key: &IdempotencyKey,
-    user_id: Uuid,
+    scope: &IdempotencyScope,
     http_response: HttpResponse,
 ) -> Result<HttpResponse, anyhow::Error> {
     let (response_head, body) = http_response.into_parts();
@@ -87,10 +108,10 @@ pub async fn save_response(
                 response_headers = $4,
                 response_body = $5
             WHERE
-                user_id = $1 AND
+                scope = $1 AND
                 idempotency_key = $2
         "#,
-                user_id,
+                scope.as_ref(),
                 idempotency_key.as_ref(),
                 status_code,
                 headers,
@@ -105,6 +126,7 @@ pub async fn save_response(
 }
 
 /// try_processing return - tells the caller what to do next for replay protection.
+#[allow(clippy::large_enum_variant)]
 pub enum NextAction {
     // There wasn't a hit in the replay table.
     // Return a transaction for later usage.
@@ -113,35 +135,147 @@ pub enum NextAction {
     ReturnSavedResponse(HttpResponse),
 }
 
+#[derive(thiserror::Error)]
+pub enum IdempotencyError {
+    #[error("{0}")]
+    InvalidKey(anyhow::Error),
+    #[error("A request with this idempotency key is still being processed.")]
+    InProgress { retry_after: Duration },
+    #[error("This idempotency key was already used for a different request.")]
+    PayloadMismatch,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for IdempotencyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl IdempotencyError {
+    /// The `Retry-After` header of in progress errors, in whole seconds rounded up.
+    pub fn retry_after(&self) -> Option<(HeaderName, String)> {
+        match self {
+            IdempotencyError::InProgress { retry_after } => Some((
+                RETRY_AFTER,
+                retry_after.as_millis().div_ceil(1000).max(1).to_string(),
+            )),
+            _ => None,
+        }
+    }
+}
+
+impl ResponseError for IdempotencyError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            IdempotencyError::InvalidKey(_) => StatusCode::BAD_REQUEST,
+            IdempotencyError::InProgress { .. } => StatusCode::CONFLICT,
+            IdempotencyError::PayloadMismatch => StatusCode::UNPROCESSABLE_ENTITY,
+            IdempotencyError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let mut response = HttpResponse::build(self.status_code());
+        if let Some(retry_after) = self.retry_after() {
+            response.insert_header(retry_after);
+        }
+        match self {
+            IdempotencyError::UnexpectedError(_) => response.finish(),
+            _ => response.body(self.to_string()),
+        }
+    }
+}
+
+/// Claims <scope, idempotency_key> for processing.
+///
+///  - Keys that were created more than `ttl` ago are expired and treated as fresh.
+///  - If another request holds the key, waits up to `in_progress_timeout` for it to
+///    finish before giving up with `IdempotencyError::InProgress`.
+///  - If the key was used for a request with a different fingerprint, fails with
+///    `IdempotencyError::PayloadMismatch`.
 pub async fn try_processing(
     pool: &PgPool,
     idempotency_key: &IdempotencyKey,
-    user_id: Uuid,
-) -> Result<NextAction, anyhow::Error> {
-    let mut transaction = pool.begin().await?;
+    scope: &IdempotencyScope,
+    request_fingerprint: &RequestFingerprint,
+    settings: &IdempotencySettings,
+) -> Result<NextAction, IdempotencyError> {
+    let in_progress = || IdempotencyError::InProgress {
+        retry_after: settings.in_progress_timeout(),
+    };
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to start a transaction.")?;
+    // Bound how long we block on a concurrent request holding the same key.
+    sqlx::query("SELECT set_config('lock_timeout', $1, true)")
+        .bind(format!("{}ms", settings.in_progress_timeout().as_millis()))
+        .execute(&mut *transaction)
+        .await
+        .context("Failed to set the lock timeout.")?;
     let query = sqlx::query!(
         r#"
             INSERT INTO idempotency (
-                user_id,
+                scope,
                 idempotency_key,
+                request_fingerprint,
                 created_at
             )
-            VALUES($1, $2, now())
-            ON CONFLICT DO NOTHING
+            VALUES($1, $2, $3, now())
+            ON CONFLICT (scope, idempotency_key) DO UPDATE
+            SET
+                request_fingerprint = EXCLUDED.request_fingerprint,
+                created_at = now(),
+                response_status_code = NULL,
+                response_headers = NULL,
+                response_body = NULL
+            WHERE idempotency.created_at <= now() - $4::interval
         "#,
-        user_id,
-        idempotency_key.as_ref()
+        scope.as_ref(),
+        idempotency_key.as_ref(),
+        request_fingerprint.as_ref(),
+        settings.ttl() as Duration,
     );
-    // Try to insert the <user, key> tuple, if it one exists this will be 0.
-    let n_inserted_rows = transaction.execute(query).await?.rows_affected();
+    // Try to insert the <scope, key> tuple (or take over an expired one),
+    // if a live one exists this will be 0.
+    let n_inserted_rows = match transaction.execute(query).await {
+        Ok(r) => r.rows_affected(),
+        Err(sqlx::Error::Database(e)) if e.code().as_deref() == Some(LOCK_NOT_AVAILABLE) => {
+            return Err(in_progress());
+        }
+        Err(e) => {
+            return Err(anyhow::anyhow!(e)
+                .context("Failed to claim the key.")
+                .into())
+        }
+    };
 
     if n_inserted_rows > 0 {
-        Ok(NextAction::StartProcessing(transaction))
-    } else {
-        // One exists, return the saved response..
-        let saved_response = get_saved_response(pool, idempotency_key, user_id)
-            .await?
-            .ok_or_else(|| anyhow::anyhow!("We expected a saved response, we didn't find it"))?;
-        Ok(NextAction::ReturnSavedResponse(saved_response))
+        // The rest of the transaction belongs to the caller, restore the default.
+        sqlx::query("SET LOCAL lock_timeout = DEFAULT")
+            .execute(&mut *transaction)
+            .await
+            .context("Failed to reset the lock timeout.")?;
+        return Ok(NextAction::StartProcessing(transaction));
     }
+
+    // One exists, return the saved response..
+    let saved_entry = get_saved_entry(pool, idempotency_key, scope)
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("We expected a saved entry, we didn't find it"))?;
+    match &saved_entry.request_fingerprint {
+        // Entries saved before fingerprinting was introduced match anything.
+        Some(f) if f != request_fingerprint.as_ref() => {
+            return Err(IdempotencyError::PayloadMismatch)
+        }
+        _ => {}
+    }
+    // No response yet: the request that claimed the key is still going.
+    let saved_response = saved_response(saved_entry)?.ok_or_else(in_progress)?;
+    Ok(NextAction::ReturnSavedResponse(saved_response))
 }
+
+// SQLSTATE for a lock wait that exceeded `lock_timeout`.
+const LOCK_NOT_AVAILABLE: &str = "55P03";
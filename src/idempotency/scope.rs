@@ -0,0 +1,29 @@
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+/// Namespace an idempotency key lives in.
+/// Two callers can use the same key without stepping on each other.
+#[derive(Debug, Clone)]
+pub struct IdempotencyScope(String);
+
+impl IdempotencyScope {
+    /// Scope of a logged in user.
+    pub fn user(user_id: Uuid) -> Self {
+        Self(format!("user:{}", user_id))
+    }
+
+    /// Scope of an anonymous client, identified by its address and user agent.
+    pub fn client(address: &str, user_agent: &str) -> Self {
+        let mut hasher = Sha256::new();
+        hasher.update(address.as_bytes());
+        hasher.update(b"\n");
+        hasher.update(user_agent.as_bytes());
+        Self(format!("client:{}", hex::encode(hasher.finalize())))
+    }
+}
+
+impl AsRef<str> for IdempotencyScope {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
@@ -0,0 +1,60 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use actix_web::{HttpMessage, HttpRequest};
+use sqlx::{PgPool, Postgres, Transaction};
+
+/// The transaction holding the request's idempotency key, shared with the handler
+/// through the request extensions.
+///
+/// Handlers write into it with `begin_transaction`/`commit_transaction`: their changes
+/// are committed along with the saved response, so a retry either gets that response
+/// back or runs the handler again from scratch, never both.
+#[derive(Clone)]
+pub(super) struct IdempotencyTransaction(Rc<RefCell<Option<Transaction<'static, Postgres>>>>);
+
+impl IdempotencyTransaction {
+    pub(super) fn new(transaction: Transaction<'static, Postgres>) -> Self {
+        Self(Rc::new(RefCell::new(Some(transaction))))
+    }
+
+    /// Takes the transaction back, None if the handler took it and didn't hand it back.
+    pub(super) fn take(&self) -> Option<Transaction<'static, Postgres>> {
+        self.0.borrow_mut().take()
+    }
+}
+
+/// Starts the transaction for a handler's writes: the one holding the idempotency key
+/// if the route is idempotent and the request has a key, a new one otherwise.
+pub async fn begin_transaction(
+    request: &HttpRequest,
+    pool: &PgPool,
+) -> Result<Transaction<'static, Postgres>, sqlx::Error> {
+    let idempotency_transaction = request
+        .extensions()
+        .get::<IdempotencyTransaction>()
+        .and_then(IdempotencyTransaction::take);
+    match idempotency_transaction {
+        Some(transaction) => Ok(transaction),
+        None => pool.begin().await,
+    }
+}
+
+/// Commits a transaction from `begin_transaction`. The idempotency transaction is handed
+/// back to the middleware instead, which commits it once the response is saved.
+///
+/// Dropping the transaction rather than committing it rolls back the handler's writes
+/// and releases the idempotency key.
+pub async fn commit_transaction(
+    request: &HttpRequest,
+    transaction: Transaction<'static, Postgres>,
+) -> Result<(), sqlx::Error> {
+    if let Some(slot) = request.extensions().get::<IdempotencyTransaction>() {
+        let mut slot = slot.0.borrow_mut();
+        if slot.is_none() {
+            *slot = Some(transaction);
+            return Ok(());
+        }
+    }
+    transaction.commit().await
+}
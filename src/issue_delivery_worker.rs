@@ -1,8 +1,13 @@
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use crate::{
-    configuration::Settings, domain::SubscriberEmail, email_client::EmailClient,
+    configuration::Settings,
+    domain::SubscriberEmail,
+    email_client::EmailClient,
+    metrics::METRICS,
+    request_id::{with_request_id, RequestId},
     startup::get_connection_pool,
+    telemetry::link_to_traceparent,
 };
 use sqlx::{Executor, PgPool, Postgres, Transaction};
 use tracing::{field::display, Span};
@@ -17,7 +22,8 @@ pub enum ExecutionOutcome {
     skip_all,
     fields(
         newsletter_issue_id=tracing::field::Empty,
-        subscriber_email=tracing::field::Empty
+        subscriber_email=tracing::field::Empty,
+        x_request_id=tracing::field::Empty
     ),
     err
 )]
@@ -29,22 +35,40 @@ pub async fn try_execute_task(
     if task.is_none() {
         return Ok(ExecutionOutcome::EmptyQueue);
     }
-    let (transaction, issue_id, email) = task.unwrap();
+    let (
+        transaction,
+        DeliveryTask {
+            issue_id,
+            email,
+            trace_context,
+            request_id,
+        },
+    ) = task.unwrap();
     Span::current()
-        .record("newsletter_issue_id", &display(issue_id))
-        .record("subscriber_email", &display(&email));
+        .record("newsletter_issue_id", display(issue_id))
+        .record("subscriber_email", display(&email));
+    if let Some(trace_context) = trace_context {
+        link_to_traceparent(&trace_context);
+    }
+    // Correlates the delivery with the publish request.
+    let request_id = request_id.as_deref().and_then(RequestId::parse);
+    if let Some(request_id) = &request_id {
+        Span::current().record("x_request_id", display(request_id));
+    }
     match SubscriberEmail::parse(email.clone()) {
         Ok(email) => {
             let issue = get_issue(pool, issue_id).await?;
-            if let Err(e) = email_client
-                .send_email(
+            let outcome = with_request_id(
+                request_id,
+                email_client.send_email(
                     &email,
                     &issue.title,
                     &issue.html_content,
                     &issue.text_content,
-                )
-                .await
-            {
+                ),
+            )
+            .await;
+            if let Err(e) = outcome {
                 tracing::error!(
                 error.cause_chain = ?e,
                 error.message = %e,
@@ -67,15 +91,23 @@ pub async fn try_execute_task(
 
 type PgTransaction = Transaction<'static, Postgres>;
 
+struct DeliveryTask {
+    issue_id: Uuid,
+    email: String,
+    // W3C traceparent and X-Request-Id of the request that enqueued the task.
+    trace_context: Option<String>,
+    request_id: Option<String>,
+}
+
 /// Procceses an email delivery task.
 #[tracing::instrument(skip_all)]
 async fn dequeue_task(
     pool: &PgPool,
-) -> Result<Option<(PgTransaction, Uuid, String)>, anyhow::Error> {
+) -> Result<Option<(PgTransaction, DeliveryTask)>, anyhow::Error> {
     let mut transaction = pool.begin().await?;
     let r = sqlx::query!(
         r#"
-            SELECT newsletter_issue_id, subscriber_email
+            SELECT newsletter_issue_id, subscriber_email, trace_context, request_id
             FROM issue_delivery_queue
             FOR UPDATE
             SKIP LOCKED
@@ -87,8 +119,12 @@ async fn dequeue_task(
     if let Some(row) = r {
         Ok(Some((
             transaction,
-            row.newsletter_issue_id,
-            row.subscriber_email,
+            DeliveryTask {
+                issue_id: row.newsletter_issue_id,
+                email: row.subscriber_email,
+                trace_context: row.trace_context,
+                request_id: row.request_id,
+            },
         )))
     } else {
         Ok(None)
@@ -142,7 +178,18 @@ async fn get_issue(pool: &PgPool, issue_id: Uuid) -> Result<NewsletterIssue, any
 
 async fn worker_loop(pool: PgPool, email_client: EmailClient) -> Result<(), anyhow::Error> {
     loop {
-        match try_execute_task(&pool, &email_client).await {
+        let start = Instant::now();
+        let outcome = try_execute_task(&pool, &email_client).await;
+        let outcome_label = match outcome {
+            Ok(ExecutionOutcome::TaskCompleted) => "task_completed",
+            Ok(ExecutionOutcome::EmptyQueue) => "empty_queue",
+            Err(_) => "error",
+        };
+        METRICS
+            .delivery_worker_iteration_duration_seconds
+            .with_label_values(&[outcome_label])
+            .observe(start.elapsed().as_secs_f64());
+        match outcome {
             Ok(ExecutionOutcome::TaskCompleted) => {
                 tokio::time::sleep(Duration::from_secs(1)).await;
             }
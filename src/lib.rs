@@ -1,11 +1,22 @@
+pub mod audit;
 pub mod authentication;
+pub mod client_ip;
 pub mod configuration;
 pub mod domain;
 pub mod email_client;
+pub mod email_domains;
+pub mod form_token;
 pub mod idempotency;
 pub mod issue_delivery_worker;
+pub mod metrics;
+pub mod migrations;
+pub mod newsletter_issues;
+pub mod rate_limit;
+pub mod request_id;
 pub mod routes;
+pub mod security_headers;
 pub mod session_state;
+pub mod session_store;
 pub mod startup;
 pub mod telemetry;
 pub mod utils;
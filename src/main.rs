@@ -1,27 +1,71 @@
 use std::fmt::{Debug, Display};
 
 use tokio::task::JoinError;
-use zero2prod2::configuration::get_configuration;
+use zero2prod2::configuration::{get_configuration, Settings};
+use zero2prod2::idempotency::run_cleanup_worker_until_stopped;
 use zero2prod2::issue_delivery_worker::run_worker_until_stopped;
-use zero2prod2::startup::Application;
-use zero2prod2::telemetry::{get_subscriber, init_subscriber};
+use zero2prod2::migrations::{apply_email_normalization, check_schema_version};
+use zero2prod2::newsletter_issues::run_issue_scheduler_until_stopped;
+use zero2prod2::rate_limit::run_rate_limit_cleanup_worker_until_stopped;
+use zero2prod2::session_store::run_session_cleanup_worker_until_stopped;
+use zero2prod2::startup::{get_connection_pool, Application};
+use zero2prod2::telemetry::{get_subscriber, init_subscriber, shutdown_tracing};
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
+    let configuration = get_configuration().expect("Failed to read configuration.");
+
     // Setup tracing and logging.
-    let subscriber = get_subscriber("zero2prod2".into(), "info".into(), std::io::stdout);
-    init_subscriber(subscriber);
+    let (subscriber, log_filter) = get_subscriber(
+        "zero2prod2".into(),
+        "info".into(),
+        &configuration.telemetry,
+        std::io::stdout,
+    );
+    init_subscriber(subscriber, log_filter);
 
-    let configuration = get_configuration().expect("Failed to read configuration.");
+    // One-off maintenance commands run instead of the application.
+    if let Some(command) = std::env::args().nth(1) {
+        let outcome = match command.as_str() {
+            "normalize-subscriber-emails" => normalize_subscriber_emails(configuration).await,
+            _ => Err(anyhow::anyhow!("Unknown command `{}`.", command)),
+        };
+        shutdown_tracing();
+        return outcome;
+    }
 
     let application = Application::build(configuration.clone()).await?;
     let application_task = tokio::spawn(application.run_until_stopped());
-    let worker_task = tokio::spawn(run_worker_until_stopped(configuration));
+    let worker_task = tokio::spawn(run_worker_until_stopped(configuration.clone()));
+    let idempotency_cleanup_task =
+        tokio::spawn(run_cleanup_worker_until_stopped(configuration.clone()));
+    let rate_limit_cleanup_task = tokio::spawn(run_rate_limit_cleanup_worker_until_stopped(
+        configuration.clone(),
+    ));
+    let session_cleanup_task = tokio::spawn(run_session_cleanup_worker_until_stopped(
+        configuration.clone(),
+    ));
+    let issue_scheduler_task = tokio::spawn(run_issue_scheduler_until_stopped(configuration));
 
     tokio::select! {
         o = application_task => report_exit("API", o),
         o = worker_task => report_exit("Background worker", o),
+        o = idempotency_cleanup_task => report_exit("Idempotency cleanup worker", o),
+        o = rate_limit_cleanup_task => report_exit("Rate limit cleanup worker", o),
+        o = session_cleanup_task => report_exit("Session cleanup worker", o),
+        o = issue_scheduler_task => report_exit("Issue scheduler", o),
     }
+    shutdown_tracing();
+    Ok(())
+}
+
+/// Applies the configured email normalization to the stored subscribers, deleting the
+/// duplicates it reveals. See `apply_email_normalization`.
+async fn normalize_subscriber_emails(configuration: Settings) -> anyhow::Result<()> {
+    let pool = get_connection_pool(&configuration.database);
+    check_schema_version(&pool).await?;
+    // The deleted subscribers are logged.
+    apply_email_normalization(&pool, configuration.subscriptions.email_normalization()).await?;
     Ok(())
 }
 
@@ -0,0 +1,187 @@
+use std::time::Instant;
+
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use actix_web_lab::middleware::Next;
+use anyhow::Context;
+use once_cell::sync::Lazy;
+use prometheus::{
+    Encoder, Histogram, HistogramOpts, HistogramVec, IntCounterVec, IntGauge, IntGaugeVec, Opts,
+    Registry, TextEncoder,
+};
+use sqlx::PgPool;
+
+use crate::utils::e500;
+
+/// Application wide metrics, exposed in the prometheus text format on /metrics.
+pub static METRICS: Lazy<Metrics> =
+    Lazy::new(|| Metrics::new().expect("Failed to register metrics."));
+
+pub struct Metrics {
+    registry: Registry,
+    /// HTTP requests by method, matched route and status code.
+    pub http_requests_total: IntCounterVec,
+    /// HTTP request latency by method, matched route and status code.
+    pub http_request_duration_seconds: HistogramVec,
+    /// Emails waiting in issue_delivery_queue, refreshed on scrape.
+    pub delivery_queue_depth: IntGauge,
+    /// Emails accepted by the email provider.
+    pub emails_sent_total: IntCounterVec,
+    /// Emails the email provider failed to accept.
+    pub emails_failed_total: IntCounterVec,
+    /// Duration of a delivery worker iteration by outcome.
+    pub delivery_worker_iteration_duration_seconds: HistogramVec,
+    /// Duration of an argon2 password hash verification.
+    pub password_verification_duration_seconds: Histogram,
+    /// Database pool connections by state (idle, in_use), refreshed on scrape.
+    pub db_pool_connections: IntGaugeVec,
+    /// Database pool size limit.
+    pub db_pool_max_connections: IntGauge,
+}
+
+impl Metrics {
+    fn new() -> Result<Self, prometheus::Error> {
+        let registry = Registry::new();
+        let http_requests_total = IntCounterVec::new(
+            Opts::new("http_requests_total", "HTTP requests handled."),
+            &["method", "route", "status"],
+        )?;
+        let http_request_duration_seconds = HistogramVec::new(
+            HistogramOpts::new(
+                "http_request_duration_seconds",
+                "HTTP request latency in seconds.",
+            ),
+            &["method", "route", "status"],
+        )?;
+        let delivery_queue_depth = IntGauge::new(
+            "delivery_queue_depth",
+            "Emails waiting in the issue delivery queue.",
+        )?;
+        let emails_sent_total = IntCounterVec::new(
+            Opts::new(
+                "emails_sent_total",
+                "Emails accepted by the email provider.",
+            ),
+            &["provider"],
+        )?;
+        let emails_failed_total = IntCounterVec::new(
+            Opts::new(
+                "emails_failed_total",
+                "Emails the email provider failed to accept.",
+            ),
+            &["provider"],
+        )?;
+        let delivery_worker_iteration_duration_seconds = HistogramVec::new(
+            HistogramOpts::new(
+                "delivery_worker_iteration_duration_seconds",
+                "Duration of a delivery worker iteration in seconds.",
+            ),
+            &["outcome"],
+        )?;
+        let password_verification_duration_seconds = Histogram::with_opts(HistogramOpts::new(
+            "password_verification_duration_seconds",
+            "Duration of an argon2 password verification in seconds.",
+        ))?;
+        let db_pool_connections = IntGaugeVec::new(
+            Opts::new("db_pool_connections", "Database pool connections."),
+            &["state"],
+        )?;
+        let db_pool_max_connections = IntGauge::new(
+            "db_pool_max_connections",
+            "Maximum number of database pool connections.",
+        )?;
+
+        registry.register(Box::new(http_requests_total.clone()))?;
+        registry.register(Box::new(http_request_duration_seconds.clone()))?;
+        registry.register(Box::new(delivery_queue_depth.clone()))?;
+        registry.register(Box::new(emails_sent_total.clone()))?;
+        registry.register(Box::new(emails_failed_total.clone()))?;
+        registry.register(Box::new(delivery_worker_iteration_duration_seconds.clone()))?;
+        registry.register(Box::new(password_verification_duration_seconds.clone()))?;
+        registry.register(Box::new(db_pool_connections.clone()))?;
+        registry.register(Box::new(db_pool_max_connections.clone()))?;
+
+        Ok(Self {
+            registry,
+            http_requests_total,
+            http_request_duration_seconds,
+            delivery_queue_depth,
+            emails_sent_total,
+            emails_failed_total,
+            delivery_worker_iteration_duration_seconds,
+            password_verification_duration_seconds,
+            db_pool_connections,
+            db_pool_max_connections,
+        })
+    }
+}
+
+/// Middleware recording request counts and latencies.
+/// Requests are labelled by route pattern (e.g. /subscriptions/confirm) rather than path
+/// to keep the number of series bounded.
+pub async fn record_http_metrics(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let method = req.method().to_string();
+    let start = Instant::now();
+    let response = next.call(req).await?;
+    let route = response
+        .request()
+        .match_pattern()
+        .unwrap_or_else(|| "unmatched".into());
+    let status = response.status().as_u16().to_string();
+    let labels = [method.as_str(), route.as_str(), status.as_str()];
+    METRICS.http_requests_total.with_label_values(&labels).inc();
+    METRICS
+        .http_request_duration_seconds
+        .with_label_values(&labels)
+        .observe(start.elapsed().as_secs_f64());
+    Ok(response)
+}
+
+/// Returns all metrics in the prometheus text format.
+pub async fn metrics(pool: web::Data<PgPool>) -> Result<HttpResponse, actix_web::Error> {
+    update_pool_metrics(&pool);
+    update_delivery_queue_depth(&pool).await.map_err(e500)?;
+
+    let mut buffer = vec![];
+    TextEncoder::new()
+        .encode(&METRICS.registry.gather(), &mut buffer)
+        .map_err(e500)?;
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType(
+            TextEncoder::new()
+                .format_type()
+                .parse()
+                .expect("Invalid prometheus content type."),
+        ))
+        .body(buffer))
+}
+
+fn update_pool_metrics(pool: &PgPool) {
+    let size = i64::from(pool.size());
+    let idle = pool.num_idle() as i64;
+    METRICS
+        .db_pool_connections
+        .with_label_values(&["idle"])
+        .set(idle);
+    METRICS
+        .db_pool_connections
+        .with_label_values(&["in_use"])
+        .set(size - idle);
+    METRICS
+        .db_pool_max_connections
+        .set(i64::from(pool.options().get_max_connections()));
+}
+
+async fn update_delivery_queue_depth(pool: &PgPool) -> Result<(), anyhow::Error> {
+    let depth = sqlx::query_scalar!(r#"SELECT COUNT(*) as "count!" FROM issue_delivery_queue"#)
+        .fetch_one(pool)
+        .await
+        .context("Failed to count the issue delivery queue.")?;
+    METRICS.delivery_queue_depth.set(depth);
+    Ok(())
+}
@@ -0,0 +1,255 @@
+use anyhow::Context;
+use sqlx::migrate::Migrator;
+use sqlx::{PgConnection, PgExecutor, PgPool};
+
+use crate::domain::EmailNormalization;
+
+/// Migrations under ./migrations, embedded into the binary at compile time.
+static MIGRATOR: Migrator = sqlx::migrate!("./migrations");
+
+/// Key of the postgres advisory lock held while migrating.
+/// Only one instance at a time gets to touch the schema, the others wait.
+const MIGRATION_LOCK_ID: i64 = 0x7a65_726f_3270_726f;
+
+/// Applies all pending migrations.
+///
+/// Holds a postgres advisory lock for the whole run so that multiple instances
+/// booting at the same time don't race each other.
+/// Fails if the database was migrated by a newer binary.
+#[tracing::instrument(name = "Run database migrations", skip(pool))]
+pub async fn run_migrations(pool: &PgPool) -> Result<(), anyhow::Error> {
+    let mut connection = pool
+        .acquire()
+        .await
+        .context("Failed to acquire a Postgres connection to run migrations.")?;
+    sqlx::query("SELECT pg_advisory_lock($1)")
+        .bind(MIGRATION_LOCK_ID)
+        .execute(&mut *connection)
+        .await
+        .context("Failed to acquire the migration lock.")?;
+
+    // Always release the lock, even if migrating failed.
+    let outcome = migrate(&mut connection).await;
+
+    sqlx::query("SELECT pg_advisory_unlock($1)")
+        .bind(MIGRATION_LOCK_ID)
+        .execute(&mut *connection)
+        .await
+        .context("Failed to release the migration lock.")?;
+    outcome
+}
+
+async fn migrate(connection: &mut PgConnection) -> Result<(), anyhow::Error> {
+    check_schema_version(&mut *connection).await?;
+    MIGRATOR
+        .run(connection)
+        .await
+        .context("Failed to run database migrations.")?;
+    Ok(())
+}
+
+/// Returns an error if the database has migrations applied that this binary
+/// doesn't know about, i.e. the schema is newer than the code.
+#[tracing::instrument(name = "Check database schema version", skip(executor))]
+pub async fn check_schema_version<'c, E>(executor: E) -> Result<(), anyhow::Error>
+where
+    E: PgExecutor<'c>,
+{
+    // The migrations table is owned by sqlx and doesn't exist until the first run.
+    let applied_version: Option<i64> = sqlx::query_scalar(
+        r#"
+        SELECT MAX(version)
+        FROM _sqlx_migrations
+        WHERE success = true
+        "#,
+    )
+    .fetch_one(executor)
+    .await
+    .or_else(|e| match e {
+        // 42P01 = undefined_table.
+        sqlx::Error::Database(e) if e.code().as_deref() == Some("42P01") => Ok(None),
+        e => Err(e),
+    })
+    .context("Failed to read the applied schema version.")?;
+
+    let known_version = MIGRATOR.iter().map(|m| m.version).max().unwrap_or(0);
+    match applied_version {
+        Some(applied) if applied > known_version => anyhow::bail!(
+            "The database schema (version {applied}) is newer than this binary \
+            knows about (version {known_version}). Refusing to start."
+        ),
+        _ => Ok(()),
+    }
+}
+
+/// What `apply_email_normalization` changed.
+#[derive(Debug, Default)]
+pub struct EmailNormalizationReport {
+    /// Emails of the subscribers deleted as duplicates of another, tokens included.
+    pub deleted_subscribers: Vec<String>,
+    /// Subscribers whose email was rewritten to its folded form.
+    pub folded_subscribers: u64,
+    /// Pending deliveries deleted as duplicates of another.
+    pub deleted_deliveries: u64,
+}
+
+/// Brings the stored subscriber emails in line with the configured normalization.
+///
+/// The migrations only normalize the domain. When the local part is case folded too, this
+/// folds the existing emails, deleting all but one subscriber per folded address, and
+/// enforces uniqueness on the folded form with a unique index on `lower(email)`. The index
+/// is dropped again when folding is turned off, `Foo@example.com` and `foo@example.com`
+/// are then different subscribers.
+///
+/// Deleting subscribers can't be undone: this only runs when an operator asks for it, with
+/// `zero2prod2 normalize-subscriber-emails`, never on startup.
+#[tracing::instrument(name = "Apply the subscriber email normalization", skip(pool))]
+pub async fn apply_email_normalization(
+    pool: &PgPool,
+    normalization: EmailNormalization,
+) -> Result<EmailNormalizationReport, anyhow::Error> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to start a transaction.")?;
+    // Don't race an instance that is migrating the schema.
+    sqlx::query("SELECT pg_advisory_xact_lock($1)")
+        .bind(MIGRATION_LOCK_ID)
+        .execute(&mut *transaction)
+        .await
+        .context("Failed to acquire the migration lock.")?;
+    if !normalization.fold_local_part_case {
+        sqlx::query("DROP INDEX IF EXISTS subscriptions_folded_email_key")
+            .execute(&mut *transaction)
+            .await
+            .context("Failed to drop the folded email index.")?;
+        transaction
+            .commit()
+            .await
+            .context("Failed to commit the email normalization.")?;
+        return Ok(EmailNormalizationReport::default());
+    }
+
+    // Keep one subscriber per folded email: confirmed first, then the oldest.
+    sqlx::query(
+        r#"
+        CREATE TEMPORARY TABLE duplicate_subscriptions ON COMMIT DROP AS
+        SELECT id FROM (
+            SELECT
+                id,
+                row_number() OVER (
+                    PARTITION BY lower(email)
+                    ORDER BY status = 'confirmed' DESC, subscribed_at, id
+                ) AS rank
+            FROM subscriptions
+        ) ranked
+        WHERE rank > 1
+        "#,
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to find the duplicate subscriptions.")?;
+    sqlx::query(
+        r#"
+        DELETE FROM subscription_tokens
+        WHERE subscriber_id IN (SELECT id FROM duplicate_subscriptions)
+        "#,
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to delete the tokens of duplicate subscriptions.")?;
+    let deleted_subscribers: Vec<String> = sqlx::query_scalar(
+        r#"
+        DELETE FROM subscriptions WHERE id IN (SELECT id FROM duplicate_subscriptions)
+        RETURNING email
+        "#,
+    )
+    .fetch_all(&mut *transaction)
+    .await
+    .context("Failed to delete the duplicate subscriptions.")?;
+    let folded_subscribers =
+        sqlx::query("UPDATE subscriptions SET email = lower(email) WHERE email <> lower(email)")
+            .execute(&mut *transaction)
+            .await
+            .context("Failed to fold the subscriber emails.")?
+            .rows_affected();
+    // Same for pending deliveries, so nobody gets an issue twice.
+    let deleted_deliveries = sqlx::query(
+        r#"
+        DELETE FROM issue_delivery_queue q
+        USING issue_delivery_queue other
+        WHERE q.newsletter_issue_id = other.newsletter_issue_id
+            AND lower(q.subscriber_email) = lower(other.subscriber_email)
+            AND q.subscriber_email > other.subscriber_email
+        "#,
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to delete the duplicate deliveries.")?
+    .rows_affected();
+    for (query, what) in [
+        (
+            r#"
+            UPDATE issue_delivery_queue SET subscriber_email = lower(subscriber_email)
+            WHERE subscriber_email <> lower(subscriber_email)
+            "#,
+            "Failed to fold the emails of pending deliveries.",
+        ),
+        (
+            r#"
+            CREATE UNIQUE INDEX IF NOT EXISTS subscriptions_folded_email_key
+            ON subscriptions (lower(email))
+            "#,
+            "Failed to create the folded email index.",
+        ),
+    ] {
+        sqlx::query(query)
+            .execute(&mut *transaction)
+            .await
+            .context(what)?;
+    }
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit the email normalization.")?;
+
+    for email in &deleted_subscribers {
+        tracing::warn!(subscriber_email = %email, "Deleted a duplicate subscriber");
+    }
+    tracing::info!(
+        deleted_subscribers = deleted_subscribers.len(),
+        folded_subscribers,
+        deleted_deliveries,
+        "Folded the subscriber emails"
+    );
+    Ok(EmailNormalizationReport {
+        deleted_subscribers,
+        folded_subscribers,
+        deleted_deliveries,
+    })
+}
+
+/// Warns when the stored emails don't match the configured normalization, i.e. when
+/// `apply_email_normalization` still has to be run. Changes nothing.
+#[tracing::instrument(name = "Check the subscriber email normalization", skip(executor))]
+pub async fn check_email_normalization<'c, E>(
+    executor: E,
+    normalization: EmailNormalization,
+) -> Result<(), anyhow::Error>
+where
+    E: PgExecutor<'c>,
+{
+    let folded: bool =
+        sqlx::query_scalar("SELECT to_regclass('subscriptions_folded_email_key') IS NOT NULL")
+            .fetch_one(executor)
+            .await
+            .context("Failed to look up the folded email index.")?;
+    if folded != normalization.fold_local_part_case {
+        tracing::warn!(
+            fold_email_local_part_case = normalization.fold_local_part_case,
+            "The stored subscriber emails don't match the configured normalization, \
+            run `zero2prod2 normalize-subscriber-emails` to apply it"
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,352 @@
+//! Newsletter issues: drafting, scheduling and publishing them, i.e. queueing their
+//! delivery to the confirmed subscribers.
+use std::time::Duration;
+
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::{Executor, PgExecutor, PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::configuration::Settings;
+use crate::domain::{IssueStatus, SubscriptionStatus};
+use crate::request_id::current_request_id;
+use crate::routes::error_chain_fmt;
+use crate::startup::get_connection_pool;
+use crate::telemetry::current_traceparent;
+use crate::utils::run_periodically;
+
+/// How often the scheduler looks for issues due for publishing.
+const SCHEDULER_INTERVAL: Duration = Duration::from_secs(10);
+
+/// A newsletter issue, along with the progress of its delivery.
+pub struct IssueRecord {
+    pub newsletter_issue_id: Uuid,
+    pub title: String,
+    pub text_content: String,
+    pub html_content: String,
+    pub status: IssueStatus,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    pub scheduled_for: Option<DateTime<Utc>>,
+    pub published_at: Option<DateTime<Utc>>,
+    // Unknown for issues published before it was recorded.
+    pub recipient_count: Option<i64>,
+    // Emails still waiting in the delivery queue.
+    pub pending_deliveries: i64,
+}
+
+#[derive(thiserror::Error)]
+pub enum IssueError {
+    #[error("There is no newsletter issue with this id.")]
+    NotFound,
+    #[error("The issue is {}, it can't be {}.", .current.as_str(), .action)]
+    InvalidTransition {
+        current: IssueStatus,
+        action: &'static str,
+    },
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for IssueError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+/// Stores a new draft and returns its id.
+#[tracing::instrument(skip_all)]
+pub async fn insert_newsletter_issue(
+    transaction: &mut Transaction<'_, Postgres>,
+    title: &str,
+    text_content: &str,
+    html_content: &str,
+) -> Result<Uuid, sqlx::Error> {
+    let newsletter_issue_id = Uuid::new_v4();
+    let query = sqlx::query!(
+        r#"
+        INSERT INTO newsletter_issues (
+            newsletter_issue_id,
+            title,
+            text_content,
+            html_content,
+            status,
+            created_at,
+            updated_at
+        )
+        VALUES ($1, $2, $3, $4, $5, now(), now())
+        "#,
+        newsletter_issue_id,
+        title,
+        text_content,
+        html_content,
+        IssueStatus::Draft as IssueStatus,
+    );
+    transaction.execute(query).await?;
+    Ok(newsletter_issue_id)
+}
+
+/// Queues an email to every confirmed subscriber, returns how many were queued.
+#[tracing::instrument(skip_all)]
+pub async fn enqueue_delivery_tasks(
+    transaction: &mut Transaction<'_, Postgres>,
+    newsletter_issue_id: Uuid,
+) -> Result<u64, sqlx::Error> {
+    // Lets the delivery worker link its spans and emails back to this request.
+    let trace_context = current_traceparent();
+    let request_id = current_request_id().map(|id| id.to_string());
+    let query = sqlx::query!(
+        r#"
+        INSERT INTO issue_delivery_queue (
+            newsletter_issue_id,
+            subscriber_email,
+            trace_context,
+            request_id
+        )
+        SELECT $1, email, $2, $3
+        FROM subscriptions
+        WHERE status = $4
+        "#,
+        newsletter_issue_id,
+        trace_context,
+        request_id,
+        SubscriptionStatus::Confirmed as SubscriptionStatus,
+    );
+    let n_queued = transaction.execute(query).await?.rows_affected();
+    Ok(n_queued)
+}
+
+/// Publishes a draft or scheduled issue: queues its delivery and marks it as published.
+/// The issue is locked until `transaction` ends, it can't be published twice.
+#[tracing::instrument(skip(transaction))]
+pub async fn publish_issue_now(
+    transaction: &mut Transaction<'_, Postgres>,
+    newsletter_issue_id: Uuid,
+) -> Result<(), IssueError> {
+    let current = sqlx::query_scalar!(
+        r#"
+        SELECT status AS "status: IssueStatus"
+        FROM newsletter_issues
+        WHERE newsletter_issue_id = $1
+        FOR UPDATE
+        "#,
+        newsletter_issue_id,
+    )
+    .fetch_optional(&mut **transaction)
+    .await
+    .context("Failed to lock the newsletter issue.")?
+    .ok_or(IssueError::NotFound)?;
+    if !current.can_transition_to(IssueStatus::Published) {
+        return Err(IssueError::InvalidTransition {
+            current,
+            action: "published",
+        });
+    }
+
+    let n_recipients = enqueue_delivery_tasks(transaction, newsletter_issue_id)
+        .await
+        .context("Failed to enqueue delivery tasks.")?;
+    sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET status = $2, published_at = now(), updated_at = now(), scheduled_for = NULL,
+            recipient_count = $3
+        WHERE newsletter_issue_id = $1
+        "#,
+        newsletter_issue_id,
+        IssueStatus::Published as IssueStatus,
+        n_recipients as i64,
+    )
+    .execute(&mut **transaction)
+    .await
+    .context("Failed to mark the newsletter issue as published.")?;
+    Ok(())
+}
+
+/// Replaces the fields of an unpublished issue that are `Some`.
+#[tracing::instrument(skip(transaction, title, text_content, html_content))]
+pub async fn update_issue(
+    transaction: &mut Transaction<'_, Postgres>,
+    newsletter_issue_id: Uuid,
+    title: Option<&str>,
+    text_content: Option<&str>,
+    html_content: Option<&str>,
+) -> Result<(), IssueError> {
+    let editable: Vec<IssueStatus> = IssueStatus::ALL
+        .into_iter()
+        .filter(|s| s.is_editable())
+        .collect();
+    let n_updated = sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET title = COALESCE($2, title),
+            text_content = COALESCE($3, text_content),
+            html_content = COALESCE($4, html_content),
+            updated_at = now()
+        WHERE newsletter_issue_id = $1 AND status = ANY($5)
+        "#,
+        newsletter_issue_id,
+        title,
+        text_content,
+        html_content,
+        editable as Vec<IssueStatus>,
+    )
+    .execute(&mut **transaction)
+    .await
+    .context("Failed to update the newsletter issue.")?
+    .rows_affected();
+    if n_updated == 0 {
+        return Err(why_not(&mut **transaction, newsletter_issue_id, "edited").await);
+    }
+    Ok(())
+}
+
+/// Has the scheduler publish the issue at `publish_at`.
+#[tracing::instrument(skip(transaction))]
+pub async fn schedule_issue(
+    transaction: &mut Transaction<'_, Postgres>,
+    newsletter_issue_id: Uuid,
+    publish_at: DateTime<Utc>,
+) -> Result<(), IssueError> {
+    let n_updated = sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET status = $2, scheduled_for = $3, updated_at = now()
+        WHERE newsletter_issue_id = $1 AND status = ANY($4)
+        "#,
+        newsletter_issue_id,
+        IssueStatus::Scheduled as IssueStatus,
+        publish_at,
+        IssueStatus::sources_of(IssueStatus::Scheduled) as Vec<IssueStatus>,
+    )
+    .execute(&mut **transaction)
+    .await
+    .context("Failed to schedule the newsletter issue.")?
+    .rows_affected();
+    if n_updated == 0 {
+        return Err(why_not(&mut **transaction, newsletter_issue_id, "scheduled").await);
+    }
+    Ok(())
+}
+
+/// Explains why a guarded update of the issue didn't go through.
+async fn why_not(
+    executor: impl PgExecutor<'_>,
+    newsletter_issue_id: Uuid,
+    action: &'static str,
+) -> IssueError {
+    match get_issue(executor, newsletter_issue_id).await {
+        Ok(Some(issue)) => IssueError::InvalidTransition {
+            current: issue.status,
+            action,
+        },
+        Ok(None) => IssueError::NotFound,
+        Err(e) => IssueError::UnexpectedError(e),
+    }
+}
+
+#[tracing::instrument(skip(executor))]
+pub async fn get_issue(
+    executor: impl PgExecutor<'_>,
+    newsletter_issue_id: Uuid,
+) -> Result<Option<IssueRecord>, anyhow::Error> {
+    let issue = sqlx::query_as!(
+        IssueRecord,
+        r#"
+        SELECT i.newsletter_issue_id, i.title, i.text_content, i.html_content,
+            i.status AS "status: IssueStatus", i.created_at, i.updated_at, i.scheduled_for,
+            i.published_at, i.recipient_count,
+            (
+                SELECT COUNT(*) FROM issue_delivery_queue q
+                WHERE q.newsletter_issue_id = i.newsletter_issue_id
+            ) AS "pending_deliveries!"
+        FROM newsletter_issues i
+        WHERE i.newsletter_issue_id = $1
+        "#,
+        newsletter_issue_id,
+    )
+    .fetch_optional(executor)
+    .await
+    .context("Failed to get a newsletter issue.")?;
+    Ok(issue)
+}
+
+/// Returns the issues in `status`, all of them if `None`, the most recently created first.
+#[tracing::instrument(skip(pool))]
+pub async fn list_issues(
+    pool: &PgPool,
+    status: Option<IssueStatus>,
+    limit: i64,
+    offset: i64,
+) -> Result<Vec<IssueRecord>, anyhow::Error> {
+    let issues = sqlx::query_as!(
+        IssueRecord,
+        r#"
+        SELECT i.newsletter_issue_id, i.title, i.text_content, i.html_content,
+            i.status AS "status: IssueStatus", i.created_at, i.updated_at, i.scheduled_for,
+            i.published_at, i.recipient_count,
+            (
+                SELECT COUNT(*) FROM issue_delivery_queue q
+                WHERE q.newsletter_issue_id = i.newsletter_issue_id
+            ) AS "pending_deliveries!"
+        FROM newsletter_issues i
+        WHERE ($1::issue_status IS NULL OR i.status = $1)
+        ORDER BY i.created_at DESC, i.newsletter_issue_id
+        LIMIT $2 OFFSET $3
+        "#,
+        status as Option<IssueStatus>,
+        limit,
+        offset,
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to list newsletter issues.")?;
+    Ok(issues)
+}
+
+/// Publishes the scheduled issues that are due, returns how many were published.
+#[tracing::instrument(skip_all, err)]
+pub async fn publish_due_issues(pool: &PgPool) -> Result<u64, anyhow::Error> {
+    let mut n_published = 0;
+    loop {
+        let mut transaction = pool
+            .begin()
+            .await
+            .context("Failed to start a transaction.")?;
+        // Other instances' schedulers skip the issue this one is publishing.
+        let due = sqlx::query_scalar!(
+            r#"
+            SELECT newsletter_issue_id
+            FROM newsletter_issues
+            WHERE status = $1 AND scheduled_for <= now()
+            ORDER BY scheduled_for
+            FOR UPDATE SKIP LOCKED
+            LIMIT 1
+            "#,
+            IssueStatus::Scheduled as IssueStatus,
+        )
+        .fetch_optional(&mut *transaction)
+        .await
+        .context("Failed to look for due newsletter issues.")?;
+        let Some(newsletter_issue_id) = due else {
+            return Ok(n_published);
+        };
+        publish_issue_now(&mut transaction, newsletter_issue_id)
+            .await
+            .context("Failed to publish a scheduled newsletter issue.")?;
+        transaction
+            .commit()
+            .await
+            .context("Failed to commit a scheduled newsletter issue.")?;
+        tracing::info!(%newsletter_issue_id, "Published a scheduled newsletter issue.");
+        n_published += 1;
+    }
+}
+
+/// Runs a loop that publishes scheduled issues once they're due.
+pub async fn run_issue_scheduler_until_stopped(
+    configuration: Settings,
+) -> Result<(), anyhow::Error> {
+    let pool = get_connection_pool(&configuration.database);
+    run_periodically(SCHEDULER_INTERVAL, || publish_due_issues(&pool)).await
+}
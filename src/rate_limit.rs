@@ -0,0 +1,63 @@
+use std::time::Duration;
+
+use anyhow::Context;
+use sqlx::PgPool;
+
+use crate::configuration::{RateLimitSettings, Settings};
+use crate::startup::get_connection_pool;
+use crate::utils::run_periodically;
+
+/// How often expired counters are deleted.
+const CLEANUP_INTERVAL: Duration = Duration::from_secs(3600);
+
+/// Counts a hit against `key` and returns whether it's within the limit.
+///
+/// Counters live in postgres so that all instances share them. Windows are fixed:
+/// the first hit opens one, the counter resets once it's over.
+#[tracing::instrument(name = "Check rate limit", skip(pool, key, limit))]
+pub async fn check_rate_limit(
+    pool: &PgPool,
+    scope: &str,
+    key: &str,
+    limit: &RateLimitSettings,
+) -> Result<bool, anyhow::Error> {
+    let hits = sqlx::query_scalar!(
+        r#"
+        INSERT INTO rate_limits (scope, key, hits, window_ends_at)
+        VALUES ($1, $2, 1, now() + $3::interval)
+        ON CONFLICT (scope, key) DO UPDATE SET
+            hits = CASE WHEN rate_limits.window_ends_at <= now()
+                THEN 1 ELSE rate_limits.hits + 1 END,
+            window_ends_at = CASE WHEN rate_limits.window_ends_at <= now()
+                THEN now() + $3::interval ELSE rate_limits.window_ends_at END
+        RETURNING hits
+        "#,
+        scope,
+        key,
+        limit.window() as Duration,
+    )
+    .fetch_one(pool)
+    .await
+    .context("Failed to count a hit against the rate limit.")?;
+    Ok(hits as u32 <= limit.max_hits)
+}
+
+/// Deletes the counters whose window is over.
+/// Returns the number of deleted counters.
+#[tracing::instrument(skip(pool), fields(n_deleted_counters = tracing::field::Empty), err)]
+pub async fn delete_expired_rate_limits(pool: &PgPool) -> Result<u64, anyhow::Error> {
+    let n_deleted_counters = sqlx::query!("DELETE FROM rate_limits WHERE window_ends_at <= now()")
+        .execute(pool)
+        .await?
+        .rows_affected();
+    tracing::Span::current().record("n_deleted_counters", n_deleted_counters);
+    Ok(n_deleted_counters)
+}
+
+/// Runs a loop that periodically deletes expired rate limit counters.
+pub async fn run_rate_limit_cleanup_worker_until_stopped(
+    configuration: Settings,
+) -> Result<(), anyhow::Error> {
+    let pool = get_connection_pool(&configuration.database);
+    run_periodically(CLEANUP_INTERVAL, || delete_expired_rate_limits(&pool)).await
+}
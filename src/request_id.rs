@@ -0,0 +1,139 @@
+use std::future::Future;
+
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::{HeaderName, HeaderValue};
+use actix_web::HttpMessage;
+use actix_web_lab::middleware::Next;
+use tracing::Span;
+use tracing_actix_web::{root_span, DefaultRootSpanBuilder, Level, RootSpanBuilder};
+use uuid::Uuid;
+
+pub const REQUEST_ID_HEADER: HeaderName = HeaderName::from_static("x-request-id");
+
+/// Longest `X-Request-Id` accepted from clients, longer ones are replaced.
+const MAX_REQUEST_ID_LENGTH: usize = 128;
+
+tokio::task_local! {
+    static REQUEST_ID: RequestId;
+}
+
+/// Id correlating a request with its logs, error pages and outgoing emails.
+/// Taken from the `X-Request-Id` header if the client sent a sensible one, generated otherwise.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RequestId(String);
+
+impl RequestId {
+    /// Parses a client provided id: printable ASCII, at most 128 characters.
+    pub fn parse(s: &str) -> Option<Self> {
+        let is_valid = !s.is_empty()
+            && s.len() <= MAX_REQUEST_ID_LENGTH
+            && s.chars().all(|c| c.is_ascii_graphic());
+        is_valid.then(|| Self(s.to_owned()))
+    }
+
+    pub fn generate() -> Self {
+        Self(Uuid::new_v4().to_string())
+    }
+}
+
+impl AsRef<str> for RequestId {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl std::fmt::Display for RequestId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+/// Id of the request being handled by the current task, if any.
+pub fn current_request_id() -> Option<RequestId> {
+    REQUEST_ID.try_with(RequestId::clone).ok()
+}
+
+/// Runs `f` as if it was handling the request `request_id`, e.g. for work a
+/// request queued up.
+pub async fn with_request_id<F: Future>(request_id: Option<RequestId>, f: F) -> F::Output {
+    match request_id {
+        Some(request_id) => REQUEST_ID.scope(request_id, f).await,
+        None => f.await,
+    }
+}
+
+/// `TracingLogger` root span builder recording the request id as `x_request_id`.
+///
+/// `request_id` is taken by tracing-actix-web's own id, which can't be set from
+/// the header. Requests without a header reuse it, so that both match.
+pub struct RequestIdRootSpanBuilder;
+
+impl RootSpanBuilder for RequestIdRootSpanBuilder {
+    fn on_request_start(request: &ServiceRequest) -> Span {
+        let request_id = request
+            .headers()
+            .get(REQUEST_ID_HEADER)
+            .and_then(|h| h.to_str().ok())
+            .and_then(RequestId::parse)
+            .or_else(|| {
+                let extensions = request.extensions();
+                let generated = extensions.get::<tracing_actix_web::RequestId>()?;
+                Some(RequestId(generated.to_string()))
+            })
+            .unwrap_or_else(RequestId::generate);
+        request.extensions_mut().insert(request_id.clone());
+        root_span!(level = Level::INFO, request, x_request_id = %request_id)
+    }
+
+    fn on_request_end<B: MessageBody>(
+        span: Span,
+        outcome: &Result<ServiceResponse<B>, actix_web::Error>,
+    ) {
+        DefaultRootSpanBuilder::on_request_end(span, outcome);
+    }
+}
+
+/// Middleware making the request id available to the handlers and echoing it
+/// back in the `X-Request-Id` response header.
+/// Errors returned by inner middlewares skip the header, their `e500` pages still show the id.
+/// Must be wrapped by a `TracingLogger<RequestIdRootSpanBuilder>`.
+pub async fn propagate_request_id(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let request_id = req
+        .extensions()
+        .get::<RequestId>()
+        .cloned()
+        .unwrap_or_else(RequestId::generate);
+    let mut response = REQUEST_ID.scope(request_id.clone(), next.call(req)).await?;
+    if let Ok(value) = HeaderValue::from_str(request_id.as_ref()) {
+        response.headers_mut().insert(REQUEST_ID_HEADER, value);
+    }
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RequestId;
+    use claims::{assert_none, assert_some};
+
+    #[test]
+    fn printable_ascii_ids_are_accepted() {
+        assert_some!(RequestId::parse("req-1234_abcd.EF"));
+    }
+
+    #[test]
+    fn empty_or_overlong_ids_are_rejected() {
+        assert_none!(RequestId::parse(""));
+        assert_none!(RequestId::parse(&"a".repeat(129)));
+    }
+
+    #[test]
+    fn ids_with_whitespace_or_non_ascii_characters_are_rejected() {
+        assert_none!(RequestId::parse("a b"));
+        assert_none!(RequestId::parse("a\nb"));
+        assert_none!(RequestId::parse("réq"));
+    }
+}
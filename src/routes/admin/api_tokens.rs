@@ -0,0 +1,218 @@
+//! /admin/api_tokens: lists the API tokens of the current user, creates and revokes them.
+use actix_web::http::header::{HeaderValue, CACHE_CONTROL};
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpRequest, HttpResponse};
+use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages};
+use askama::Template;
+use chrono::Utc;
+use secrecy::{ExposeSecret, Secret};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::audit::{record_audit_event, AuditAction};
+use crate::authentication::{
+    create_api_token, list_api_tokens, needs_reauthentication, revoke_api_token, ApiScope, UserId,
+};
+use crate::configuration::SessionSettings;
+use crate::routes::admin::reauthenticate::ReturnTo;
+use crate::routes::page::{flash_message_contents, html_page};
+use crate::session_state::TypedSession;
+use crate::utils::{e500, see_other};
+
+/// Tokens can't be made to last longer, they must be rotated.
+const MAX_LIFETIME_DAYS: i64 = 365;
+const MAX_NAME_LENGTH: usize = 100;
+
+const DATE_FORMAT: &str = "%Y-%m-%d %H:%M UTC";
+
+/// A row of the API tokens table.
+struct TokenRow {
+    token_id: Uuid,
+    name: String,
+    scopes: String,
+    created_at: String,
+    expires_at: String,
+    last_used_at: String,
+    is_expired: bool,
+}
+
+#[derive(Template)]
+#[template(path = "admin/api_tokens.html")]
+struct ApiTokensTemplate {
+    tokens: Vec<TokenRow>,
+    scopes: Vec<&'static str>,
+    // The token just created, shown this once.
+    new_token: Option<String>,
+    flash_messages: Vec<String>,
+    csrf_token: String,
+}
+
+pub async fn admin_api_tokens(
+    session: TypedSession,
+    pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    render(
+        &session,
+        &pool,
+        **user_id,
+        None,
+        flash_message_contents(&flash_messages),
+    )
+    .await
+}
+
+/// Creates a token and shows it, rather than redirecting: it must not end up in a cookie.
+/// Tokens that can publish need a recent password, like large publishes do.
+#[tracing::instrument(name = "Create an admin API token", skip_all, fields(user_id=%&*user_id))]
+pub async fn create_admin_api_token(
+    // Repeated `scope` fields don't deserialize into a struct.
+    form: web::Form<Vec<(String, String)>>,
+    session: TypedSession,
+    pool: web::Data<PgPool>,
+    session_settings: web::Data<SessionSettings>,
+    user_id: web::ReqData<UserId>,
+    request: HttpRequest,
+) -> Result<HttpResponse, actix_web::Error> {
+    let (name, scopes, lifetime_days) = match parse_form(&form) {
+        Ok(parsed) => parsed,
+        Err(message) => {
+            FlashMessage::error(message).send();
+            return Ok(see_other("/admin/api_tokens"));
+        }
+    };
+    if scopes.contains(&ApiScope::NewslettersPublish)
+        && needs_reauthentication(&session, &session_settings, Utc::now()).map_err(e500)?
+    {
+        FlashMessage::error(format!(
+            "Tokens with the {} scope require your password. \
+            Confirm it, then create the token again.",
+            ApiScope::NewslettersPublish.as_str()
+        ))
+        .send();
+        return Ok(see_other(ReturnTo::ApiTokens.reauthenticate_path()));
+    }
+    let expires_at = Utc::now() + chrono::Duration::days(lifetime_days);
+    // The token and its audit row are committed together.
+    let mut transaction = pool.begin().await.map_err(e500)?;
+    let (token_id, token) =
+        create_api_token(&mut *transaction, **user_id, &name, &scopes, expires_at)
+            .await
+            .map_err(e500)?;
+    record_audit_event(
+        &mut *transaction,
+        &request,
+        Some(**user_id),
+        AuditAction::ApiTokenCreate,
+        Some(&token_id.to_string()),
+    )
+    .await
+    .map_err(e500)?;
+    transaction.commit().await.map_err(e500)?;
+    let mut response = render(&session, &pool, **user_id, Some(token), vec![]).await?;
+    response
+        .headers_mut()
+        .insert(CACHE_CONTROL, HeaderValue::from_static("no-store"));
+    Ok(response)
+}
+
+/// Revokes one of the user's tokens, requests bearing it get a 401 from now on.
+#[tracing::instrument(name = "Revoke an admin API token", skip(pool, user_id, request), fields(user_id=%&*user_id))]
+pub async fn revoke_admin_api_token(
+    path: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+    request: HttpRequest,
+) -> Result<HttpResponse, actix_web::Error> {
+    let token_id = path.into_inner();
+    // The revocation and its audit row are committed together.
+    let mut transaction = pool.begin().await.map_err(e500)?;
+    if revoke_api_token(&mut *transaction, **user_id, token_id)
+        .await
+        .map_err(e500)?
+    {
+        record_audit_event(
+            &mut *transaction,
+            &request,
+            Some(**user_id),
+            AuditAction::ApiTokenRevoke,
+            Some(&token_id.to_string()),
+        )
+        .await
+        .map_err(e500)?;
+        transaction.commit().await.map_err(e500)?;
+        FlashMessage::info("The API token has been revoked.").send();
+    }
+    Ok(see_other("/admin/api_tokens"))
+}
+
+/// Returns the name, scopes and lifetime in days of the token to create, or a message for
+/// the user.
+fn parse_form(fields: &[(String, String)]) -> Result<(String, Vec<ApiScope>, i64), String> {
+    let field = |name: &str| {
+        fields
+            .iter()
+            .find(|(field, _)| field == name)
+            .map(|(_, value)| value.trim())
+            .unwrap_or_default()
+    };
+    let name = field("name");
+    if name.is_empty() || name.chars().count() > MAX_NAME_LENGTH {
+        return Err(format!(
+            "The token needs a name, of at most {} characters.",
+            MAX_NAME_LENGTH
+        ));
+    }
+    let mut scopes = Vec::new();
+    for (_, value) in fields.iter().filter(|(field, _)| field == "scope") {
+        let scope = ApiScope::parse(value).ok_or_else(|| format!("Unknown scope {}.", value))?;
+        if !scopes.contains(&scope) {
+            scopes.push(scope);
+        }
+    }
+    if scopes.is_empty() {
+        return Err("Pick at least one scope for the token.".into());
+    }
+    let lifetime_days = field("expires_in_days")
+        .parse::<i64>()
+        .ok()
+        .filter(|days| (1..=MAX_LIFETIME_DAYS).contains(days))
+        .ok_or_else(|| format!("Tokens must expire within 1 to {} days.", MAX_LIFETIME_DAYS))?;
+    Ok((name.to_owned(), scopes, lifetime_days))
+}
+
+async fn render(
+    session: &TypedSession,
+    pool: &PgPool,
+    user_id: Uuid,
+    new_token: Option<Secret<String>>,
+    flash_messages: Vec<String>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let now = Utc::now();
+    let tokens = list_api_tokens(pool, user_id)
+        .await
+        .map_err(e500)?
+        .into_iter()
+        .map(|t| TokenRow {
+            token_id: t.token_id,
+            name: t.name,
+            scopes: t.scopes.join(", "),
+            created_at: t.created_at.format(DATE_FORMAT).to_string(),
+            expires_at: t.expires_at.format(DATE_FORMAT).to_string(),
+            last_used_at: t
+                .last_used_at
+                .map(|d| d.format(DATE_FORMAT).to_string())
+                .unwrap_or_else(|| "never".into()),
+            is_expired: t.expires_at <= now,
+        })
+        .collect();
+    let template = ApiTokensTemplate {
+        tokens,
+        scopes: ApiScope::ALL.iter().map(ApiScope::as_str).collect(),
+        new_token: new_token.map(|token| token.expose_secret().to_owned()),
+        flash_messages,
+        csrf_token: session.get_or_create_csrf_token().map_err(e500)?,
+    };
+    html_page(StatusCode::OK, &template)
+}
@@ -0,0 +1,185 @@
+//! /admin/audit: lists the audit log, filtered by action, actor and day, and exports it as CSV.
+use actix_web::http::header::{ContentDisposition, DispositionParam, DispositionType};
+use actix_web::http::StatusCode;
+use actix_web::web::Bytes;
+use actix_web::{web, HttpRequest, HttpResponse};
+use actix_web_flash_messages::IncomingFlashMessages;
+use askama::Template;
+use chrono::NaiveDate;
+use futures_util::stream::{self, StreamExt};
+use sqlx::PgPool;
+
+use crate::audit::{
+    audit_events_to_csv, list_audit_events, AuditAction, AuditLogFilter, AUDIT_CSV_HEADER,
+};
+use crate::routes::page::{flash_message_contents, html_page};
+use crate::session_state::TypedSession;
+use crate::utils::{e400, e500};
+
+/// Most events listed on the page, the CSV export has the rest.
+const PAGE_SIZE: i64 = 200;
+/// Events read from the database at a time while exporting.
+const EXPORT_BATCH_SIZE: i64 = 1_000;
+
+const DATE_FORMAT: &str = "%Y-%m-%d %H:%M:%S UTC";
+
+/// Query parameters of both the page and the export, as sent by the filter form: fields left
+/// blank come as empty strings.
+#[derive(serde::Deserialize)]
+pub struct AuditLogQuery {
+    action: Option<String>,
+    actor: Option<String>,
+    since: Option<String>,
+    until: Option<String>,
+}
+
+impl AuditLogQuery {
+    fn to_filter(&self) -> Result<AuditLogFilter, String> {
+        let action = match non_empty(&self.action) {
+            Some(action) => Some(
+                AuditAction::parse(action)
+                    .ok_or_else(|| format!("{} is not an audited action.", action))?,
+            ),
+            None => None,
+        };
+        Ok(AuditLogFilter {
+            action,
+            actor: non_empty(&self.actor).map(str::to_owned),
+            since: parse_day(&self.since)?,
+            until: parse_day(&self.until)?,
+        })
+    }
+}
+
+fn non_empty(parameter: &Option<String>) -> Option<&str> {
+    parameter
+        .as_deref()
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+}
+
+fn parse_day(parameter: &Option<String>) -> Result<Option<NaiveDate>, String> {
+    non_empty(parameter)
+        .map(|day| {
+            NaiveDate::parse_from_str(day, "%Y-%m-%d")
+                .map_err(|_| format!("{} is not a YYYY-MM-DD date.", day))
+        })
+        .transpose()
+}
+
+/// A row of the audit log table.
+struct EventRow {
+    occurred_at: String,
+    actor: String,
+    action: String,
+    target: String,
+    ip_address: String,
+    request_id: String,
+}
+
+/// An option of the action filter.
+struct ActionOption {
+    name: &'static str,
+    selected: bool,
+}
+
+#[derive(Template)]
+#[template(path = "admin/audit.html")]
+struct AuditTemplate {
+    events: Vec<EventRow>,
+    actions: Vec<ActionOption>,
+    actor: String,
+    since: String,
+    until: String,
+    // Query string of the export link, the filters of the page.
+    export_query: String,
+    is_truncated: bool,
+    flash_messages: Vec<String>,
+    csrf_token: String,
+}
+
+pub async fn admin_audit_log(
+    query: web::Query<AuditLogQuery>,
+    request: HttpRequest,
+    pool: web::Data<PgPool>,
+    session: TypedSession,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let filter = query.to_filter().map_err(e400)?;
+    let mut events = list_audit_events(&pool, &filter, None, PAGE_SIZE + 1)
+        .await
+        .map_err(e500)?;
+    let is_truncated = events.len() as i64 > PAGE_SIZE;
+    events.truncate(PAGE_SIZE as usize);
+    let events = events
+        .into_iter()
+        .map(|e| EventRow {
+            occurred_at: e.occurred_at.format(DATE_FORMAT).to_string(),
+            actor: e.actor.unwrap_or_default(),
+            action: e.action,
+            target: e.target.unwrap_or_default(),
+            ip_address: e.ip_address.unwrap_or_else(|| "unknown".into()),
+            request_id: e.request_id.unwrap_or_default(),
+        })
+        .collect();
+    let actions = AuditAction::ALL
+        .into_iter()
+        .map(|action| ActionOption {
+            name: action.as_str(),
+            selected: Some(action) == filter.action,
+        })
+        .collect();
+    let template = AuditTemplate {
+        events,
+        actions,
+        actor: filter.actor.unwrap_or_default(),
+        since: filter.since.map(|d| d.to_string()).unwrap_or_default(),
+        until: filter.until.map(|d| d.to_string()).unwrap_or_default(),
+        export_query: request.query_string().to_owned(),
+        is_truncated,
+        flash_messages: flash_message_contents(&flash_messages),
+        csrf_token: session.get_or_create_csrf_token().map_err(e500)?,
+    };
+    html_page(StatusCode::OK, &template)
+}
+
+/// The events of the page, and the older ones it leaves out, as a CSV download.
+pub async fn export_audit_log(
+    query: web::Query<AuditLogQuery>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let filter = query.to_filter().map_err(e400)?;
+    // Streamed a batch at a time, however many events there are. The header is sent by
+    // then, a failing batch can only cut the download short.
+    let pool = pool.into_inner();
+    let batches = stream::try_unfold(Some(None), move |before| {
+        let (pool, filter) = (pool.clone(), filter.clone());
+        async move {
+            let Some(before) = before else {
+                return Ok(None);
+            };
+            let events = list_audit_events(&pool, &filter, before, EXPORT_BATCH_SIZE)
+                .await
+                .inspect_err(|e| {
+                    tracing::error!(error.cause_chain = ?e, "The audit log export was cut short")
+                })?;
+            // A short batch is the last one.
+            let next = match events.last() {
+                Some(last) if events.len() as i64 == EXPORT_BATCH_SIZE => {
+                    Some(Some((last.occurred_at, last.id)))
+                }
+                _ => None,
+            };
+            Ok::<_, anyhow::Error>(Some((Bytes::from(audit_events_to_csv(&events)), next)))
+        }
+    });
+    let csv =
+        stream::once(async { Ok(Bytes::from_static(AUDIT_CSV_HEADER.as_bytes())) }).chain(batches);
+    Ok(HttpResponse::Ok()
+        .content_type("text/csv; charset=utf-8")
+        .insert_header(ContentDisposition {
+            disposition: DispositionType::Attachment,
+            parameters: vec![DispositionParam::Filename("audit-log.csv".into())],
+        })
+        .streaming(csv))
+}
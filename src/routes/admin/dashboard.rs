@@ -1,16 +1,31 @@
 use actix_web::{
-    http::header::{ContentType, LOCATION},
+    http::{header::LOCATION, StatusCode},
     web, HttpResponse,
 };
+use actix_web_flash_messages::IncomingFlashMessages;
 use anyhow::Context;
+use askama::Template;
 use sqlx::PgPool;
 use uuid::Uuid;
 
-use crate::{session_state::TypedSession, utils::e500};
+use crate::{
+    routes::page::{flash_message_contents, html_page},
+    session_state::TypedSession,
+    utils::e500,
+};
+
+#[derive(Template)]
+#[template(path = "admin/dashboard.html")]
+struct DashboardTemplate {
+    username: String,
+    flash_messages: Vec<String>,
+    csrf_token: String,
+}
 
 pub async fn admin_dashboard(
     session: TypedSession,
     pool: web::Data<PgPool>,
+    flash_messages: IncomingFlashMessages,
 ) -> Result<HttpResponse, actix_web::Error> {
     let username = if let Some(user_id) = session.get_user_id().map_err(e500)? {
         get_username(user_id, &pool).await.map_err(e500)?
@@ -19,30 +34,12 @@ pub async fn admin_dashboard(
             .insert_header((LOCATION, "/login"))
             .finish());
     };
-    Ok(HttpResponse::Ok()
-        .content_type(ContentType::html())
-        .body(format!(
-            r#"<!DOCTYPE html>
-<html lang="en">
-<head>
-    <meta http-equiv="content-type" content="text/html; charset=utf-8">
-    <title>Admin dashboard</title>
-</head>
-<body>
-    <p>Welcome {username}!</p>
-    <p> Available Actions:</p>
-    <ol>
-        <li><a href="/admin/password">Change Password</a></li>
-        <li>
-            <form name="logoutForm" action="/admin/logout" method="post">
-                <input type="submit" value="Logout">
-            </form>
-        </li>
-        <li><a href="/admin/newsletter">Send a newsletter issue</a></li>
-    </ol>
-</body>
-</html>"#
-        )))
+    let template = DashboardTemplate {
+        username,
+        flash_messages: flash_message_contents(&flash_messages),
+        csrf_token: session.get_or_create_csrf_token().map_err(e500)?,
+    };
+    html_page(StatusCode::OK, &template)
 }
 
 #[tracing::instrument(name = "Get username", skip(pool))]
@@ -0,0 +1,61 @@
+//! /admin/log_filter: reads and changes the log filter at runtime,
+//! e.g. to turn on `sqlx=debug` during an incident.
+use actix_web::{web, HttpRequest, HttpResponse};
+use sqlx::PgPool;
+
+use crate::audit::{record_audit_event, AuditAction};
+use crate::authentication::UserId;
+use crate::telemetry::{log_filter, LogFilterError, LogFilterHandle};
+use crate::utils::{e400, e500};
+
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct LogFilter {
+    /// `EnvFilter` directives, e.g. "info,sqlx=debug".
+    directives: String,
+}
+
+/// Returns the current log filter directives.
+pub async fn get_log_filter() -> Result<HttpResponse, actix_web::Error> {
+    let directives = handle()?.current().map_err(e500)?;
+    Ok(HttpResponse::Ok().json(LogFilter { directives }))
+}
+
+/// Replaces the log filter, returns a 400 if the directives don't parse.
+#[tracing::instrument(name = "Change the log filter", skip_all, fields(directives = %body.directives))]
+pub async fn set_log_filter(
+    body: web::Json<LogFilter>,
+    pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+    request: HttpRequest,
+) -> Result<HttpResponse, actix_web::Error> {
+    let handle = handle()?;
+    let previous_directives = handle.current().map_err(e500)?;
+    // The audit row is written first and committed once the filter is changed: a change
+    // that can't be audited is rolled back, and vice versa.
+    let mut transaction = pool.begin().await.map_err(e500)?;
+    record_audit_event(
+        &mut *transaction,
+        &request,
+        Some(**user_id),
+        AuditAction::LogFilterChange,
+        Some(&body.directives),
+    )
+    .await
+    .map_err(e500)?;
+    handle.reload(&body.directives).map_err(|e| match e {
+        LogFilterError::InvalidDirectives(_) => e400(e),
+        LogFilterError::UnexpectedError(_) => e500(e),
+    })?;
+    if let Err(e) = transaction.commit().await {
+        handle.reload(&previous_directives).map_err(e500)?;
+        return Err(e500(e));
+    }
+    tracing::warn!("Log filter changed.");
+    let directives = handle.current().map_err(e500)?;
+    Ok(HttpResponse::Ok().json(LogFilter { directives }))
+}
+
+fn handle() -> Result<&'static LogFilterHandle, actix_web::Error> {
+    log_filter()
+        .ok_or_else(|| e500("The log filter can't be changed, there's no global subscriber."))
+}
@@ -1,17 +1,33 @@
-use actix_web::HttpResponse;
+use actix_web::{web, HttpRequest, HttpResponse};
 use actix_web_flash_messages::FlashMessage;
+use sqlx::PgPool;
 
 use crate::{
+    audit::{record_audit_event, AuditAction},
+    authentication::revoke_session,
     session_state::TypedSession,
     utils::{e500, see_other},
 };
 
-pub async fn log_out(session: TypedSession) -> Result<HttpResponse, actix_web::Error> {
-    if session.get_user_id().map_err(e500)?.is_none() {
-        Ok(see_other("/login"))
-    } else {
-        session.log_out();
-        FlashMessage::info("You have successfully logged out.").send();
-        Ok(see_other("/login"))
+pub async fn log_out(
+    session: TypedSession,
+    pool: web::Data<PgPool>,
+    request: HttpRequest,
+) -> Result<HttpResponse, actix_web::Error> {
+    match session.get_user_id().map_err(e500)? {
+        None => Ok(see_other("/login")),
+        Some(user_id) => {
+            if let Some(session_id) = session.get_session_id().map_err(e500)? {
+                revoke_session(&pool, user_id, session_id)
+                    .await
+                    .map_err(e500)?;
+            }
+            record_audit_event(&**pool, &request, Some(user_id), AuditAction::LogOut, None)
+                .await
+                .map_err(e500)?;
+            session.log_out();
+            FlashMessage::info("You have successfully logged out.").send();
+            Ok(see_other("/login"))
+        }
     }
 }
@@ -1,9 +1,19 @@
+mod api_tokens;
+mod audit;
 mod dashboard;
+mod log_filter;
 mod logout;
 mod newsletter;
 mod password;
+mod reauthenticate;
+mod sessions;
 
+pub use api_tokens::{admin_api_tokens, create_admin_api_token, revoke_admin_api_token};
+pub use audit::{admin_audit_log, export_audit_log, AuditLogQuery};
 pub use dashboard::admin_dashboard;
+pub use log_filter::*;
 pub use logout::*;
 pub use newsletter::*;
 pub use password::*;
+pub use reauthenticate::{reauthenticate, reauthenticate_form};
+pub use sessions::*;
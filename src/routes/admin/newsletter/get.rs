@@ -1,61 +1,30 @@
 // Handler that returns a form to publish a new newsletter.
-use actix_web::http::header::ContentType;
+use actix_web::http::StatusCode;
 use actix_web::HttpResponse;
 use actix_web_flash_messages::IncomingFlashMessages;
-use std::fmt::Write;
+use askama::Template;
+use uuid::Uuid;
+
+use crate::routes::page::{flash_message_contents, html_page};
+use crate::session_state::TypedSession;
+use crate::utils::e500;
+
+#[derive(Template)]
+#[template(path = "admin/newsletter.html")]
+struct NewsletterFormTemplate {
+    flash_messages: Vec<String>,
+    idempotency_key: Uuid,
+    csrf_token: String,
+}
 
 pub async fn newsletter_form(
+    session: TypedSession,
     flash_messages: IncomingFlashMessages,
 ) -> Result<HttpResponse, actix_web::Error> {
-    let mut msg_html = String::new();
-    for m in flash_messages.iter() {
-        writeln!(msg_html, "<p><i>{}</i></p>", m.content()).unwrap();
-    }
-    let idempotency_key = uuid::Uuid::new_v4();
-
-    Ok(HttpResponse::Ok()
-        .content_type(ContentType::html())
-        .body(format!(
-            r#"<!DOCTYPE html>
-<html lang="en">
-<head>
-    <meta http-equiv="content-type" content="text/html; charset=utf-8">
-    <title>Publish Newsletter Issue</title>
-</head>
-<body>
-    {msg_html}
-    <form action="/admin/newsletter" method="post">
-        <label>Title:<br>
-            <input
-                type="text"
-                placeholder="Enter the issue title"
-                name="title"
-            >
-        </label>
-        <br>
-        <label>Plain text content:<br>
-            <textarea
-                placeholder="Enter the content in plain text"
-                name="text_content"
-                rows="20"
-                cols="50"
-            ></textarea>
-        </label>
-        <br>
-        <label>HTML content:<br>
-            <textarea
-                placeholder="Enter the content in HTML format"
-                name="html_content"
-                rows="20"
-                cols="50"
-            ></textarea>
-        </label>
-        <br>
-        <input hidden type="text" name="idempotency_key" value="{idempotency_key}">
-        <button type="submit">Publish</button>
-    </form>
-    <p><a href="/admin/dashboard">&lt;- Back</a></p>
-</body>
-</html>"#,
-        )))
+    let template = NewsletterFormTemplate {
+        flash_messages: flash_message_contents(&flash_messages),
+        idempotency_key: Uuid::new_v4(),
+        csrf_token: session.get_or_create_csrf_token().map_err(e500)?,
+    };
+    html_page(StatusCode::OK, &template)
 }
@@ -1,4 +1,9 @@
 mod get;
 pub use get::newsletter_form;
 mod post;
-pub use post::publish_newsletter;
+pub use post::{
+    newsletter_published, publish_newsletter, reject_large_publishes_without_reauthentication,
+    PublishError,
+};
+mod preview;
+pub use preview::preview_newsletter;
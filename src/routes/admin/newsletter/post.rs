@@ -1,17 +1,27 @@
 /// /newsletters handler
 ///
-///
-use crate::authentication::UserId;
-use crate::idempotency::{save_response, try_processing, IdempotencyKey, NextAction};
+/// Replay protection is handled by the `Idempotency` middleware wrapping the route, the
+/// issue is written in the transaction holding the idempotency key.
+use crate::audit::{record_audit_event, AuditAction};
+use crate::authentication::{needs_reauthentication, UserId};
+use crate::configuration::SessionSettings;
+use crate::domain::SubscriptionStatus;
+use crate::idempotency::{begin_transaction, commit_transaction};
+use crate::newsletter_issues::{insert_newsletter_issue, publish_issue_now};
 use crate::routes::error_chain_fmt;
-use crate::utils::{e400, e500, see_other};
-use actix_web::http::StatusCode;
+use crate::session_state::TypedSession;
+use crate::utils::{e500, see_other};
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::{Method, StatusCode};
 use actix_web::web;
-use actix_web::HttpResponse;
 use actix_web::ResponseError;
+use actix_web::{FromRequest, HttpRequest, HttpResponse};
 use actix_web_flash_messages::FlashMessage;
+use actix_web_lab::middleware::Next;
 use anyhow::Context;
-use sqlx::{Executor, PgPool, Postgres, Transaction};
+use chrono::Utc;
+use sqlx::{PgPool, Postgres, Transaction};
 use uuid::Uuid;
 
 #[derive(serde::Deserialize)]
@@ -19,8 +29,6 @@ pub struct FormData {
     title: String,
     text_content: String,
     html_content: String,
-    // Used avoid replaying requests.
-    idempotency_key: String,
 }
 
 /// Registers task to email all confirmed users.
@@ -32,50 +40,131 @@ pub async fn publish_newsletter(
     form: web::Form<FormData>,
     pool: web::Data<PgPool>,
     user_id: web::ReqData<UserId>,
+    request: HttpRequest,
 ) -> Result<HttpResponse, actix_web::Error> {
-    let user_id = user_id.into_inner();
     // We must unpack the struct to avoid the upsetting borrow checker..
     let FormData {
         title,
         text_content,
         html_content,
-        idempotency_key,
     } = form.0;
-    let idempotency_key: IdempotencyKey = idempotency_key.try_into().map_err(e400)?;
-    let mut transaction = match try_processing(&pool, &idempotency_key, *user_id)
-        .await
-        .map_err(e500)?
-    {
-        NextAction::StartProcessing(t) => t,
-        NextAction::ReturnSavedResponse(r) => {
-            success_message().send();
-            return Ok(r);
-        }
-    };
-
-    let issue_id = insert_newsletter_issue(&mut transaction, &title, &text_content, &html_content)
-        .await
-        .context("Failed to store newsletter issue details")
-        .map_err(e500)?;
-
-    enqueue_delivery_tasks(&mut transaction, issue_id)
+    let mut transaction = begin_transaction(&request, &pool)
         .await
-        .context("Failed to enquee delivery tasks")
+        .context("Failed to start a transaction")
         .map_err(e500)?;
-
-    let response = see_other("/admin/newsletter");
-    let response = save_response(transaction, &idempotency_key, *user_id, response)
+    publish_issue(
+        &mut transaction,
+        &request,
+        **user_id,
+        &title,
+        &text_content,
+        &html_content,
+    )
+    .await
+    .map_err(e500)?;
+    commit_transaction(&request, transaction)
         .await
+        .context("Failed to commit the newsletter issue")
         .map_err(e500)?;
-    success_message().send();
-    Ok(response)
+    // The flash message is sent by the redirect target: a replayed response can't carry it.
+    Ok(see_other("/admin/newsletter/published"))
 }
 
-fn success_message() -> FlashMessage {
+/// Where publishing redirects to, tells the user it went through.
+pub async fn newsletter_published() -> HttpResponse {
     FlashMessage::info(
         "The newsletter issue has been accepted - \
         emails will go out shortly.",
     )
+    .send();
+    see_other("/admin/newsletter")
+}
+
+/// Stores the issue and queues its delivery to all confirmed subscribers, recording
+/// who published it. Returns the issue's id.
+async fn publish_issue(
+    transaction: &mut Transaction<'_, Postgres>,
+    request: &HttpRequest,
+    user_id: Uuid,
+    title: &str,
+    text_content: &str,
+    html_content: &str,
+) -> Result<Uuid, anyhow::Error> {
+    let issue_id = insert_newsletter_issue(transaction, title, text_content, html_content)
+        .await
+        .context("Failed to store newsletter issue details")?;
+
+    publish_issue_now(transaction, issue_id)
+        .await
+        .context("Failed to publish the newsletter issue")?;
+
+    record_audit_event(
+        &mut **transaction,
+        request,
+        Some(user_id),
+        AuditAction::NewsletterPublish,
+        Some(&issue_id.to_string()),
+    )
+    .await?;
+    Ok(issue_id)
+}
+
+/// Middleware sending users to /admin/reauthenticate before they publish to more confirmed
+/// subscribers than the configured threshold, unless they typed their password recently.
+///
+/// It wraps the `Idempotency` middleware so that the redirect isn't saved as the response
+/// to the idempotency key.
+pub async fn reject_large_publishes_without_reauthentication(
+    mut req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    if req.method() != Method::POST {
+        return next
+            .call(req)
+            .await
+            .map(ServiceResponse::map_into_left_body);
+    }
+    let (Some(pool), Some(settings)) = (
+        req.app_data::<web::Data<PgPool>>().cloned(),
+        req.app_data::<web::Data<SessionSettings>>().cloned(),
+    ) else {
+        let e = anyhow::anyhow!("The reauthentication middleware is missing its app data.");
+        return Err(e500(e));
+    };
+    let session = {
+        let (http_request, payload) = req.parts_mut();
+        TypedSession::from_request(http_request, payload).await
+    }?;
+
+    if needs_reauthentication(&session, &settings, Utc::now()).map_err(e500)? {
+        let n_subscribers = count_confirmed_subscribers(&pool).await.map_err(e500)?;
+        if n_subscribers > settings.reauthentication_subscriber_threshold {
+            FlashMessage::error(format!(
+                "Publishing to more than {} subscribers requires your password. \
+                Confirm it, then publish the issue again.",
+                settings.reauthentication_subscriber_threshold
+            ))
+            .send();
+            // Not an error, errors skip the outer middlewares and with them the flash message.
+            let response = req.into_response(see_other("/admin/reauthenticate"));
+            return Ok(response.map_into_right_body());
+        }
+    }
+    next.call(req)
+        .await
+        .map(ServiceResponse::map_into_left_body)
+}
+
+#[tracing::instrument(skip_all)]
+async fn count_confirmed_subscribers(pool: &PgPool) -> Result<i64, anyhow::Error> {
+    let n_subscribers = sqlx::query_scalar!(
+        r#"SELECT COUNT(*) AS "count!" FROM subscriptions WHERE status = $1"#,
+        SubscriptionStatus::Confirmed as SubscriptionStatus,
+    )
+    .fetch_one(pool)
+    .await
+    .context("Failed to count the confirmed subscribers.")?;
+    Ok(n_subscribers)
 }
 
 #[derive(thiserror::Error)]
@@ -99,52 +188,3 @@ impl ResponseError for PublishError {
         }
     }
 }
-
-#[tracing::instrument(skip_all)]
-async fn insert_newsletter_issue(
-    transaction: &mut Transaction<'_, Postgres>,
-    title: &str,
-    text_content: &str,
-    html_content: &str,
-) -> Result<Uuid, sqlx::Error> {
-    let newsletter_issue_id = Uuid::new_v4();
-    let query = sqlx::query!(
-        r#"
-        INSERT INTO newsletter_issues (
-            newsletter_issue_id, 
-            title, 
-            text_content, 
-            html_content,
-            published_at
-        )
-        VALUES ($1, $2, $3, $4, now())
-        "#,
-        newsletter_issue_id,
-        title,
-        text_content,
-        html_content
-    );
-    transaction.execute(query).await?;
-    Ok(newsletter_issue_id)
-}
-
-#[tracing::instrument(skip_all)]
-async fn enqueue_delivery_tasks(
-    transaction: &mut Transaction<'_, Postgres>,
-    newsletter_issue_id: Uuid,
-) -> Result<(), sqlx::Error> {
-    let query = sqlx::query!(
-        r#"
-        INSERT INTO issue_delivery_queue (
-            newsletter_issue_id, 
-            subscriber_email
-        )
-        SELECT $1, email
-        FROM subscriptions
-        WHERE status = 'confirmed'
-        "#,
-        newsletter_issue_id,
-    );
-    transaction.execute(query).await?;
-    Ok(())
-}
@@ -0,0 +1,36 @@
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpResponse};
+use askama::Template;
+
+use crate::routes::page::html_page;
+
+#[derive(serde::Deserialize)]
+pub struct FormData {
+    title: String,
+    html_content: String,
+}
+
+#[derive(Template)]
+#[template(path = "admin/newsletter_preview.html")]
+struct NewsletterPreviewTemplate {
+    title: String,
+    html_content: String,
+}
+
+/// Renders the HTML content of an issue from the publish form, without publishing it.
+///
+/// The issue's HTML is shown as is, `SecurityHeaders` gives this page its own
+/// Content-Security-Policy.
+pub async fn preview_newsletter(
+    form: web::Form<FormData>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let FormData {
+        title,
+        html_content,
+    } = form.0;
+    let template = NewsletterPreviewTemplate {
+        title,
+        html_content,
+    };
+    html_page(StatusCode::OK, &template)
+}
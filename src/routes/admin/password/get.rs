@@ -1,10 +1,18 @@
-use actix_web::http::header::ContentType;
+use actix_web::http::StatusCode;
 use actix_web::HttpResponse;
 use actix_web_flash_messages::IncomingFlashMessages;
+use askama::Template;
 
+use crate::routes::page::{flash_message_contents, html_page};
 use crate::session_state::TypedSession;
 use crate::utils::{e500, see_other};
-use std::fmt::Write;
+
+#[derive(Template)]
+#[template(path = "admin/password.html")]
+struct ChangePasswordTemplate {
+    flash_messages: Vec<String>,
+    csrf_token: String,
+}
 
 pub async fn change_password_form(
     session: TypedSession,
@@ -14,50 +22,9 @@ pub async fn change_password_form(
         return Ok(see_other("/login"));
     }
 
-    let mut msg_html = String::new();
-    for m in flash_messages.iter() {
-        writeln!(msg_html, "<p><i>{}</i></p>", m.content()).unwrap();
-    }
-    Ok(HttpResponse::Ok()
-        .content_type(ContentType::html())
-        .body(format!(
-            r#"<!DOCTYPE html>
-<html lang="en">
-<head>
-    <meta http-equiv="content-type" content="text/html; charset=utf-8">
-    <title>Change Password</title>
-</head>
-<body>
- {msg_html}
-<form action="/admin/password" method="post">
-        <label>Current password
-            <input
-                type="password"
-                placeholder="Enter current password"
-                name="current_password"
-            >
-        </label>
-        <br>
-        <label>New password
-            <input
-                type="password"
-                placeholder="Enter new password"
-                name="new_password"
-            >
-        </label>
-        <br>
-        <label>Confirm new password
-            <input
-                type="password"
-                placeholder="Type the new password again"
-                name="new_password_check"
-            >
-        </label>
-        <br>
-        <button type="submit">Change password</button>
-    </form>
-    <p><a href="/admin/dashboard">&lt;- Back</a></p>
-</body>
-</html>"#,
-        )))
+    let template = ChangePasswordTemplate {
+        flash_messages: flash_message_contents(&flash_messages),
+        csrf_token: session.get_or_create_csrf_token().map_err(e500)?,
+    };
+    html_page(StatusCode::OK, &template)
 }
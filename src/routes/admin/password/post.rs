@@ -1,12 +1,18 @@
 //! src/routes/admin/password/post.rs
-use actix_web::{web, HttpResponse};
+use actix_web::{web, HttpRequest, HttpResponse};
 use actix_web_flash_messages::FlashMessage;
 use secrecy::{ExposeSecret, Secret};
 use sqlx::PgPool;
 
 use crate::{
-    authentication::{validate_credentials, AuthError, Credentials, UserId},
+    audit::{record_audit_event, AuditAction},
+    authentication::{
+        is_recent_password, revoke_all_api_tokens, revoke_other_sessions, validate_credentials,
+        AuthError, Credentials, PasswordPolicy, UserId,
+    },
+    configuration::PasswordHashingSettings,
     routes::admin::dashboard::get_username,
+    session_state::TypedSession,
     utils::{e500, see_other},
 };
 
@@ -20,7 +26,11 @@ pub struct FormData {
 pub async fn change_password(
     form: web::Form<FormData>,
     pool: web::Data<PgPool>,
+    password_hashing: web::Data<PasswordHashingSettings>,
+    password_policy: web::Data<PasswordPolicy>,
     user_id: web::ReqData<UserId>,
+    session: TypedSession,
+    request: HttpRequest,
 ) -> Result<HttpResponse, actix_web::Error> {
     let user_id = user_id.into_inner();
 
@@ -32,22 +42,65 @@ pub async fn change_password(
     // Check if current password is valid
     let username = get_username(*user_id, &pool).await.map_err(e500)?;
     let credentials = Credentials {
-        username,
+        username: username.clone(),
         password: form.0.current_password,
     };
 
-    if let Err(e) = validate_credentials(credentials, &pool).await {
+    if let Err(e) = validate_credentials(credentials, &pool, &password_hashing).await {
         return match e {
             AuthError::InvalidCredentials(_) => {
                 FlashMessage::error("The current password is incorrect.").send();
                 Ok(see_other("/admin/password"))
             }
-            AuthError::UnexpectedError(_) => Err(e500(e).into()),
+            AuthError::UnexpectedError(_) => Err(e500(e)),
         };
     }
-    crate::authentication::change_password(*user_id, form.0.new_password, &pool)
+    if let Err(message) = password_policy.check(&form.0.new_password, &username) {
+        FlashMessage::error(message).send();
+        return Ok(see_other("/admin/password"));
+    }
+    let history_size = password_policy.history_size();
+    if is_recent_password(*user_id, &form.0.new_password, &pool, history_size)
+        .await
+        .map_err(e500)?
+    {
+        FlashMessage::error(format!(
+            "The new password must differ from your last {} passwords.",
+            history_size
+        ))
+        .send();
+        return Ok(see_other("/admin/password"));
+    }
+    // The new password, the revocations and the audit row are committed together.
+    let mut transaction = pool.begin().await.map_err(e500)?;
+    crate::authentication::change_password(
+        *user_id,
+        form.0.new_password,
+        &mut transaction,
+        &password_hashing,
+        history_size,
+    )
+    .await
+    .map_err(e500)?;
+    // Whoever knew the old password may still be logged in elsewhere, or have made tokens.
+    if let Some(session_id) = session.get_session_id().map_err(e500)? {
+        revoke_other_sessions(&mut *transaction, *user_id, session_id)
+            .await
+            .map_err(e500)?;
+    }
+    revoke_all_api_tokens(&mut *transaction, *user_id)
         .await
         .map_err(e500)?;
+    record_audit_event(
+        &mut *transaction,
+        &request,
+        Some(*user_id),
+        AuditAction::PasswordChange,
+        None,
+    )
+    .await
+    .map_err(e500)?;
+    transaction.commit().await.map_err(e500)?;
     FlashMessage::error("Your password has been changed.").send();
     Ok(see_other("/admin/password"))
 }
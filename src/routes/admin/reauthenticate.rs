@@ -0,0 +1,118 @@
+//! /admin/reauthenticate: asks for the password again before sensitive actions.
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages};
+use askama::Template;
+use chrono::Utc;
+use secrecy::Secret;
+use sqlx::PgPool;
+
+use crate::authentication::{validate_credentials, AuthError, Credentials, UserId};
+use crate::configuration::PasswordHashingSettings;
+use crate::routes::admin::dashboard::get_username;
+use crate::routes::page::{flash_message_contents, html_page};
+use crate::session_state::TypedSession;
+use crate::utils::{e500, see_other};
+
+/// The sensitive action the user is sent back to once their password is confirmed.
+#[derive(serde::Deserialize, Clone, Copy, Debug, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ReturnTo {
+    #[default]
+    Newsletter,
+    ApiTokens,
+}
+
+impl ReturnTo {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ReturnTo::Newsletter => "newsletter",
+            ReturnTo::ApiTokens => "api_tokens",
+        }
+    }
+
+    /// Where to ask for the password before going back to this action.
+    pub fn reauthenticate_path(&self) -> &'static str {
+        match self {
+            ReturnTo::Newsletter => "/admin/reauthenticate",
+            ReturnTo::ApiTokens => "/admin/reauthenticate?return_to=api_tokens",
+        }
+    }
+
+    fn path(&self) -> &'static str {
+        match self {
+            ReturnTo::Newsletter => "/admin/newsletter",
+            ReturnTo::ApiTokens => "/admin/api_tokens",
+        }
+    }
+
+    fn confirmation(&self) -> &'static str {
+        match self {
+            ReturnTo::Newsletter => "Your password has been confirmed, you can publish the issue.",
+            ReturnTo::ApiTokens => "Your password has been confirmed, you can create the token.",
+        }
+    }
+}
+
+#[derive(Template)]
+#[template(path = "admin/reauthenticate.html")]
+struct ReauthenticateTemplate {
+    return_to: &'static str,
+    flash_messages: Vec<String>,
+    csrf_token: String,
+}
+
+#[derive(serde::Deserialize)]
+pub struct QueryParams {
+    #[serde(default)]
+    return_to: ReturnTo,
+}
+
+pub async fn reauthenticate_form(
+    query: web::Query<QueryParams>,
+    session: TypedSession,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let template = ReauthenticateTemplate {
+        return_to: query.return_to.as_str(),
+        flash_messages: flash_message_contents(&flash_messages),
+        csrf_token: session.get_or_create_csrf_token().map_err(e500)?,
+    };
+    html_page(StatusCode::OK, &template)
+}
+
+#[derive(serde::Deserialize)]
+pub struct FormData {
+    password: Secret<String>,
+    #[serde(default)]
+    return_to: ReturnTo,
+}
+
+/// Checks the password of the logged in user, sensitive actions are allowed for a while after.
+#[tracing::instrument(name = "Reauthenticate", skip_all, fields(user_id=%&*user_id))]
+pub async fn reauthenticate(
+    form: web::Form<FormData>,
+    pool: web::Data<PgPool>,
+    password_hashing: web::Data<PasswordHashingSettings>,
+    user_id: web::ReqData<UserId>,
+    session: TypedSession,
+) -> Result<HttpResponse, actix_web::Error> {
+    let return_to = form.return_to;
+    let username = get_username(**user_id, &pool).await.map_err(e500)?;
+    let credentials = Credentials {
+        username,
+        password: form.0.password,
+    };
+    if let Err(e) = validate_credentials(credentials, &pool, &password_hashing).await {
+        return match e {
+            AuthError::InvalidCredentials(_) => {
+                FlashMessage::error("The password is incorrect.").send();
+                Ok(see_other(return_to.reauthenticate_path()))
+            }
+            AuthError::UnexpectedError(_) => Err(e500(e)),
+        };
+    }
+    session.insert_authenticated_at(Utc::now()).map_err(e500)?;
+    FlashMessage::info(return_to.confirmation()).send();
+    Ok(see_other(return_to.path()))
+}
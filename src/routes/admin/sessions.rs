@@ -0,0 +1,115 @@
+//! /admin/sessions: lists the logged in sessions of the current user and revokes them.
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpRequest, HttpResponse};
+use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages};
+use askama::Template;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::audit::{record_audit_event, AuditAction};
+use crate::authentication::{list_sessions, revoke_other_sessions, revoke_session, UserId};
+use crate::configuration::SessionSettings;
+use crate::routes::page::{flash_message_contents, html_page};
+use crate::session_state::TypedSession;
+use crate::utils::{e500, see_other};
+
+/// A row of the sessions table.
+struct SessionRow {
+    session_id: Uuid,
+    created_at: String,
+    last_seen_at: String,
+    ip_address: String,
+    user_agent: String,
+    is_current: bool,
+}
+
+#[derive(Template)]
+#[template(path = "admin/sessions.html")]
+struct SessionsTemplate {
+    sessions: Vec<SessionRow>,
+    flash_messages: Vec<String>,
+    csrf_token: String,
+}
+
+const DATE_FORMAT: &str = "%Y-%m-%d %H:%M UTC";
+
+pub async fn admin_sessions(
+    session: TypedSession,
+    pool: web::Data<PgPool>,
+    session_settings: web::Data<SessionSettings>,
+    user_id: web::ReqData<UserId>,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let current_session_id = session.get_session_id().map_err(e500)?;
+    let sessions = list_sessions(&pool, **user_id, session_settings.state_ttl())
+        .await
+        .map_err(e500)?
+        .into_iter()
+        .map(|s| SessionRow {
+            session_id: s.session_id,
+            created_at: s.created_at.format(DATE_FORMAT).to_string(),
+            last_seen_at: s.last_seen_at.format(DATE_FORMAT).to_string(),
+            ip_address: s.ip_address.unwrap_or_else(|| "unknown".into()),
+            user_agent: s.user_agent.unwrap_or_else(|| "unknown".into()),
+            is_current: Some(s.session_id) == current_session_id,
+        })
+        .collect();
+    let template = SessionsTemplate {
+        sessions,
+        flash_messages: flash_message_contents(&flash_messages),
+        csrf_token: session.get_or_create_csrf_token().map_err(e500)?,
+    };
+    html_page(StatusCode::OK, &template)
+}
+
+/// Logs out one of the user's sessions.
+#[tracing::instrument(name = "Revoke an admin session", skip(pool, user_id, request), fields(user_id=%&*user_id))]
+pub async fn revoke_admin_session(
+    path: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+    request: HttpRequest,
+) -> Result<HttpResponse, actix_web::Error> {
+    let session_id = path.into_inner();
+    revoke_session(&pool, **user_id, session_id)
+        .await
+        .map_err(e500)?;
+    record_audit_event(
+        &**pool,
+        &request,
+        Some(**user_id),
+        AuditAction::SessionRevoke,
+        Some(&session_id.to_string()),
+    )
+    .await
+    .map_err(e500)?;
+    FlashMessage::info("The session has been logged out.").send();
+    Ok(see_other("/admin/sessions"))
+}
+
+/// Logs out all of the user's sessions but the current one.
+#[tracing::instrument(name = "Revoke other admin sessions", skip_all, fields(user_id=%&*user_id))]
+pub async fn revoke_other_admin_sessions(
+    session: TypedSession,
+    pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+    request: HttpRequest,
+) -> Result<HttpResponse, actix_web::Error> {
+    let Some(current_session_id) = session.get_session_id().map_err(e500)? else {
+        return Err(e500("The session has no id."));
+    };
+    revoke_other_sessions(&**pool, **user_id, current_session_id)
+        .await
+        .map_err(e500)?;
+    record_audit_event(
+        &**pool,
+        &request,
+        Some(**user_id),
+        AuditAction::OtherSessionsRevoke,
+        None,
+    )
+    .await
+    .map_err(e500)?;
+    FlashMessage::info("You have been logged out everywhere else.").send();
+    Ok(see_other("/admin/sessions"))
+}
@@ -0,0 +1,122 @@
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpResponse, ResponseError};
+
+use crate::idempotency::{Idempotency, IdempotencyError};
+use crate::newsletter_issues::IssueError;
+use crate::request_id::current_request_id;
+use crate::routes::error_chain_fmt;
+
+/// Errors of the /api routes, rendered as
+/// `{"error": {"code": "...", "message": "...", "request_id": "..."}}`.
+#[derive(thiserror::Error)]
+pub enum ApiError {
+    /// The request doesn't parse, e.g. malformed JSON or an unknown query parameter value.
+    #[error("{0}")]
+    InvalidRequest(String),
+    /// The request parses but its values don't make sense, e.g. an empty title.
+    #[error("{0}")]
+    ValidationFailed(String),
+    #[error("There is no newsletter issue with this id.")]
+    NotFound,
+    /// The resource isn't in a state that allows it, e.g. publishing an issue twice.
+    #[error("{0}")]
+    Conflict(String),
+    /// The `Idempotency-Key` is missing or malformed, in use by another request, or was
+    /// used for a different one. Never an `IdempotencyError::UnexpectedError`.
+    #[error(transparent)]
+    Idempotency(IdempotencyError),
+    #[error("Something went wrong.")]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl ApiError {
+    fn code(&self) -> &'static str {
+        match self {
+            ApiError::InvalidRequest(_) => "invalid_request",
+            ApiError::ValidationFailed(_) => "validation_failed",
+            ApiError::NotFound => "not_found",
+            ApiError::Conflict(_) => "conflict",
+            ApiError::Idempotency(e) => match e {
+                IdempotencyError::InvalidKey(_) => "invalid_idempotency_key",
+                IdempotencyError::InProgress { .. } => "idempotency_key_in_use",
+                IdempotencyError::PayloadMismatch => "idempotency_key_reused",
+                IdempotencyError::UnexpectedError(_) => "internal_error",
+            },
+            ApiError::UnexpectedError(_) => "internal_error",
+        }
+    }
+}
+
+impl From<IdempotencyError> for ApiError {
+    fn from(e: IdempotencyError) -> Self {
+        match e {
+            IdempotencyError::UnexpectedError(e) => ApiError::UnexpectedError(e),
+            e => ApiError::Idempotency(e),
+        }
+    }
+}
+
+impl From<IssueError> for ApiError {
+    fn from(e: IssueError) -> Self {
+        match e {
+            IssueError::NotFound => ApiError::NotFound,
+            IssueError::InvalidTransition { .. } => ApiError::Conflict(e.to_string()),
+            IssueError::UnexpectedError(e) => ApiError::UnexpectedError(e),
+        }
+    }
+}
+
+impl std::fmt::Debug for ApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for ApiError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ApiError::InvalidRequest(_) => StatusCode::BAD_REQUEST,
+            ApiError::ValidationFailed(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            ApiError::NotFound => StatusCode::NOT_FOUND,
+            ApiError::Conflict(_) => StatusCode::CONFLICT,
+            ApiError::Idempotency(e) => e.status_code(),
+            ApiError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let mut response = HttpResponse::build(self.status_code());
+        if let ApiError::Idempotency(e) = self {
+            if let Some(retry_after) = e.retry_after() {
+                response.insert_header(retry_after);
+            }
+        }
+        response.json(serde_json::json!({
+            "error": {
+                "code": self.code(),
+                "message": self.to_string(),
+                "request_id": current_request_id().map(|id| id.to_string()),
+            }
+        }))
+    }
+}
+
+/// `Idempotency` rendering its errors like the other /api errors.
+pub fn api_idempotency() -> Idempotency {
+    Idempotency::new().error_handler(|e| ApiError::from(e).into())
+}
+
+/// Turns malformed JSON bodies into `invalid_request` errors.
+pub fn api_json_config() -> web::JsonConfig {
+    web::JsonConfig::default().error_handler(|e, _| ApiError::InvalidRequest(e.to_string()).into())
+}
+
+/// Turns malformed ids in paths into `not_found` errors.
+pub fn api_path_config() -> web::PathConfig {
+    web::PathConfig::default().error_handler(|_, _| ApiError::NotFound.into())
+}
+
+/// Turns malformed query strings into `invalid_request` errors.
+pub fn api_query_config() -> web::QueryConfig {
+    web::QueryConfig::default().error_handler(|e, _| ApiError::InvalidRequest(e.to_string()).into())
+}
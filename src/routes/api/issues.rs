@@ -0,0 +1,322 @@
+//! /api/v1/issues: drafts, edits, schedules and publishes newsletter issues.
+//!
+//! Writes go through the transaction holding the idempotency key, see
+//! `begin_transaction`, so that they're committed along with the saved response.
+use actix_web::http::header::LOCATION;
+use actix_web::{web, HttpRequest, HttpResponse};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::{PgExecutor, PgPool};
+use uuid::Uuid;
+
+use super::ApiError;
+use crate::audit::{record_audit_event, AuditAction};
+use crate::authentication::{ApiScope, ApiToken, UserId};
+use crate::domain::IssueStatus;
+use crate::idempotency::{begin_transaction, commit_transaction};
+use crate::newsletter_issues::{
+    get_issue, insert_newsletter_issue, list_issues, publish_issue_now, schedule_issue,
+    update_issue, IssueRecord,
+};
+
+const DEFAULT_PAGE_SIZE: i64 = 20;
+const MAX_PAGE_SIZE: i64 = 100;
+
+#[derive(serde::Deserialize)]
+pub struct NewIssue {
+    title: String,
+    text_content: String,
+    html_content: String,
+}
+
+/// Fields left out are left unchanged.
+#[derive(serde::Deserialize)]
+pub struct IssueChanges {
+    title: Option<String>,
+    text_content: Option<String>,
+    html_content: Option<String>,
+}
+
+#[derive(serde::Deserialize)]
+pub struct IssueSchedule {
+    /// RFC 3339, e.g. "2026-11-01T09:00:00Z".
+    publish_at: String,
+}
+
+#[derive(serde::Deserialize)]
+pub struct ListParameters {
+    status: Option<String>,
+    limit: Option<i64>,
+    offset: Option<i64>,
+}
+
+/// An issue as listed, without its content.
+#[derive(serde::Serialize)]
+struct IssueSummary {
+    id: Uuid,
+    title: String,
+    status: &'static str,
+    created_at: String,
+    updated_at: String,
+    scheduled_for: Option<String>,
+    published_at: Option<String>,
+    delivery: Delivery,
+}
+
+#[derive(serde::Serialize)]
+struct Delivery {
+    // Confirmed subscribers when the issue was published.
+    recipients: Option<i64>,
+    // Emails not sent yet.
+    pending: i64,
+}
+
+#[derive(serde::Serialize)]
+struct IssueDetails {
+    #[serde(flatten)]
+    summary: IssueSummary,
+    text_content: String,
+    html_content: String,
+}
+
+impl From<IssueRecord> for IssueDetails {
+    fn from(issue: IssueRecord) -> Self {
+        let summary = IssueSummary {
+            id: issue.newsletter_issue_id,
+            title: issue.title,
+            status: issue.status.as_str(),
+            created_at: issue.created_at.to_rfc3339(),
+            updated_at: issue.updated_at.to_rfc3339(),
+            scheduled_for: issue.scheduled_for.map(|d| d.to_rfc3339()),
+            published_at: issue.published_at.map(|d| d.to_rfc3339()),
+            delivery: Delivery {
+                recipients: issue.recipient_count,
+                pending: issue.pending_deliveries,
+            },
+        };
+        Self {
+            summary,
+            text_content: issue.text_content,
+            html_content: issue.html_content,
+        }
+    }
+}
+
+/// Creates a draft, needs the `issues:write` scope.
+#[tracing::instrument(name = "Create a draft issue through the API", skip_all, fields(user_id=%&*user_id))]
+pub async fn api_create_issue(
+    body: web::Json<NewIssue>,
+    pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+    api_token: web::ReqData<ApiToken>,
+    request: HttpRequest,
+) -> Result<HttpResponse, actix_web::Error> {
+    api_token.require_scope(ApiScope::IssuesWrite)?;
+    validate_content("title", Some(&body.title))?;
+    validate_content("text_content", Some(&body.text_content))?;
+    validate_content("html_content", Some(&body.html_content))?;
+    let mut transaction = begin_transaction(&request, &pool)
+        .await
+        .context("Failed to start a transaction.")
+        .map_err(ApiError::from)?;
+    let issue_id = insert_newsletter_issue(
+        &mut transaction,
+        &body.title,
+        &body.text_content,
+        &body.html_content,
+    )
+    .await
+    .context("Failed to store the draft.")
+    .map_err(ApiError::from)?;
+    let issue = fetch_issue(&mut *transaction, issue_id).await?;
+    commit_transaction(&request, transaction)
+        .await
+        .context("Failed to commit the draft.")
+        .map_err(ApiError::from)?;
+    Ok(HttpResponse::Created()
+        .insert_header((LOCATION, format!("/api/v1/issues/{}", issue_id)))
+        .json(issue))
+}
+
+/// Returns an issue and the progress of its delivery, needs the `issues:read` scope.
+pub async fn api_get_issue(
+    path: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    api_token: web::ReqData<ApiToken>,
+) -> Result<HttpResponse, actix_web::Error> {
+    api_token.require_scope(ApiScope::IssuesRead)?;
+    let issue = fetch_issue(&**pool, path.into_inner()).await?;
+    Ok(HttpResponse::Ok().json(issue))
+}
+
+/// Lists issues, the most recently created first, needs the `issues:read` scope.
+pub async fn api_list_issues(
+    query: web::Query<ListParameters>,
+    pool: web::Data<PgPool>,
+    api_token: web::ReqData<ApiToken>,
+) -> Result<HttpResponse, actix_web::Error> {
+    api_token.require_scope(ApiScope::IssuesRead)?;
+    let status = match &query.status {
+        Some(status) => Some(IssueStatus::parse(status).ok_or_else(|| {
+            ApiError::InvalidRequest(format!("{} is not an issue status.", status))
+        })?),
+        None => None,
+    };
+    let limit = query.limit.unwrap_or(DEFAULT_PAGE_SIZE);
+    if !(1..=MAX_PAGE_SIZE).contains(&limit) {
+        let message = format!("limit must be between 1 and {}.", MAX_PAGE_SIZE);
+        return Err(ApiError::ValidationFailed(message).into());
+    }
+    let offset = query.offset.unwrap_or(0);
+    if offset < 0 {
+        return Err(ApiError::ValidationFailed("offset can't be negative.".into()).into());
+    }
+    let issues: Vec<IssueSummary> = list_issues(&pool, status, limit, offset)
+        .await
+        .map_err(ApiError::from)?
+        .into_iter()
+        .map(|issue| IssueDetails::from(issue).summary)
+        .collect();
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "issues": issues })))
+}
+
+/// Edits a draft or scheduled issue, needs the `issues:write` scope.
+#[tracing::instrument(name = "Update an issue through the API", skip_all, fields(user_id=%&*user_id))]
+pub async fn api_update_issue(
+    path: web::Path<Uuid>,
+    body: web::Json<IssueChanges>,
+    pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+    api_token: web::ReqData<ApiToken>,
+    request: HttpRequest,
+) -> Result<HttpResponse, actix_web::Error> {
+    api_token.require_scope(ApiScope::IssuesWrite)?;
+    validate_content("title", body.title.as_deref())?;
+    validate_content("text_content", body.text_content.as_deref())?;
+    validate_content("html_content", body.html_content.as_deref())?;
+    let issue_id = path.into_inner();
+    let mut transaction = begin_transaction(&request, &pool)
+        .await
+        .context("Failed to start a transaction.")
+        .map_err(ApiError::from)?;
+    update_issue(
+        &mut transaction,
+        issue_id,
+        body.title.as_deref(),
+        body.text_content.as_deref(),
+        body.html_content.as_deref(),
+    )
+    .await
+    .map_err(ApiError::from)?;
+    let issue = fetch_issue(&mut *transaction, issue_id).await?;
+    commit_transaction(&request, transaction)
+        .await
+        .context("Failed to commit the changes to the issue.")
+        .map_err(ApiError::from)?;
+    Ok(HttpResponse::Ok().json(issue))
+}
+
+/// Publishes a draft or scheduled issue now, needs the `newsletters:publish` scope.
+#[tracing::instrument(name = "Publish an issue through the API", skip_all, fields(user_id=%&*user_id))]
+pub async fn api_publish_issue(
+    path: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+    api_token: web::ReqData<ApiToken>,
+    request: HttpRequest,
+) -> Result<HttpResponse, actix_web::Error> {
+    api_token.require_scope(ApiScope::NewslettersPublish)?;
+    let issue_id = path.into_inner();
+    let mut transaction = begin_transaction(&request, &pool)
+        .await
+        .context("Failed to start a transaction.")
+        .map_err(ApiError::from)?;
+    publish_issue_now(&mut transaction, issue_id)
+        .await
+        .map_err(ApiError::from)?;
+    record_audit_event(
+        &mut *transaction,
+        &request,
+        Some(**user_id),
+        AuditAction::NewsletterPublish,
+        Some(&issue_id.to_string()),
+    )
+    .await
+    .map_err(ApiError::from)?;
+    let issue = fetch_issue(&mut *transaction, issue_id).await?;
+    commit_transaction(&request, transaction)
+        .await
+        .context("Failed to commit the newsletter issue.")
+        .map_err(ApiError::from)?;
+    Ok(HttpResponse::Ok().json(issue))
+}
+
+/// Has the issue published at a later time, needs the `newsletters:publish` scope.
+/// Scheduling an already scheduled issue moves it.
+#[tracing::instrument(name = "Schedule an issue through the API", skip_all, fields(user_id=%&*user_id))]
+pub async fn api_schedule_issue(
+    path: web::Path<Uuid>,
+    body: web::Json<IssueSchedule>,
+    pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+    api_token: web::ReqData<ApiToken>,
+    request: HttpRequest,
+) -> Result<HttpResponse, actix_web::Error> {
+    api_token.require_scope(ApiScope::NewslettersPublish)?;
+    let publish_at = DateTime::parse_from_rfc3339(&body.publish_at)
+        .map_err(|_| {
+            ApiError::ValidationFailed(format!(
+                "{} is not an RFC 3339 date and time.",
+                body.publish_at
+            ))
+        })?
+        .with_timezone(&Utc);
+    if publish_at <= Utc::now() {
+        let message = "publish_at must be in the future, publish the issue instead.";
+        return Err(ApiError::ValidationFailed(message.into()).into());
+    }
+    let issue_id = path.into_inner();
+    let mut transaction = begin_transaction(&request, &pool)
+        .await
+        .context("Failed to start a transaction.")
+        .map_err(ApiError::from)?;
+    schedule_issue(&mut transaction, issue_id, publish_at)
+        .await
+        .map_err(ApiError::from)?;
+    record_audit_event(
+        &mut *transaction,
+        &request,
+        Some(**user_id),
+        AuditAction::NewsletterSchedule,
+        Some(&issue_id.to_string()),
+    )
+    .await
+    .map_err(ApiError::from)?;
+    let issue = fetch_issue(&mut *transaction, issue_id).await?;
+    commit_transaction(&request, transaction)
+        .await
+        .context("Failed to commit the schedule.")
+        .map_err(ApiError::from)?;
+    Ok(HttpResponse::Ok().json(issue))
+}
+
+async fn fetch_issue(
+    executor: impl PgExecutor<'_>,
+    issue_id: Uuid,
+) -> Result<IssueDetails, ApiError> {
+    get_issue(executor, issue_id)
+        .await?
+        .map(IssueDetails::from)
+        .ok_or(ApiError::NotFound)
+}
+
+/// Issues can't have a blank title or content.
+fn validate_content(field: &str, value: Option<&str>) -> Result<(), ApiError> {
+    match value {
+        Some(value) if value.trim().is_empty() => Err(ApiError::ValidationFailed(format!(
+            "{} can't be empty.",
+            field
+        ))),
+        _ => Ok(()),
+    }
+}
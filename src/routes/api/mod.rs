@@ -0,0 +1,7 @@
+//! /api/v1: programmatic access, authenticated with `Authorization: Bearer` API tokens
+//! instead of a session. Requests and responses are JSON, errors included.
+mod error;
+mod issues;
+
+pub use error::{api_idempotency, api_json_config, api_path_config, api_query_config, ApiError};
+pub use issues::*;
@@ -1,8 +1,151 @@
-/// /health_check route handler
+/// /health_check and /health/* route handlers
 ///
-use actix_web::HttpResponse;
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::time::Instant;
+
+use actix_web::{web, HttpResponse};
+use anyhow::Context;
+use sqlx::PgPool;
+
+use crate::configuration::HealthSettings;
+use crate::email_client::EmailClient;
 
 /// Returns an empty OK HttpResonse.
 pub async fn health_check() -> HttpResponse {
     HttpResponse::Ok().finish()
 }
+
+/// Liveness probe: the process is up and serving requests.
+pub async fn health_live() -> HttpResponse {
+    HttpResponse::Ok().json(serde_json::json!({ "status": "ok" }))
+}
+
+/// Wraps the redis client, used to check the session store can be reached.
+/// Only registered when sessions are stored in redis.
+pub struct RedisClient(pub redis::Client);
+
+#[derive(serde::Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+enum Status {
+    Ok,
+    Unavailable,
+}
+
+#[derive(serde::Serialize)]
+struct CheckReport {
+    status: Status,
+    latency_ms: f64,
+    // Failures of non critical dependencies are reported, but don't make the instance unready.
+    critical: bool,
+}
+
+#[derive(serde::Serialize)]
+struct ReadinessReport {
+    status: Status,
+    checks: BTreeMap<&'static str, CheckReport>,
+}
+
+/// Readiness probe: checks postgres, redis when it stores the sessions and, if enabled,
+/// the email provider, all at once.
+/// Returns a 503 if a critical dependency is unavailable.
+///
+/// The probe is public: failures are logged, the report only has their status.
+#[tracing::instrument(name = "Check readiness", skip_all)]
+pub async fn health_ready(
+    pool: web::Data<PgPool>,
+    redis_client: Option<web::Data<RedisClient>>,
+    email_client: web::Data<EmailClient>,
+    settings: web::Data<HealthSettings>,
+) -> HttpResponse {
+    let timeout = settings.timeout();
+    let postgres = check(timeout, true, async {
+        sqlx::query("SELECT 1")
+            .execute(pool.get_ref())
+            .await
+            .context("Failed to query postgres.")?;
+        Ok(())
+    });
+    let redis = async {
+        let redis_client = redis_client?;
+        let report = check(timeout, true, async {
+            let mut connection = redis_client
+                .0
+                .get_multiplexed_tokio_connection()
+                .await
+                .context("Failed to connect to redis.")?;
+            redis::cmd("PING")
+                .query_async::<_, String>(&mut connection)
+                .await
+                .context("Failed to ping redis.")?;
+            Ok(())
+        })
+        .await;
+        Some(report)
+    };
+    let email_provider = async {
+        if !settings.check_email_provider {
+            return None;
+        }
+        let report = check(timeout, false, async {
+            email_client
+                .ping()
+                .await
+                .context("Failed to reach the email provider.")
+        })
+        .await;
+        Some(report)
+    };
+    let (postgres, redis, email_provider) = tokio::join!(postgres, redis, email_provider);
+
+    let mut checks = BTreeMap::new();
+    checks.insert("postgres", postgres);
+    if let Some(redis) = redis {
+        checks.insert("redis", redis);
+    }
+    if let Some(email_provider) = email_provider {
+        checks.insert("email_provider", email_provider);
+    }
+    let ready = checks
+        .values()
+        .all(|c| !c.critical || c.status == Status::Ok);
+    let report = ReadinessReport {
+        status: if ready {
+            Status::Ok
+        } else {
+            Status::Unavailable
+        },
+        checks,
+    };
+    if ready {
+        HttpResponse::Ok().json(report)
+    } else {
+        HttpResponse::ServiceUnavailable().json(report)
+    }
+}
+
+/// Runs a single dependency check, timing it.
+async fn check(
+    timeout: std::time::Duration,
+    critical: bool,
+    f: impl Future<Output = Result<(), anyhow::Error>>,
+) -> CheckReport {
+    let start = Instant::now();
+    let outcome = match tokio::time::timeout(timeout, f).await {
+        Ok(outcome) => outcome,
+        Err(_) => Err(anyhow::anyhow!("Timed out after {:?}.", timeout)),
+    };
+    let latency_ms = start.elapsed().as_secs_f64() * 1000.0;
+    let status = match outcome {
+        Ok(()) => Status::Ok,
+        Err(e) => {
+            tracing::warn!(error.cause_chain = ?e, "Dependency check failed.");
+            Status::Unavailable
+        }
+    };
+    CheckReport {
+        status,
+        latency_ms,
+        critical,
+    }
+}
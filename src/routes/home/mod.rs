@@ -1,7 +1,19 @@
-use actix_web::{http::header::ContentType, HttpResponse};
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpResponse};
+use askama::Template;
+use chrono::Utc;
 
-pub async fn home() -> HttpResponse {
-    HttpResponse::Ok()
-        .content_type(ContentType::html())
-        .body(include_str!("home.html"))
+use crate::form_token::FormToken;
+use crate::routes::page::html_page;
+use crate::startup::HmacSecret;
+
+#[derive(Template)]
+#[template(path = "home.html")]
+struct HomeTemplate {
+    form_token: String,
+}
+
+pub async fn home(hmac_secret: web::Data<HmacSecret>) -> Result<HttpResponse, actix_web::Error> {
+    let form_token = FormToken::new(Utc::now()).sign(&hmac_secret);
+    html_page(StatusCode::OK, &HomeTemplate { form_token })
 }
@@ -1,47 +1,21 @@
-use actix_web::http::header::ContentType;
+use actix_web::http::StatusCode;
 use actix_web::HttpResponse;
 use actix_web_flash_messages::IncomingFlashMessages;
-use std::fmt::Write;
+use askama::Template;
 
-pub async fn login_form(flash_messages: IncomingFlashMessages) -> HttpResponse {
-    let mut flash_msg_html = String::new();
-    for m in flash_messages.iter() {
-        writeln!(flash_msg_html, "<p><i>{}</i></p>", m.content()).unwrap();
-    }
+use crate::routes::page::{flash_message_contents, html_page};
 
-    HttpResponse::Ok()
-        .content_type(ContentType::html())
-        .body(format!(
-            r#"
-<!DOCTYPE html>
-<html lang="en">
-    <head>
-        <meta http-equiv="content-type" content="text/html; charset=utf-8">
-        <title>Login</title>
-    </head>
-    {flash_msg_html}
-    <body>
-        <form action="/login" method="post">
-            <label>Username
-                <input 
-                    type="text" 
-                    placeholder="Enter Username" 
-                    name="username"
-                >
-            </label>
-
-            <label>Password
-                <input 
-                    type="password" 
-                    placeholder="Enter Password"
-                    name="password"
-                >
-            </label>
+#[derive(Template)]
+#[template(path = "login.html")]
+struct LoginTemplate {
+    flash_messages: Vec<String>,
+}
 
-            <button type="submit">Login</button>
-        </form>
-    </body>
-</html>
-        "#
-        ))
+pub async fn login_form(
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let template = LoginTemplate {
+        flash_messages: flash_message_contents(&flash_messages),
+    };
+    html_page(StatusCode::OK, &template)
 }
@@ -1,11 +1,16 @@
 use actix_web::error::InternalError;
 use actix_web::web;
-use actix_web::{http::header::LOCATION, HttpResponse};
+use actix_web::{http::header::LOCATION, HttpRequest, HttpResponse};
 use actix_web_flash_messages::FlashMessage;
+use chrono::Utc;
 use secrecy::Secret;
 use sqlx::PgPool;
 
-use crate::authentication::{validate_credentials, AuthError, Credentials};
+use crate::audit::{record_audit_event, AuditAction};
+use crate::authentication::{
+    record_session, validate_credentials, AuthError, Credentials, SessionMetadata,
+};
+use crate::configuration::{PasswordHashingSettings, SessionSettings};
 use crate::routes::error_chain_fmt;
 use crate::session_state::TypedSession;
 
@@ -13,28 +18,55 @@ use crate::session_state::TypedSession;
 pub struct FormData {
     username: String,
     password: Secret<String>,
+    // Sent as "on" when the checkbox is ticked, missing otherwise.
+    remember_me: Option<String>,
 }
 
 #[tracing::instrument(
-    skip(form, pool,session),
+    skip(form, pool, session_settings, password_hashing, session, request),
     fields(username=tracing::field::Empty, user_id=tracing::field::Empty))]
 pub async fn login(
     form: web::Form<FormData>,
     pool: web::Data<PgPool>,
+    session_settings: web::Data<SessionSettings>,
+    password_hashing: web::Data<PasswordHashingSettings>,
     session: TypedSession,
+    request: HttpRequest,
 ) -> Result<HttpResponse, InternalError<LoginError>> {
+    let remember_me = form.0.remember_me.is_some();
+    let username = form.0.username;
     let credentials = Credentials {
-        username: form.0.username,
+        username: username.clone(),
         password: form.0.password,
     };
 
-    match validate_credentials(credentials, &pool).await {
+    match validate_credentials(credentials, &pool, &password_hashing).await {
         Ok(user_id) => {
-            tracing::Span::current().record("user_id", &tracing::field::display(&user_id));
+            tracing::Span::current().record("user_id", tracing::field::display(&user_id));
             session.renew();
             session
                 .insert_user_id(user_id)
                 .map_err(|e| login_redirect(LoginError::UnexpectedError(e.into())))?;
+            session
+                .start(remember_me, Utc::now())
+                .map_err(|e| login_redirect(LoginError::UnexpectedError(e.into())))?;
+            session
+                .rotate_csrf_token()
+                .map_err(|e| login_redirect(LoginError::UnexpectedError(e.into())))?;
+            let session_id = record_session(
+                &pool,
+                user_id,
+                &SessionMetadata::from_request(&request),
+                session_settings.state_ttl(),
+            )
+            .await
+            .map_err(|e| login_redirect(LoginError::UnexpectedError(e)))?;
+            session
+                .insert_session_id(session_id)
+                .map_err(|e| login_redirect(LoginError::UnexpectedError(e.into())))?;
+            record_audit_event(&**pool, &request, Some(user_id), AuditAction::LogIn, None)
+                .await
+                .map_err(|e| login_redirect(LoginError::UnexpectedError(e)))?;
 
             Ok(HttpResponse::SeeOther()
                 .insert_header((LOCATION, "/admin/dashboard"))
@@ -42,7 +74,18 @@ pub async fn login(
         }
         Err(e) => {
             let e = match e {
-                AuthError::InvalidCredentials(_) => LoginError::AuthError(e.into()),
+                AuthError::InvalidCredentials(_) => {
+                    record_audit_event(
+                        &**pool,
+                        &request,
+                        None,
+                        AuditAction::FailedLogIn,
+                        Some(&username),
+                    )
+                    .await
+                    .map_err(|e| login_redirect(LoginError::UnexpectedError(e)))?;
+                    LoginError::AuthError(e.into())
+                }
                 AuthError::UnexpectedError(_) => LoginError::UnexpectedError(e.into()),
             };
             Err(login_redirect(e))
@@ -1,11 +1,14 @@
 mod admin;
+mod api;
 mod health_check;
 mod home;
 mod login;
+mod page;
 mod subscriptions;
 mod subscriptions_confirm;
 
 pub use admin::*;
+pub use api::*;
 pub use health_check::*;
 pub use home::*;
 pub use login::*;
@@ -0,0 +1,26 @@
+use actix_web::http::header::ContentType;
+use actix_web::http::StatusCode;
+use actix_web::HttpResponse;
+use actix_web_flash_messages::IncomingFlashMessages;
+use askama::Template;
+
+use crate::utils::e500;
+
+/// Renders one of the templates under ./templates as an HTML response.
+pub fn html_page(
+    status: StatusCode,
+    template: &impl Template,
+) -> Result<HttpResponse, actix_web::Error> {
+    let body = template.render().map_err(e500)?;
+    Ok(HttpResponse::build(status)
+        .content_type(ContentType::html())
+        .body(body))
+}
+
+/// Flash messages, for the templates' `partials/flash_messages.html`.
+pub fn flash_message_contents(flash_messages: &IncomingFlashMessages) -> Vec<String> {
+    flash_messages
+        .iter()
+        .map(|m| m.content().to_owned())
+        .collect()
+}
@@ -1,32 +1,55 @@
+use actix_web::error::InternalError;
 use actix_web::http::StatusCode;
 /// /subscriptions handlers.
-use actix_web::{web, HttpResponse, ResponseError};
+use actix_web::{web, HttpRequest, HttpResponse, ResponseError};
 use anyhow::Context;
+use askama::Template;
 use chrono::Utc;
 use rand::distributions::Alphanumeric;
 use rand::{thread_rng, Rng};
 use sqlx::Executor;
 use sqlx::{PgPool, Postgres, Transaction};
+use std::time::Duration;
 use uuid::Uuid;
 
 use crate::{
-    domain::{NewSubscriber, SubscriberEmail, SubscriberName},
+    client_ip::client_ip,
+    configuration::SubscriptionSettings,
+    domain::{
+        EmailNormalization, NewSubscriber, SubscriberEmail, SubscriberName, SubscriptionStatus,
+    },
     email_client::EmailClient,
-    startup::ApplicationBaseUrl,
+    email_domains::EmailDomainValidator,
+    form_token::{FormToken, FormTokenError},
+    rate_limit::check_rate_limit,
+    request_id::current_request_id,
+    routes::page::html_page,
+    startup::{ApplicationBaseUrl, HmacSecret},
+    utils::prefers_html,
 };
 
 #[derive(serde::Deserialize)]
 pub struct FormData {
     pub email: String,
     pub name: String,
+    /// Signed timestamp of when the form was served, see `FormToken`.
+    #[serde(default)]
+    pub form_token: String,
+    /// Honeypot: hidden from people, bots fill it in.
+    #[serde(default)]
+    pub website: String,
 }
 
-/// Subscribes a user to the newsletter
+/// Subscribes a user to the newsletter.
+/// Answers browsers with a "check your inbox" page, or an error page.
 ///  - Preconditions
 ///     * email and name set.
+///     * a valid form token, served at least a few seconds ago.
+///     * the client and the email address are within their rate limits.
+#[allow(clippy::too_many_arguments)]
 #[tracing::instrument(
     name = "Adding a new subscriber",
-    skip(form, db_pool, base_url),
+    skip(form, request, db_pool, base_url, settings, email_domain_validator, hmac_secret),
     fields(
         subscriber_email = %form.email,
         subscriber_name = %form.name
@@ -34,40 +57,168 @@ pub struct FormData {
 )]
 pub async fn subscribe(
     form: web::Form<FormData>,
+    request: HttpRequest,
     db_pool: web::Data<PgPool>,
     email_client: web::Data<EmailClient>,
     base_url: web::Data<ApplicationBaseUrl>,
-) -> Result<HttpResponse, SubscribeError> {
-    let new_subscriber = form.0.try_into().map_err(SubscribeError::ValidationError)?;
+    settings: web::Data<SubscriptionSettings>,
+    email_domain_validator: web::Data<EmailDomainValidator>,
+    hmac_secret: web::Data<HmacSecret>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let email = form.email.clone();
+    let outcome: Result<(), SubscribeError> = async {
+        let bot_protection = &settings.bot_protection;
+        if !form.website.is_empty() {
+            // Don't let on that we noticed.
+            tracing::info!("Ignoring a subscription with the honeypot field filled in.");
+            return Ok(());
+        }
+        FormToken::verify(&form.form_token, &hmac_secret, bot_protection, Utc::now())?;
+        let client_address = client_ip(&request)
+            .map(|ip| ip.to_string())
+            .unwrap_or_default();
+        if !check_rate_limit(
+            &db_pool,
+            "subscribe_ip",
+            &client_address,
+            &bot_protection.per_ip,
+        )
+        .await?
+        {
+            return Err(SubscribeError::TooManyRequests);
+        }
 
-    let mut transaction = db_pool
-        .begin()
-        .await
-        .context("Failed to acquire a new Postgres connection from the pool")?;
+        let new_subscriber = parse_new_subscriber(form.0, settings.email_normalization())
+            .map_err(SubscribeError::ValidationError)?;
+        email_domain_validator
+            .validate(&new_subscriber.email)
+            .await
+            .map_err(SubscribeError::ValidationError)?;
+        // Whatever the normalization, Foo@example.com and foo@example.com share a limit.
+        let email_key = new_subscriber.email.as_ref().to_lowercase();
+        if !check_rate_limit(
+            &db_pool,
+            "subscribe_email",
+            &email_key,
+            &bot_protection.per_email,
+        )
+        .await?
+        {
+            return Err(SubscribeError::TooManyRequests);
+        }
 
-    let subscriber_id = insert_subscriber(&mut transaction, &new_subscriber)
-        .await
-        .context("Failed to insert a new subscriber into the database.")?;
-    let subscription_token = generate_subscription_token();
+        let mut transaction = db_pool
+            .begin()
+            .await
+            .context("Failed to acquire a new Postgres connection from the pool")?;
 
-    store_token(&mut transaction, subscriber_id, &subscription_token)
+        delete_abandoned_subscription(
+            &mut transaction,
+            &new_subscriber.email,
+            settings.confirmation_token_ttl(),
+        )
         .await
-        .context("Failed to store the confirmation token for a new subscriber.")?;
+        .context("Failed to delete an abandoned subscription.")?;
+        let existing = find_subscriber(&mut transaction, &new_subscriber.email)
+            .await
+            .context("Failed to look up the subscriber.")?;
+        let subscriber_id = match existing {
+            // Unsubscribed and bounced addresses subscribe again, confirming like new ones.
+            Some((subscriber_id, status))
+                if status != SubscriptionStatus::Pending
+                    && status.can_transition_to(SubscriptionStatus::Pending) =>
+            {
+                resubscribe(&mut transaction, subscriber_id, &new_subscriber)
+                    .await
+                    .context("Failed to subscribe a former subscriber again.")?;
+                subscriber_id
+            }
+            // Answer as for a new subscriber, not to tell who's subscribed.
+            Some(_) => {
+                tracing::info!("The email address is already subscribed.");
+                return Ok(());
+            }
+            None => match insert_subscriber(&mut transaction, &new_subscriber).await {
+                Ok(subscriber_id) => subscriber_id,
+                // Subscribed concurrently, or under another case when it's folded.
+                Err(sqlx::Error::Database(e)) if e.is_unique_violation() => {
+                    tracing::info!("The email address is already subscribed.");
+                    return Ok(());
+                }
+                Err(e) => {
+                    return Err(anyhow::Error::new(e)
+                        .context("Failed to insert a new subscriber into the database.")
+                        .into())
+                }
+            },
+        };
+        let subscription_token = generate_subscription_token();
+
+        store_token(&mut transaction, subscriber_id, &subscription_token)
+            .await
+            .context("Failed to store the confirmation token for a new subscriber.")?;
 
-    transaction
-        .commit()
+        transaction
+            .commit()
+            .await
+            .context("failed to commit sql transaction to add new subscriber.")?;
+
+        send_confirmation_email(
+            &email_client,
+            new_subscriber,
+            &base_url.0,
+            &subscription_token,
+        )
         .await
-        .context("failed to commit sql transaction to add new subscriber.")?;
+        .context("Failed to send confirmation email")?;
+        Ok(())
+    }
+    .await;
 
-    send_confirmation_email(
-        &email_client,
-        new_subscriber,
-        &base_url.0,
-        &subscription_token,
-    )
-    .await
-    .context("Failed to send confirmation email")?;
-    Ok(HttpResponse::Ok().finish())
+    // Browsers get pages, programmatic clients a bare status code.
+    let prefers_html = prefers_html(&request);
+    match outcome {
+        Ok(()) if prefers_html => html_page(
+            StatusCode::OK,
+            &CheckYourInboxTemplate {
+                email: email.trim(),
+            },
+        ),
+        Ok(()) => Ok(HttpResponse::Ok().finish()),
+        Err(e) if prefers_html => {
+            let page = subscribe_error_page(&e).unwrap_or_else(|e| e.error_response());
+            Err(InternalError::from_response(e, page).into())
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+#[derive(Template)]
+#[template(path = "check_your_inbox.html")]
+struct CheckYourInboxTemplate<'a> {
+    email: &'a str,
+}
+
+#[derive(Template)]
+#[template(path = "subscribe_error.html")]
+struct SubscribeErrorTemplate {
+    message: String,
+}
+
+fn subscribe_error_page(e: &SubscribeError) -> Result<HttpResponse, actix_web::Error> {
+    let message = match e {
+        // The details are for the logs.
+        SubscribeError::UnexpectedError(_) => {
+            let mut message =
+                "Something went wrong on our side, please try again later.".to_string();
+            if let Some(request_id) = current_request_id() {
+                message.push_str(&format!(" Request id: {}", request_id));
+            }
+            message
+        }
+        e => e.to_string(),
+    };
+    html_page(e.status_code(), &SubscribeErrorTemplate { message })
 }
 
 #[derive(thiserror::Error)]
@@ -75,6 +226,10 @@ pub enum SubscribeError {
     #[error("{0}")]
     ValidationError(String),
     #[error(transparent)]
+    InvalidFormToken(#[from] FormTokenError),
+    #[error("Too many subscription attempts, please try again later.")]
+    TooManyRequests,
+    #[error(transparent)]
     UnexpectedError(#[from] anyhow::Error),
 }
 
@@ -88,6 +243,8 @@ impl ResponseError for SubscribeError {
     fn status_code(&self) -> StatusCode {
         match self {
             SubscribeError::ValidationError(_) => StatusCode::BAD_REQUEST,
+            SubscribeError::InvalidFormToken(_) => StatusCode::BAD_REQUEST,
+            SubscribeError::TooManyRequests => StatusCode::TOO_MANY_REQUESTS,
             SubscribeError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
@@ -108,10 +265,7 @@ async fn store_token(
         subscription_token,
         subscriber_id
     );
-    transaction
-        .execute(query)
-        .await
-        .map_err(|e| StoreTokenError(e))?;
+    transaction.execute(query).await.map_err(StoreTokenError)?;
     Ok(())
 }
 
@@ -183,14 +337,95 @@ async fn send_confirmation_email(
         .await
 }
 
-impl TryFrom<FormData> for NewSubscriber {
-    type Error = String;
+/// Validates the form, the email is stored in its normalized form.
+fn parse_new_subscriber(
+    form: FormData,
+    normalization: EmailNormalization,
+) -> Result<NewSubscriber, String> {
+    let name = SubscriberName::parse(form.name)?;
+    let email = SubscriberEmail::parse_with(form.email, normalization)?;
+    Ok(NewSubscriber { email, name })
+}
 
-    fn try_from(value: FormData) -> Result<Self, Self::Error> {
-        let name = SubscriberName::parse(value.name)?;
-        let email = SubscriberEmail::parse(value.email)?;
-        Ok(Self { email, name })
-    }
+/// Deletes the pending subscription to `email` if its confirmation links all
+/// expired, so that the address can subscribe again.
+#[tracing::instrument(name = "Delete abandoned subscription", skip(transaction, email))]
+async fn delete_abandoned_subscription(
+    transaction: &mut Transaction<'_, Postgres>,
+    email: &SubscriberEmail,
+    token_ttl: Duration,
+) -> Result<(), sqlx::Error> {
+    let query = sqlx::query!(
+        r#"
+        WITH abandoned AS (
+            SELECT id FROM subscriptions s
+            WHERE s.email = $1 AND s.status = $2
+                AND NOT EXISTS (
+                    SELECT 1 FROM subscription_tokens t
+                    WHERE t.subscriber_id = s.id AND t.created_at > now() - $3::interval
+                )
+        ), deleted_tokens AS (
+            DELETE FROM subscription_tokens WHERE subscriber_id IN (SELECT id FROM abandoned)
+        )
+        DELETE FROM subscriptions WHERE id IN (SELECT id FROM abandoned)
+        "#,
+        email.as_ref(),
+        SubscriptionStatus::Pending as SubscriptionStatus,
+        token_ttl as Duration,
+    );
+    transaction.execute(query).await?;
+    Ok(())
+}
+
+/// Returns the id and status of the subscriber with `email`, locking their row.
+#[tracing::instrument(name = "Find subscriber", skip(transaction, email))]
+async fn find_subscriber(
+    transaction: &mut Transaction<'_, Postgres>,
+    email: &SubscriberEmail,
+) -> Result<Option<(Uuid, SubscriptionStatus)>, sqlx::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT id, status AS "status: SubscriptionStatus"
+        FROM subscriptions
+        WHERE email = $1
+        FOR UPDATE
+        "#,
+        email.as_ref(),
+    )
+    .fetch_optional(&mut **transaction)
+    .await?;
+    Ok(row.map(|r| (r.id, r.status)))
+}
+
+/// Moves a former subscriber back to pending, under their new name. Their old
+/// confirmation links are deleted: only the one about to be sent confirms.
+#[tracing::instrument(name = "Subscribe again", skip(transaction, new_subscriber))]
+async fn resubscribe(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    new_subscriber: &NewSubscriber,
+) -> Result<(), sqlx::Error> {
+    let pending = SubscriptionStatus::Pending;
+    sqlx::query!(
+        r#"
+        UPDATE subscriptions SET status = $2, name = $3, subscribed_at = $4
+        WHERE id = $1 AND status = ANY($5)
+        "#,
+        subscriber_id,
+        pending as SubscriptionStatus,
+        new_subscriber.name.as_ref(),
+        Utc::now(),
+        SubscriptionStatus::sources_of(pending) as Vec<SubscriptionStatus>,
+    )
+    .execute(&mut **transaction)
+    .await?;
+    sqlx::query!(
+        "DELETE FROM subscription_tokens WHERE subscriber_id = $1",
+        subscriber_id
+    )
+    .execute(&mut **transaction)
+    .await?;
+    Ok(())
 }
 
 #[tracing::instrument(
@@ -205,12 +440,13 @@ async fn insert_subscriber(
     let query = sqlx::query!(
         r#"
        INSERT INTO subscriptions (id, email, name, subscribed_at, status)
-       VALUES($1, $2, $3, $4, 'pending_confirmation')
+       VALUES($1, $2, $3, $4, $5)
        "#,
         subscriber_id,
         new_subscriber.email.as_ref(),
         new_subscriber.name.as_ref(),
-        Utc::now()
+        Utc::now(),
+        SubscriptionStatus::Pending as SubscriptionStatus,
     );
     transaction.execute(query).await?;
     Ok(subscriber_id)
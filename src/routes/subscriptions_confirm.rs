@@ -1,36 +1,127 @@
-use actix_web::{web, HttpResponse};
+use std::time::Duration;
+
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpRequest, HttpResponse};
+use askama::Template;
 use sqlx::PgPool;
 use uuid::Uuid;
 
+use crate::configuration::SubscriptionSettings;
+use crate::domain::SubscriptionStatus;
+use crate::routes::page::html_page;
+use crate::utils::prefers_html;
+
 #[derive(serde::Deserialize)]
 pub struct Parameters {
     subscription_token: String,
 }
 
-#[tracing::instrument(name = "Confirm a pending subscriber", skip(parameters))]
-pub async fn confirm(parameters: web::Query<Parameters>, pool: web::Data<PgPool>) -> HttpResponse {
-    let id = match get_subscriber_id_from_token(&pool, &parameters.subscription_token).await {
-        Ok(id) => id,
-        Err(_) => return HttpResponse::InternalServerError().finish(),
+/// Confirms the subscriber the token was sent to.
+/// Answers browsers with a page, programmatic clients with a bare status code.
+#[tracing::instrument(
+    name = "Confirm a pending subscriber",
+    skip(parameters, request, settings)
+)]
+pub async fn confirm(
+    parameters: web::Query<Parameters>,
+    request: HttpRequest,
+    pool: web::Data<PgPool>,
+    settings: web::Data<SubscriptionSettings>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let outcome = match get_subscriber_id_from_token(
+        &pool,
+        &parameters.subscription_token,
+        settings.confirmation_token_ttl(),
+    )
+    .await
+    {
+        Ok(Some((subscriber_id, false))) => match confirm_subscriber(&pool, subscriber_id).await {
+            Ok(true) => ConfirmOutcome::Confirmed,
+            // e.g. they unsubscribed since, confirming would resubscribe them.
+            Ok(false) => ConfirmOutcome::NotPending,
+            Err(_) => ConfirmOutcome::Failed,
+        },
+        Ok(Some((_, true))) => ConfirmOutcome::Expired,
+        // Non existing token.
+        Ok(None) => ConfirmOutcome::InvalidToken,
+        Err(_) => ConfirmOutcome::Failed,
     };
+    if prefers_html(&request) {
+        outcome.page()
+    } else {
+        Ok(HttpResponse::new(outcome.status_code()))
+    }
+}
 
-    match id {
-        // Non existing token.
-        None => HttpResponse::Unauthorized().finish(),
-        Some(subscriber_id) => {
-            if confirm_subscriber(&pool, subscriber_id).await.is_err() {
-                return HttpResponse::InternalServerError().finish();
-            }
-            HttpResponse::Ok().finish()
+enum ConfirmOutcome {
+    Confirmed,
+    InvalidToken,
+    Expired,
+    NotPending,
+    Failed,
+}
+
+impl ConfirmOutcome {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            Self::Confirmed => StatusCode::OK,
+            Self::InvalidToken => StatusCode::UNAUTHORIZED,
+            Self::Expired => StatusCode::GONE,
+            Self::NotPending => StatusCode::CONFLICT,
+            Self::Failed => StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
+
+    fn page(&self) -> Result<HttpResponse, actix_web::Error> {
+        let (title, message) = match self {
+            Self::Confirmed => (
+                "You're subscribed!",
+                "Thanks for confirming, the next issue will land in your inbox.",
+            ),
+            Self::InvalidToken => (
+                "Invalid confirmation link",
+                "This link isn't valid, make sure you copied all of it from the email.",
+            ),
+            Self::Expired => (
+                "This link has expired",
+                "Confirmation links are only valid for a few days.",
+            ),
+            Self::NotPending => (
+                "This link can't be used anymore",
+                "This subscription can't be confirmed anymore, e.g. because it was cancelled.",
+            ),
+            Self::Failed => (
+                "Something went wrong",
+                "We couldn't confirm your subscription, please try again later.",
+            ),
+        };
+        let template = ConfirmTemplate {
+            title,
+            message,
+            offer_to_subscribe_again: matches!(self, Self::Expired),
+        };
+        html_page(self.status_code(), &template)
+    }
 }
 
+#[derive(Template)]
+#[template(path = "confirm.html")]
+struct ConfirmTemplate {
+    title: &'static str,
+    message: &'static str,
+    offer_to_subscribe_again: bool,
+}
+
+/// Marks the subscriber as confirmed, returns false if their current status
+/// can't transition to confirmed.
 #[tracing::instrument(name = "Mark subscriber as confirmed", skip(subscriber_id, pool))]
-async fn confirm_subscriber(pool: &PgPool, subscriber_id: Uuid) -> Result<(), sqlx::Error> {
-    sqlx::query!(
-        r#"UPDATE subscriptions SET status = 'confirmed' WHERE id = $1"#,
-        subscriber_id
+async fn confirm_subscriber(pool: &PgPool, subscriber_id: Uuid) -> Result<bool, sqlx::Error> {
+    let confirmed = SubscriptionStatus::Confirmed;
+    let result = sqlx::query!(
+        r#"UPDATE subscriptions SET status = $2 WHERE id = $1 AND status = ANY($3)"#,
+        subscriber_id,
+        confirmed as SubscriptionStatus,
+        SubscriptionStatus::sources_of(confirmed) as Vec<SubscriptionStatus>,
     )
     .execute(pool)
     .await
@@ -39,18 +130,25 @@ async fn confirm_subscriber(pool: &PgPool, subscriber_id: Uuid) -> Result<(), sq
         e
     })?;
 
-    Ok(())
+    Ok(result.rows_affected() == 1)
 }
 
-#[tracing::instrument(name = "Get subscriber_id from token", skip(subscription_token, pool))]
+/// Returns the id of the subscriber the token was sent to, and whether the token expired.
+#[tracing::instrument(
+    name = "Get subscriber_id from token",
+    skip(subscription_token, pool, ttl)
+)]
 async fn get_subscriber_id_from_token(
     pool: &PgPool,
     subscription_token: &str,
-) -> Result<Option<Uuid>, sqlx::Error> {
+    ttl: Duration,
+) -> Result<Option<(Uuid, bool)>, sqlx::Error> {
     let result = sqlx::query!(
-        r#"SELECT subscriber_id FROM subscription_tokens 
+        r#"SELECT subscriber_id, created_at <= now() - $2::interval AS "expired!"
+        FROM subscription_tokens
         WHERE subscription_token = $1"#,
         subscription_token,
+        ttl as Duration,
     )
     .fetch_optional(pool)
     .await
@@ -59,5 +157,5 @@ async fn get_subscriber_id_from_token(
         e
     })?;
 
-    Ok(result.map(|r| r.subscriber_id))
+    Ok(result.map(|r| (r.subscriber_id, r.expired)))
 }
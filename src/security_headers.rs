@@ -0,0 +1,107 @@
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::error::InternalError;
+use actix_web::http::header::{
+    HeaderMap, HeaderValue, CONTENT_SECURITY_POLICY, REFERRER_POLICY, STRICT_TRANSPORT_SECURITY,
+    X_CONTENT_TYPE_OPTIONS, X_FRAME_OPTIONS,
+};
+use actix_web::web;
+use actix_web_lab::middleware::Next;
+use anyhow::Context;
+
+use crate::configuration::{Environment, SecurityHeadersSettings};
+use crate::utils::e500;
+
+/// Path of the newsletter issue preview, served with the relaxed policy.
+const PREVIEW_PATH: &str = "/admin/newsletter/preview";
+
+/// Headers added to every response by `set_security_headers`.
+pub struct SecurityHeaders {
+    content_security_policy: HeaderValue,
+    admin_content_security_policy: HeaderValue,
+    preview_content_security_policy: HeaderValue,
+    referrer_policy: HeaderValue,
+    // Only set in production.
+    strict_transport_security: Option<HeaderValue>,
+}
+
+impl SecurityHeaders {
+    pub fn from_settings(
+        settings: &SecurityHeadersSettings,
+        environment: Environment,
+    ) -> Result<Self, anyhow::Error> {
+        let header_value = |value: &str, name: &str| {
+            HeaderValue::from_str(value).with_context(|| format!("Invalid {}.", name))
+        };
+        let strict_transport_security = match environment {
+            Environment::Production => Some(header_value(
+                &format!("max-age={}", settings.hsts_max_age_seconds),
+                "hsts_max_age_seconds",
+            )?),
+            Environment::Local => None,
+        };
+        Ok(Self {
+            content_security_policy: header_value(
+                &settings.content_security_policy,
+                "content_security_policy",
+            )?,
+            admin_content_security_policy: header_value(
+                &settings.admin_content_security_policy,
+                "admin_content_security_policy",
+            )?,
+            preview_content_security_policy: header_value(
+                &settings.preview_content_security_policy,
+                "preview_content_security_policy",
+            )?,
+            referrer_policy: header_value(&settings.referrer_policy, "referrer_policy")?,
+            strict_transport_security,
+        })
+    }
+
+    fn content_security_policy(&self, path: &str) -> &HeaderValue {
+        if path == PREVIEW_PATH {
+            &self.preview_content_security_policy
+        } else if path == "/admin" || path.starts_with("/admin/") {
+            &self.admin_content_security_policy
+        } else {
+            &self.content_security_policy
+        }
+    }
+}
+
+/// Middleware adding the `Content-Security-Policy`, `X-Frame-Options`, `Referrer-Policy`,
+/// `X-Content-Type-Options` and, in production, `Strict-Transport-Security` headers.
+///
+/// Errors from inner middlewares (the CSRF check, the login redirect, API 401s...) are
+/// passed on with their response rendered here, so that it gets the headers too.
+pub async fn set_security_headers(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let Some(security_headers) = req.app_data::<web::Data<SecurityHeaders>>().cloned() else {
+        let e = anyhow::anyhow!("The security headers middleware is missing its app data.");
+        return Err(e500(e));
+    };
+    let content_security_policy = security_headers.content_security_policy(req.path()).clone();
+    let insert_headers = |headers: &mut HeaderMap| {
+        headers.insert(CONTENT_SECURITY_POLICY, content_security_policy.clone());
+        headers.insert(X_FRAME_OPTIONS, HeaderValue::from_static("DENY"));
+        headers.insert(X_CONTENT_TYPE_OPTIONS, HeaderValue::from_static("nosniff"));
+        headers.insert(REFERRER_POLICY, security_headers.referrer_policy.clone());
+        if let Some(value) = &security_headers.strict_transport_security {
+            headers.insert(STRICT_TRANSPORT_SECURITY, value.clone());
+        }
+    };
+
+    match next.call(req).await {
+        Ok(mut response) => {
+            insert_headers(response.headers_mut());
+            Ok(response)
+        }
+        Err(e) => {
+            let mut response = e.error_response();
+            insert_headers(response.headers_mut());
+            Err(InternalError::from_response(e, response).into())
+        }
+    }
+}
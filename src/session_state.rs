@@ -2,12 +2,21 @@ use std::future::{ready, Ready};
 
 use actix_session::{Session, SessionExt, SessionGetError, SessionInsertError};
 use actix_web::FromRequest;
+use chrono::{DateTime, Utc};
+use rand::distributions::Alphanumeric;
+use rand::{thread_rng, Rng};
 use uuid::Uuid;
 
 pub struct TypedSession(Session);
 
 impl TypedSession {
     const USER_ID_KEY: &'static str = "user_id";
+    const CSRF_TOKEN_KEY: &'static str = "csrf_token";
+    const SESSION_ID_KEY: &'static str = "session_id";
+    const LOGGED_IN_AT_KEY: &'static str = "logged_in_at";
+    const LAST_SEEN_AT_KEY: &'static str = "last_seen_at";
+    const AUTHENTICATED_AT_KEY: &'static str = "authenticated_at";
+    const REMEMBER_ME_KEY: &'static str = "remember_me";
 
     pub fn renew(&self) {
         self.0.renew();
@@ -21,6 +30,85 @@ impl TypedSession {
         self.0.get(Self::USER_ID_KEY)
     }
 
+    /// Id of the session's row in `user_sessions`, see `authentication::record_session`.
+    pub fn insert_session_id(&self, session_id: Uuid) -> Result<(), SessionInsertError> {
+        self.0.insert(Self::SESSION_ID_KEY, session_id)
+    }
+
+    pub fn get_session_id(&self) -> Result<Option<Uuid>, SessionGetError> {
+        self.0.get(Self::SESSION_ID_KEY)
+    }
+
+    /// Starts the session's timeouts, on login.
+    pub fn start(&self, remember_me: bool, now: DateTime<Utc>) -> Result<(), SessionInsertError> {
+        self.0.insert(Self::REMEMBER_ME_KEY, remember_me)?;
+        self.insert_time(Self::LOGGED_IN_AT_KEY, now)?;
+        self.insert_last_seen_at(now)?;
+        self.insert_authenticated_at(now)
+    }
+
+    pub fn get_remember_me(&self) -> Result<bool, SessionGetError> {
+        Ok(self.0.get(Self::REMEMBER_ME_KEY)?.unwrap_or(false))
+    }
+
+    pub fn get_logged_in_at(&self) -> Result<Option<DateTime<Utc>>, SessionGetError> {
+        self.get_time(Self::LOGGED_IN_AT_KEY)
+    }
+
+    /// Time of the session's last request, for the idle timeout.
+    pub fn insert_last_seen_at(&self, now: DateTime<Utc>) -> Result<(), SessionInsertError> {
+        self.insert_time(Self::LAST_SEEN_AT_KEY, now)
+    }
+
+    pub fn get_last_seen_at(&self) -> Result<Option<DateTime<Utc>>, SessionGetError> {
+        self.get_time(Self::LAST_SEEN_AT_KEY)
+    }
+
+    /// Last time the user typed their password in this session, on login or re-authentication.
+    pub fn insert_authenticated_at(&self, now: DateTime<Utc>) -> Result<(), SessionInsertError> {
+        self.insert_time(Self::AUTHENTICATED_AT_KEY, now)
+    }
+
+    pub fn get_authenticated_at(&self) -> Result<Option<DateTime<Utc>>, SessionGetError> {
+        self.get_time(Self::AUTHENTICATED_AT_KEY)
+    }
+
+    // Times are stored as unix timestamps in milliseconds.
+    fn insert_time(&self, key: &str, time: DateTime<Utc>) -> Result<(), SessionInsertError> {
+        self.0.insert(key, time.timestamp_millis())
+    }
+
+    fn get_time(&self, key: &str) -> Result<Option<DateTime<Utc>>, SessionGetError> {
+        Ok(self
+            .0
+            .get::<i64>(key)?
+            .and_then(DateTime::from_timestamp_millis))
+    }
+
+    pub fn get_csrf_token(&self) -> Result<Option<String>, SessionGetError> {
+        self.0.get(Self::CSRF_TOKEN_KEY)
+    }
+
+    /// Replaces the synchronizer token embedded in this session's forms, e.g. on login.
+    pub fn rotate_csrf_token(&self) -> Result<String, SessionInsertError> {
+        let mut rng = thread_rng();
+        let token: String = std::iter::repeat_with(|| rng.sample(Alphanumeric))
+            .map(char::from)
+            .take(32)
+            .collect();
+        self.0.insert(Self::CSRF_TOKEN_KEY, &token)?;
+        Ok(token)
+    }
+
+    /// Returns the synchronizer token to embed in this session's forms.
+    /// Sessions predating CSRF protection get one on first use.
+    pub fn get_or_create_csrf_token(&self) -> Result<String, anyhow::Error> {
+        match self.get_csrf_token()? {
+            Some(token) => Ok(token),
+            None => Ok(self.rotate_csrf_token()?),
+        }
+    }
+
     /// Logs out currently logged in user (session.purge()).
     pub fn log_out(self) {
         self.0.purge()
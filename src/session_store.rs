@@ -0,0 +1,239 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use actix_session::storage::{
+    LoadError, RedisSessionStore, SaveError, SessionKey, SessionStore, UpdateError,
+};
+use actix_web::cookie::time;
+use anyhow::Context;
+use rand::distributions::Alphanumeric;
+use rand::rngs::OsRng;
+use rand::Rng;
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+
+use crate::configuration::Settings;
+use crate::startup::get_connection_pool;
+use crate::utils::run_periodically;
+
+type SessionState = HashMap<String, String>;
+
+/// How often expired sessions are deleted.
+const CLEANUP_INTERVAL: Duration = Duration::from_secs(3600);
+
+/// The session store selected in the configuration.
+#[derive(Clone)]
+pub enum AppSessionStore {
+    Redis(RedisSessionStore),
+    Postgres(PgSessionStore),
+}
+
+impl SessionStore for AppSessionStore {
+    async fn load(&self, session_key: &SessionKey) -> Result<Option<SessionState>, LoadError> {
+        match self {
+            Self::Redis(store) => store.load(session_key).await,
+            Self::Postgres(store) => store.load(session_key).await,
+        }
+    }
+
+    async fn save(
+        &self,
+        session_state: SessionState,
+        ttl: &time::Duration,
+    ) -> Result<SessionKey, SaveError> {
+        match self {
+            Self::Redis(store) => store.save(session_state, ttl).await,
+            Self::Postgres(store) => store.save(session_state, ttl).await,
+        }
+    }
+
+    async fn update(
+        &self,
+        session_key: SessionKey,
+        session_state: SessionState,
+        ttl: &time::Duration,
+    ) -> Result<SessionKey, UpdateError> {
+        match self {
+            Self::Redis(store) => store.update(session_key, session_state, ttl).await,
+            Self::Postgres(store) => store.update(session_key, session_state, ttl).await,
+        }
+    }
+
+    async fn update_ttl(
+        &self,
+        session_key: &SessionKey,
+        ttl: &time::Duration,
+    ) -> Result<(), anyhow::Error> {
+        match self {
+            Self::Redis(store) => store.update_ttl(session_key, ttl).await,
+            Self::Postgres(store) => store.update_ttl(session_key, ttl).await,
+        }
+    }
+
+    async fn delete(&self, session_key: &SessionKey) -> Result<(), anyhow::Error> {
+        match self {
+            Self::Redis(store) => store.delete(session_key).await,
+            Self::Postgres(store) => store.delete(session_key).await,
+        }
+    }
+}
+
+/// Session store keeping session states in the `sessions` table, for deployments
+/// without redis.
+///
+/// States are JSON encoded like `RedisSessionStore` does. Expired rows are ignored
+/// on load and deleted by `run_session_cleanup_worker_until_stopped`.
+#[derive(Clone)]
+pub struct PgSessionStore {
+    pool: PgPool,
+}
+
+impl PgSessionStore {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+impl SessionStore for PgSessionStore {
+    async fn load(&self, session_key: &SessionKey) -> Result<Option<SessionState>, LoadError> {
+        let state = sqlx::query_scalar!(
+            "SELECT state FROM sessions WHERE key_hash = $1 AND expires_at > now()",
+            hash_key(session_key),
+        )
+        .fetch_optional(&self.pool)
+        .await
+        .context("Failed to load a session state.")
+        .map_err(LoadError::Other)?;
+        state
+            .map(|state| serde_json::from_str(&state))
+            .transpose()
+            .context("Failed to deserialize a session state.")
+            .map_err(LoadError::Deserialization)
+    }
+
+    async fn save(
+        &self,
+        session_state: SessionState,
+        ttl: &time::Duration,
+    ) -> Result<SessionKey, SaveError> {
+        let state = serde_json::to_string(&session_state)
+            .context("Failed to serialize a session state.")
+            .map_err(SaveError::Serialization)?;
+        let session_key = generate_session_key();
+        sqlx::query!(
+            r#"
+            INSERT INTO sessions (key_hash, state, expires_at)
+            VALUES ($1, $2, now() + $3::interval)
+            "#,
+            hash_key(&session_key),
+            state,
+            to_std(ttl) as Duration,
+        )
+        .execute(&self.pool)
+        .await
+        .context("Failed to save a session state.")
+        .map_err(SaveError::Other)?;
+        Ok(session_key)
+    }
+
+    async fn update(
+        &self,
+        session_key: SessionKey,
+        session_state: SessionState,
+        ttl: &time::Duration,
+    ) -> Result<SessionKey, UpdateError> {
+        let state = serde_json::to_string(&session_state)
+            .context("Failed to serialize a session state.")
+            .map_err(UpdateError::Serialization)?;
+        let n_updated = sqlx::query!(
+            r#"
+            UPDATE sessions
+            SET state = $2, expires_at = now() + $3::interval
+            WHERE key_hash = $1 AND expires_at > now()
+            "#,
+            hash_key(&session_key),
+            state,
+            to_std(ttl) as Duration,
+        )
+        .execute(&self.pool)
+        .await
+        .context("Failed to update a session state.")
+        .map_err(UpdateError::Other)?
+        .rows_affected();
+        if n_updated == 1 {
+            return Ok(session_key);
+        }
+        // The session expired since it was loaded, start a new one like `RedisSessionStore` does.
+        self.save(session_state, ttl).await.map_err(|e| match e {
+            SaveError::Serialization(e) => UpdateError::Serialization(e),
+            SaveError::Other(e) => UpdateError::Other(e),
+        })
+    }
+
+    async fn update_ttl(
+        &self,
+        session_key: &SessionKey,
+        ttl: &time::Duration,
+    ) -> Result<(), anyhow::Error> {
+        sqlx::query!(
+            "UPDATE sessions SET expires_at = now() + $2::interval WHERE key_hash = $1",
+            hash_key(session_key),
+            to_std(ttl) as Duration,
+        )
+        .execute(&self.pool)
+        .await
+        .context("Failed to update the TTL of a session.")?;
+        Ok(())
+    }
+
+    async fn delete(&self, session_key: &SessionKey) -> Result<(), anyhow::Error> {
+        sqlx::query!(
+            "DELETE FROM sessions WHERE key_hash = $1",
+            hash_key(session_key),
+        )
+        .execute(&self.pool)
+        .await
+        .context("Failed to delete a session.")?;
+        Ok(())
+    }
+}
+
+/// 64 alphanumeric characters from the OS' RNG, like actix-session's own stores.
+fn generate_session_key() -> SessionKey {
+    let key: String = std::iter::repeat_with(|| OsRng.sample(Alphanumeric))
+        .map(char::from)
+        .take(64)
+        .collect();
+    key.try_into()
+        .expect("64 alphanumeric characters are a valid session key.")
+}
+
+/// Keys are stored hashed so that a database dump doesn't let anyone take over a session.
+fn hash_key(session_key: &SessionKey) -> String {
+    hex::encode(Sha256::digest(session_key.as_ref().as_bytes()))
+}
+
+fn to_std(ttl: &time::Duration) -> Duration {
+    Duration::from_secs(ttl.whole_seconds().max(0) as u64)
+}
+
+/// Deletes the sessions that have expired.
+/// Returns the number of deleted sessions.
+#[tracing::instrument(skip(pool), fields(n_deleted_sessions = tracing::field::Empty), err)]
+pub async fn delete_expired_sessions(pool: &PgPool) -> Result<u64, anyhow::Error> {
+    let n_deleted_sessions = sqlx::query!("DELETE FROM sessions WHERE expires_at <= now()")
+        .execute(pool)
+        .await?
+        .rows_affected();
+    tracing::Span::current().record("n_deleted_sessions", n_deleted_sessions);
+    Ok(n_deleted_sessions)
+}
+
+/// Runs a loop that periodically deletes expired sessions from postgres.
+/// The table stays empty when sessions are stored in redis, which expires them itself.
+pub async fn run_session_cleanup_worker_until_stopped(
+    configuration: Settings,
+) -> Result<(), anyhow::Error> {
+    let pool = get_connection_pool(&configuration.database);
+    run_periodically(CLEANUP_INTERVAL, || delete_expired_sessions(&pool)).await
+}
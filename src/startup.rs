@@ -1,35 +1,72 @@
+use actix_session::config::{PersistentSession, TtlExtensionPolicy};
 use actix_session::storage::RedisSessionStore;
 use actix_session::SessionMiddleware;
 // startup.rs
+use actix_web::cookie::{time, Key, SameSite};
 use actix_web::dev::Server;
-use actix_web::{cookie::Key, web, App, HttpServer};
+use actix_web::{web, App, HttpServer};
 use actix_web_flash_messages::storage::CookieMessageStore;
 use actix_web_flash_messages::FlashMessagesFramework;
 use actix_web_lab::middleware::from_fn;
+use anyhow::Context;
 use secrecy::{ExposeSecret, Secret};
 use sqlx::postgres::PgPoolOptions;
 use sqlx::PgPool;
 use std::net::TcpListener;
 use tracing_actix_web::TracingLogger;
 
-use crate::authentication::reject_anonymous_users;
-use crate::configuration::{DatabaseSettings, Settings};
+use crate::authentication::{
+    reject_anonymous_users, reject_invalid_api_tokens, reject_invalid_csrf_tokens, PasswordPolicy,
+};
+use crate::client_ip::TrustedProxies;
+use crate::configuration::{
+    DatabaseSettings, HealthSettings, IdempotencySettings, PasswordHashingSettings,
+    SessionSettings, SessionStoreKind, Settings, SubscriptionSettings,
+};
 use crate::email_client::EmailClient;
+use crate::email_domains::EmailDomainValidator;
+use crate::idempotency::Idempotency;
+use crate::metrics::{metrics, record_http_metrics};
+use crate::migrations::{check_email_normalization, check_schema_version, run_migrations};
+use crate::request_id::{propagate_request_id, RequestIdRootSpanBuilder};
 use crate::routes::{
-    admin_dashboard, change_password, change_password_form, confirm, health_check, home, log_out,
-    login, login_form, newsletter_form,
+    admin_api_tokens, admin_audit_log, admin_dashboard, admin_sessions, api_create_issue,
+    api_get_issue, api_idempotency, api_json_config, api_list_issues, api_path_config,
+    api_publish_issue, api_query_config, api_schedule_issue, api_update_issue, change_password,
+    change_password_form, confirm, create_admin_api_token, export_audit_log, get_log_filter,
+    health_check, health_live, health_ready, home, log_out, login, login_form, newsletter_form,
+    newsletter_published, preview_newsletter, reauthenticate, reauthenticate_form,
+    reject_large_publishes_without_reauthentication, revoke_admin_api_token, revoke_admin_session,
+    revoke_other_admin_sessions, RedisClient,
 };
-use crate::routes::{publish_newsletter, subscribe};
+use crate::routes::{publish_newsletter, set_log_filter, subscribe};
+use crate::security_headers::{set_security_headers, SecurityHeaders};
+use crate::session_store::{AppSessionStore, PgSessionStore};
 
 pub struct Application {
     port: u16,
     server: Server,
+    // Set when /metrics is served on its own port.
+    metrics_port: Option<u16>,
+    metrics_server: Option<Server>,
 }
 
 impl Application {
     pub async fn build(configuration: Settings) -> Result<Self, anyhow::Error> {
         let connection_pool = get_connection_pool(&configuration.database);
 
+        // ------------- Bring the DB schema up to date
+        if configuration.database.migrate_on_startup {
+            run_migrations(&connection_pool).await?;
+        } else {
+            check_schema_version(&connection_pool).await?;
+        }
+        check_email_normalization(
+            &connection_pool,
+            configuration.subscriptions.email_normalization(),
+        )
+        .await?;
+
         // ------------- Setup EmailClient
         let sender_email = configuration
             .email_client
@@ -41,6 +78,17 @@ impl Application {
             configuration.email_client.authorization_token,
         );
 
+        // ------------- Setup the subscriber email domain checks
+        let email_domain_validator =
+            EmailDomainValidator::from_settings(&configuration.subscriptions)?;
+
+        // ------------- Setup the security headers, HSTS is production only
+        let security_headers = SecurityHeaders::from_settings(
+            &configuration.security_headers,
+            configuration.environment,
+        )?;
+        let password_policy = PasswordPolicy::from_settings(&configuration.password_policy);
+
         //-------------- Setup TCPListener
         let address = format!(
             "{}:{}",
@@ -48,25 +96,61 @@ impl Application {
         );
         let listener = TcpListener::bind(address).expect("Failed to bind to port");
         let port = listener.local_addr().unwrap().port();
+
+        //-------------- Setup the metrics server, if it has its own port
+        let (metrics_port, metrics_server) = match configuration.metrics.port {
+            Some(metrics_port) => {
+                let address = format!("{}:{}", configuration.application.host, metrics_port);
+                let listener = TcpListener::bind(address).expect("Failed to bind metrics port");
+                let metrics_port = listener.local_addr().unwrap().port();
+                let metrics_server = run_metrics_server(listener, connection_pool.clone())?;
+                (Some(metrics_port), Some(metrics_server))
+            }
+            None => (None, None),
+        };
+
         let server = run(
             listener,
             connection_pool,
             email_client,
             configuration.application.base_url,
             configuration.application.hmac_secret,
+            TrustedProxies(configuration.application.trusted_proxies),
             configuration.redis_uri,
+            configuration.session,
+            configuration.password_hashing,
+            configuration.idempotency,
+            configuration.health,
+            configuration.subscriptions,
+            email_domain_validator,
+            security_headers,
+            password_policy,
+            metrics_server.is_none(),
         )
         .await?;
 
-        Ok(Self { port, server })
+        Ok(Self {
+            port,
+            server,
+            metrics_port,
+            metrics_server,
+        })
     }
 
     pub fn port(&self) -> u16 {
         self.port
     }
 
+    /// Port /metrics is served on, if it's not the application's.
+    pub fn metrics_port(&self) -> Option<u16> {
+        self.metrics_port
+    }
+
     pub async fn run_until_stopped(self) -> Result<(), std::io::Error> {
-        self.server.await
+        match self.metrics_server {
+            Some(metrics_server) => tokio::try_join!(self.server, metrics_server).map(|_| ()),
+            None => self.server.await,
+        }
     }
 }
 
@@ -81,60 +165,207 @@ pub struct ApplicationBaseUrl(pub String);
 ///  Currently supported routes
 ///   - / -> home page
 ///   - /health_check -> returns OK and an empty body.
+///   - /health/live, /health/ready -> liveness and readiness probes.
 ///   - /subscriptions -> add a new subscriber to newsletter.
 ///   - /newsletters -> newsletter publishing
 ///   - /login -> login flow
 ///   - /admin -> admin dashboard
 ///   - /admin/password -> password change flow
+///   - /admin/newsletter/preview -> renders an issue without publishing it
+///   - /admin/newsletter/published -> confirms a publish, then back to the form
+///   - /admin/reauthenticate -> asks for the password again before sensitive actions
+///   - /admin/sessions -> lists and revokes the user's logged in sessions
+///   - /admin/log_filter -> read or change the log filter at runtime
+///   - /admin/audit -> lists who did what and when, /admin/audit.csv exports it
+///   - /admin/api_tokens -> creates and revokes the user's API tokens
+///   - /api/v1/issues -> drafts, schedules and publishes issues as JSON, with an
+///     `Authorization: Bearer` API token
+///   - /metrics -> prometheus metrics, only if they have no port of their own.
+#[allow(clippy::too_many_arguments)]
 pub async fn run(
     listener: TcpListener,
     db_pool: PgPool,
     email_client: EmailClient,
     base_url: String,
     hmac_secret: Secret<String>,
+    trusted_proxies: TrustedProxies,
     redis_uri: Secret<String>,
+    session_settings: SessionSettings,
+    password_hashing: PasswordHashingSettings,
+    idempotency_settings: IdempotencySettings,
+    health_settings: HealthSettings,
+    subscription_settings: SubscriptionSettings,
+    email_domain_validator: EmailDomainValidator,
+    security_headers: SecurityHeaders,
+    password_policy: PasswordPolicy,
+    serve_metrics: bool,
 ) -> Result<Server, anyhow::Error> {
     // Wrap the pool using Web::Data which boils down to an Arc smart pointer.
     let db_pool = web::Data::new(db_pool);
     let email_client = web::Data::new(email_client);
     let base_url = web::Data::new(ApplicationBaseUrl(base_url));
+    let idempotency_settings = web::Data::new(idempotency_settings);
+    let health_settings = web::Data::new(health_settings);
+    let subscription_settings = web::Data::new(subscription_settings);
+    let email_domain_validator = web::Data::new(email_domain_validator);
+    let security_headers = web::Data::new(security_headers);
+    let password_policy = web::Data::new(password_policy);
+    let trusted_proxies = web::Data::new(trusted_proxies);
 
     // Setup Flash Message middleware
     let secret_key = Key::from(hmac_secret.expose_secret().as_bytes());
     let message_store = CookieMessageStore::builder(secret_key.clone()).build();
     let message_framework = FlashMessagesFramework::builder(message_store).build();
-    // Setup redis session store.
-    let redis_store = RedisSessionStore::new(redis_uri.expose_secret()).await?;
+    // Setup the session store.
+    let (session_store, redis_client) = match session_settings.store {
+        SessionStoreKind::Redis => {
+            let redis_store = RedisSessionStore::new(redis_uri.expose_secret()).await?;
+            // Separate client for the readiness probe, the store doesn't expose its connection.
+            let redis_client = web::Data::new(RedisClient(redis::Client::open(
+                redis_uri.expose_secret().as_str(),
+            )?));
+            (AppSessionStore::Redis(redis_store), Some(redis_client))
+        }
+        SessionStoreKind::Postgres => (
+            AppSessionStore::Postgres(PgSessionStore::new(db_pool.get_ref().clone())),
+            None,
+        ),
+    };
+    // Session states and cookies outlive every timeout, which `reject_anonymous_users` enforces.
+    let session_lifecycle = PersistentSession::default()
+        .session_ttl(time::Duration::seconds(
+            session_settings.state_ttl().as_secs() as i64,
+        ))
+        .session_ttl_extension_policy(TtlExtensionPolicy::OnEveryRequest);
+    let session_settings = web::Data::new(session_settings);
+    // Fail on startup rather than on the first login.
+    password_hashing
+        .params()
+        .context("Invalid password hashing parameters.")?;
+    let password_hashing = web::Data::new(password_hashing);
     // Capture `connection` from the surrounding environment
     let server = HttpServer::new(move || {
         App::new()
+            .wrap(from_fn(set_security_headers))
             .wrap(message_framework.clone())
-            .wrap(SessionMiddleware::new(
-                redis_store.clone(),
-                secret_key.clone(),
-            ))
-            .wrap(TracingLogger::default())
+            .wrap(
+                SessionMiddleware::builder(session_store.clone(), secret_key.clone())
+                    // The session cookie is never sent along cross-site requests.
+                    .cookie_same_site(SameSite::Strict)
+                    .session_lifecycle(session_lifecycle.clone())
+                    .build(),
+            )
+            .wrap(from_fn(propagate_request_id))
+            .wrap(TracingLogger::<RequestIdRootSpanBuilder>::new())
+            .wrap(from_fn(record_http_metrics))
             .route("/", web::get().to(home))
             .service(
                 web::scope("/admin")
+                    // The last middleware registered runs first: anonymous users are
+                    // redirected to /login before their CSRF token is checked.
+                    .wrap(from_fn(reject_invalid_csrf_tokens))
                     .wrap(from_fn(reject_anonymous_users))
                     .route("/dashboard", web::get().to(admin_dashboard))
+                    .route("/api_tokens", web::get().to(admin_api_tokens))
+                    .route("/api_tokens", web::post().to(create_admin_api_token))
+                    .route(
+                        "/api_tokens/{token_id}/revoke",
+                        web::post().to(revoke_admin_api_token),
+                    )
+                    .route("/audit", web::get().to(admin_audit_log))
+                    .route("/audit.csv", web::get().to(export_audit_log))
                     .route("/password", web::get().to(change_password_form))
                     .route("/password", web::post().to(change_password))
-                    .route("/newsletter", web::get().to(newsletter_form))
-                    .route("/newsletter", web::post().to(publish_newsletter))
+                    .service(
+                        web::resource("/newsletter")
+                            .route(web::get().to(newsletter_form))
+                            .route(web::post().to(publish_newsletter))
+                            .wrap(Idempotency::new().require_key())
+                            // Runs before `Idempotency`, the redirect mustn't be replayed.
+                            .wrap(from_fn(reject_large_publishes_without_reauthentication)),
+                    )
+                    .route("/newsletter/published", web::get().to(newsletter_published))
+                    .route("/newsletter/preview", web::post().to(preview_newsletter))
                     // .route("/newsletter", web::post().to(post_newsletter))
-                    .route("/logout", web::post().to(log_out)),
+                    .route("/reauthenticate", web::get().to(reauthenticate_form))
+                    .route("/reauthenticate", web::post().to(reauthenticate))
+                    .route("/logout", web::post().to(log_out))
+                    .route("/sessions", web::get().to(admin_sessions))
+                    .route(
+                        "/sessions/revoke_others",
+                        web::post().to(revoke_other_admin_sessions),
+                    )
+                    .route(
+                        "/sessions/{session_id}/revoke",
+                        web::post().to(revoke_admin_session),
+                    )
+                    .route("/log_filter", web::get().to(get_log_filter))
+                    .route("/log_filter", web::put().to(set_log_filter)),
+            )
+            .service(
+                web::scope("/api/v1")
+                    .wrap(from_fn(reject_invalid_api_tokens))
+                    .app_data(api_json_config())
+                    .app_data(api_path_config())
+                    .app_data(api_query_config())
+                    .service(
+                        web::resource("/issues")
+                            .route(web::get().to(api_list_issues))
+                            .route(web::post().to(api_create_issue))
+                            .wrap(api_idempotency().require_key()),
+                    )
+                    .service(
+                        web::resource("/issues/{issue_id}")
+                            .route(web::get().to(api_get_issue))
+                            .route(web::patch().to(api_update_issue))
+                            .wrap(api_idempotency()),
+                    )
+                    .service(
+                        web::resource("/issues/{issue_id}/publish")
+                            .route(web::post().to(api_publish_issue))
+                            .wrap(api_idempotency().require_key()),
+                    )
+                    .service(
+                        web::resource("/issues/{issue_id}/schedule")
+                            .route(web::post().to(api_schedule_issue))
+                            .wrap(api_idempotency().require_key()),
+                    ),
             )
             .route("/login", web::get().to(login_form))
             .route("/login", web::post().to(login))
             .route("/health_check", web::get().to(health_check))
-            .route("/subscriptions", web::post().to(subscribe))
+            .route("/health/live", web::get().to(health_live))
+            .route("/health/ready", web::get().to(health_ready))
+            .configure(|cfg| {
+                if serve_metrics {
+                    cfg.route("/metrics", web::get().to(metrics));
+                }
+            })
+            .service(
+                web::resource("/subscriptions")
+                    .route(web::post().to(subscribe))
+                    .wrap(Idempotency::new()),
+            )
             .route("/subscriptions/confirm", web::get().to(confirm))
             // Get a pointer copy and attach it to the application state
             .app_data(db_pool.clone())
             .app_data(email_client.clone())
             .app_data(base_url.clone())
+            .app_data(idempotency_settings.clone())
+            .app_data(health_settings.clone())
+            .app_data(subscription_settings.clone())
+            .app_data(email_domain_validator.clone())
+            .app_data(security_headers.clone())
+            .app_data(session_settings.clone())
+            .app_data(password_hashing.clone())
+            .app_data(password_policy.clone())
+            .app_data(trusted_proxies.clone())
+            .configure(|cfg| {
+                // Only checked by the readiness probe when sessions are stored in redis.
+                if let Some(redis_client) = &redis_client {
+                    cfg.app_data(redis_client.clone());
+                }
+            })
             .app_data(web::Data::new(HmacSecret(hmac_secret.clone())))
     })
     .listen(listener)?
@@ -142,5 +373,18 @@ pub async fn run(
     Ok(server)
 }
 
+/// Returns an HTTP server only serving /metrics, for when metrics get their own port.
+pub fn run_metrics_server(listener: TcpListener, db_pool: PgPool) -> Result<Server, anyhow::Error> {
+    let db_pool = web::Data::new(db_pool);
+    let server = HttpServer::new(move || {
+        App::new()
+            .route("/metrics", web::get().to(metrics))
+            .app_data(db_pool.clone())
+    })
+    .listen(listener)?
+    .run();
+    Ok(server)
+}
+
 #[derive(Clone)]
 pub struct HmacSecret(pub Secret<String>);
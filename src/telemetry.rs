@@ -1,23 +1,86 @@
+use std::collections::HashMap;
+
+use once_cell::sync::OnceCell;
+use opentelemetry::trace::{TraceContextExt, TraceError, TracerProvider as _};
+use opentelemetry::{global, KeyValue};
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::trace::{Config, Tracer, TracerProvider};
+use opentelemetry_sdk::{runtime, Resource};
 use tokio::task::JoinHandle;
-use tracing::{subscriber::set_global_default, Subscriber};
+use tracing::{subscriber::set_global_default, Span, Subscriber};
+use tracing_appender::rolling::{RollingFileAppender, Rotation};
 use tracing_bunyan_formatter::{BunyanFormattingLayer, JsonStorageLayer};
 use tracing_log::LogTracer;
-use tracing_subscriber::{fmt::MakeWriter, layer::SubscriberExt, EnvFilter, Registry};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::fmt::writer::{BoxMakeWriter, MakeWriterExt};
+use tracing_subscriber::{fmt, fmt::MakeWriter, layer::SubscriberExt, reload, EnvFilter, Registry};
+
+use crate::configuration::{LogFileSettings, LogFormat, LogRotation, TelemetrySettings};
+
+/// W3C trace context header.
+const TRACEPARENT: &str = "traceparent";
+
+/// Handle on the filter of the global subscriber, set by `init_subscriber`.
+static LOG_FILTER: OnceCell<LogFilterHandle> = OnceCell::new();
+
+/// Changes the `EnvFilter` of a running subscriber.
+#[derive(Clone)]
+pub struct LogFilterHandle {
+    handle: reload::Handle<EnvFilter, Registry>,
+}
+
+impl LogFilterHandle {
+    /// Returns the current filter directives, e.g. "info,sqlx=debug".
+    pub fn current(&self) -> Result<String, anyhow::Error> {
+        Ok(self.handle.with_current(|filter| filter.to_string())?)
+    }
+
+    /// Replaces the filter with `directives`, e.g. "info,sqlx=debug".
+    /// Fails without touching the filter if they're invalid.
+    pub fn reload(&self, directives: &str) -> Result<(), LogFilterError> {
+        let filter = EnvFilter::try_new(directives)
+            .map_err(|e| LogFilterError::InvalidDirectives(e.into()))?;
+        self.handle
+            .reload(filter)
+            .map_err(|e| LogFilterError::UnexpectedError(e.into()))
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum LogFilterError {
+    #[error("Invalid filter directives: {0}")]
+    InvalidDirectives(anyhow::Error),
+    #[error(transparent)]
+    UnexpectedError(anyhow::Error),
+}
+
+/// Returns the handle on the global subscriber's filter, if it was set up with
+/// `init_subscriber`.
+pub fn log_filter() -> Option<&'static LogFilterHandle> {
+    LOG_FILTER.get()
+}
 
 /// Compose multiple layers into a `tracing`'s Subscriber.
 ///
 ///  Params:
 ///    - name -> formatting layer's name
 ///    - env_filter -> env filter string, .e.g. "info" to be used by default.
+///    - settings -> output format, log file and OTLP collector to export spans to.
+///
+///  Spans always carry an OpenTelemetry context, so that it can be propagated
+///  even when they're not exported. Must be called from within a tokio runtime.
 ///
 ///  
 ///  Note on return type, we're using `impl Subscriber` as a return type to avoid
 ///  spelling out a cmplex type. It must be marked as Send and Sync to be used by init subscriber.
+///  The filter can be changed at runtime through the returned handle.
 pub fn get_subscriber<Sink>(
     name: String,
     env_filter: String,
+    settings: &TelemetrySettings,
     sink: Sink,
-) -> impl Subscriber + Send + Sync
+) -> (impl Subscriber + Send + Sync, LogFilterHandle)
 where
     // This "weird syntax is a higher-ranked trait bound (HRTB)
     // It basically means that Sink Implements the MakeWriter trait for all choces
@@ -27,21 +90,119 @@ where
 {
     let env_filter =
         EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(env_filter));
-    // Dump spans into stdout.
-    let formatting_layer = BunyanFormattingLayer::new(name, sink);
+    let (env_filter, handle) = reload::Layer::new(env_filter);
+    // Dump spans into the sink, and the log file if any.
+    let writer = match &settings.log_file {
+        Some(log_file) => BoxMakeWriter::new(sink.and(file_appender(log_file))),
+        None => BoxMakeWriter::new(sink),
+    };
+    // Colours would end up as escape codes in files.
+    let ansi = settings.log_file.is_none();
+    // Only one of these is set, depending on the format.
+    let (bunyan_layer, pretty_layer, compact_layer) = match settings.format {
+        LogFormat::Json => (
+            Some(BunyanFormattingLayer::new(name.clone(), writer)),
+            None,
+            None,
+        ),
+        LogFormat::Pretty => (
+            None,
+            Some(fmt::layer().pretty().with_ansi(ansi).with_writer(writer)),
+            None,
+        ),
+        LogFormat::Compact => (
+            None,
+            None,
+            Some(fmt::layer().compact().with_ansi(ansi).with_writer(writer)),
+        ),
+    };
+    // Export spans to an OpenTelemetry collector.
+    let tracer = get_tracer(name, settings.otlp_endpoint.clone())
+        .expect("Failed to build the OTLP exporter.");
+    let opentelemetry_layer = tracing_opentelemetry::layer().with_tracer(tracer);
 
-    Registry::default()
+    let subscriber = Registry::default()
         .with(env_filter)
-        .with(JsonStorageLayer)
-        .with(formatting_layer)
+        .with(bunyan_layer.is_some().then_some(JsonStorageLayer))
+        .with(bunyan_layer)
+        .with(pretty_layer)
+        .with(compact_layer)
+        .with(opentelemetry_layer);
+    (subscriber, LogFilterHandle { handle })
+}
+
+fn file_appender(settings: &LogFileSettings) -> RollingFileAppender {
+    let rotation = match settings.rotation {
+        LogRotation::Minutely => Rotation::MINUTELY,
+        LogRotation::Hourly => Rotation::HOURLY,
+        LogRotation::Daily => Rotation::DAILY,
+        LogRotation::Never => Rotation::NEVER,
+    };
+    RollingFileAppender::new(rotation, &settings.directory, &settings.prefix)
+}
+
+/// Returns a tracer batching spans to `otlp_endpoint`, or one that drops them.
+/// Its provider is installed globally, as tracers don't keep it alive.
+fn get_tracer(name: String, otlp_endpoint: Option<String>) -> Result<Tracer, TraceError> {
+    let config = Config::default()
+        .with_resource(Resource::new([KeyValue::new("service.name", name.clone())]));
+    let tracer = match otlp_endpoint {
+        Some(endpoint) => opentelemetry_otlp::new_pipeline()
+            .tracing()
+            .with_exporter(
+                opentelemetry_otlp::new_exporter()
+                    .http()
+                    .with_endpoint(endpoint),
+            )
+            .with_trace_config(config)
+            .install_batch(runtime::Tokio)?,
+        None => {
+            let provider = TracerProvider::builder().with_config(config).build();
+            let tracer = provider.tracer(name);
+            global::set_tracer_provider(provider);
+            tracer
+        }
+    };
+    Ok(tracer)
 }
 
-pub fn init_subscriber(subscriber: impl Subscriber + Send + Sync) {
+pub fn init_subscriber(subscriber: impl Subscriber + Send + Sync, log_filter: LogFilterHandle) {
     // Redirect all `log`'s events to our subscriber
     LogTracer::init().expect("Failed to init LogTracer");
+    // Let the admin endpoint change the filter.
+    let _ = LOG_FILTER.set(log_filter);
+    // Read and write W3C `traceparent` headers.
+    global::set_text_map_propagator(TraceContextPropagator::new());
     set_global_default(subscriber).expect("Failed to set tracing subscriber.");
 }
 
+/// Flushes pending spans to the OTLP collector.
+/// Blocks, must not be called from a current thread tokio runtime.
+pub fn shutdown_tracing() {
+    global::shutdown_tracer_provider();
+}
+
+/// Returns the W3C `traceparent` of the current span, to carry its trace to
+/// another process, e.g. along with a queued task.
+pub fn current_traceparent() -> Option<String> {
+    let mut carrier = HashMap::new();
+    global::get_text_map_propagator(|propagator| {
+        propagator.inject_context(&Span::current().context(), &mut carrier)
+    });
+    carrier.remove(TRACEPARENT)
+}
+
+/// Links the current span to the span `traceparent` was taken from.
+/// Invalid trace contexts are ignored.
+pub fn link_to_traceparent(traceparent: &str) {
+    let carrier = HashMap::from([(TRACEPARENT.to_string(), traceparent.to_string())]);
+    let context = global::get_text_map_propagator(|propagator| propagator.extract(&carrier));
+    let span_context = context.span().span_context().clone();
+    if span_context.is_valid() {
+        Span::current().add_link(span_context);
+    }
+}
+
 /// Kicks off a tokio blocking task in the current span.
 pub fn spawn_blocking_with_tracing<F, R>(f: F) -> JoinHandle<R>
 where
@@ -1,11 +1,27 @@
-use actix_web::{http::header::LOCATION, HttpResponse};
+use std::future::Future;
+use std::time::Duration;
+
+use actix_web::error::InternalError;
+use actix_web::http::header::{Accept, ContentType, Header};
+use actix_web::{http::header::LOCATION, web, HttpRequest, HttpResponse};
+
+use crate::request_id::current_request_id;
 
 /// Return an opaque 500 while perserving the error's root for logging.
+/// The page carries the request id, for users to quote when reporting it.
 pub fn e500<T>(e: T) -> actix_web::Error
 where
     T: std::fmt::Debug + std::fmt::Display + 'static,
 {
-    actix_web::error::ErrorInternalServerError(e)
+    match current_request_id() {
+        Some(request_id) => {
+            let response = HttpResponse::InternalServerError()
+                .content_type(ContentType::plaintext())
+                .body(format!("{}\nRequest id: {}", e, request_id));
+            InternalError::from_response(e, response).into()
+        }
+        None => actix_web::error::ErrorInternalServerError(e),
+    }
 }
 
 /// Return a 400 with the user-representation of the validation error as body.
@@ -22,3 +38,37 @@ pub fn see_other(location: &str) -> HttpResponse {
         .insert_header((LOCATION, location))
         .finish()
 }
+
+/// Whether the client would rather get a page than a bare status code, i.e. it's a browser.
+/// Programmatic clients usually send `*/*`, or no Accept header at all.
+pub fn prefers_html(request: &HttpRequest) -> bool {
+    Accept::parse(request)
+        .ok()
+        .and_then(|accept| accept.ranked().into_iter().next())
+        .is_some_and(|mime| mime.essence_str() == "text/html")
+}
+
+/// Turns a buffered body back into a payload, for middlewares that need to read
+/// the body before the handler does.
+pub fn bytes_to_payload(body: web::Bytes) -> actix_web::dev::Payload {
+    let (_, mut payload) = actix_http::h1::Payload::create(true);
+    payload.unread_data(body);
+    payload.into()
+}
+
+/// Runs `task` every `interval`, forever, for the background workers.
+/// A failed run isn't fatal: the task's `tracing::instrument(err)` span logs the error,
+/// and the next run tries again.
+pub async fn run_periodically<F, Fut, T>(
+    interval: Duration,
+    mut task: F,
+) -> Result<(), anyhow::Error>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, anyhow::Error>>,
+{
+    loop {
+        let _ = task().await;
+        tokio::time::sleep(interval).await;
+    }
+}
@@ -57,3 +57,41 @@ async fn admin_dashboard_has_link_to_send_a_newsletter() {
     // Verify - There's a link to sending a newsletter.
     assert!(html_page.contains("Send a newsletter issue"));
 }
+
+#[tokio::test]
+async fn admin_pages_share_the_navigation() {
+    // Arrange
+    let app = spawn_app().await;
+    app.log_in().await;
+
+    for html_page in [
+        app.get_admin_dashboard_html().await,
+        app.get_change_password_html().await,
+        app.get_publish_newsletter_html().await,
+    ] {
+        // Assert
+        assert!(html_page.contains(r#"<a href="/admin/dashboard">Dashboard</a>"#));
+        assert!(html_page.contains(r#"action="/admin/logout""#));
+    }
+}
+
+#[tokio::test]
+async fn the_username_is_escaped_on_the_admin_dashboard() {
+    // Arrange
+    let app = spawn_app().await;
+    app.log_in().await;
+    sqlx::query!(
+        "UPDATE users SET username = '<script>alert(1)</script>' WHERE user_id = $1",
+        app.test_user.user_id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    // Act
+    let html_page = app.get_admin_dashboard_html().await;
+
+    // Assert
+    assert!(html_page.contains("Welcome &lt;script&gt;alert(1)&lt;/script&gt;!"));
+    assert!(!html_page.contains("<script>"));
+}
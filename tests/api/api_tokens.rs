@@ -0,0 +1,385 @@
+use std::time::Duration;
+
+use uuid::Uuid;
+use zero2prod2::configuration::SessionStoreKind;
+
+use crate::spawn_app::{assert_is_redirect_to, spawn_app, spawn_app_with, TestApp};
+
+async fn get_api_tokens_html(app: &TestApp) -> String {
+    app.api_client
+        .get(format!("{}/admin/api_tokens", &app.address))
+        .send()
+        .await
+        .expect("Failed to execute request.")
+        .text()
+        .await
+        .unwrap()
+}
+
+async fn api_create_issue(app: &TestApp, authorization: Option<&str>) -> reqwest::Response {
+    let mut request = app
+        .api_client
+        .post(format!("{}/api/v1/issues", &app.address))
+        .header("Idempotency-Key", Uuid::new_v4().to_string())
+        .json(&serde_json::json!({
+            "title": "Newsletter title",
+            "text_content": "Newsletter body as plain text",
+            "html_content": "<p>Newsletter body as HTML</p>",
+        }));
+    if let Some(authorization) = authorization {
+        request = request.header("Authorization", authorization);
+    }
+    request.send().await.expect("Failed to execute request.")
+}
+
+async fn count_issues(app: &TestApp) -> i64 {
+    sqlx::query_scalar!(r#"SELECT COUNT(*) AS "count!" FROM newsletter_issues"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn tokens_publish_issues_on_behalf_of_their_owner() {
+    // Arrange
+    let app = spawn_app().await;
+    app.log_in().await;
+    let token = app
+        .create_api_token(&["issues:write", "newsletters:publish"])
+        .await;
+    let response = api_create_issue(&app, Some(&format!("Bearer {}", token))).await;
+    assert_eq!(response.status().as_u16(), 201);
+    let body: serde_json::Value = response.json().await.unwrap();
+    let issue_id = body["id"].as_str().unwrap().to_owned();
+
+    // Act
+    let response = app
+        .api_client
+        .post(format!(
+            "{}/api/v1/issues/{}/publish",
+            &app.address, issue_id
+        ))
+        .bearer_auth(&token)
+        .header("Idempotency-Key", Uuid::new_v4().to_string())
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let actor = sqlx::query_scalar!(
+        "SELECT actor_id FROM audit_log WHERE action = 'newsletter_publish' AND target = $1",
+        issue_id,
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(actor, Some(app.test_user.user_id));
+}
+
+#[tokio::test]
+async fn tokens_are_stored_hashed_and_shown_once() {
+    // Arrange
+    let app = spawn_app().await;
+    app.log_in().await;
+
+    // Act
+    let token = app.create_api_token(&["issues:write"]).await;
+
+    // Assert
+    assert!(token.starts_with("zp_"));
+    let token_hash: String = sqlx::query_scalar!("SELECT token_hash FROM api_tokens")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_ne!(token_hash, token);
+    let html = get_api_tokens_html(&app).await;
+    assert!(html.contains("<td>CMS</td>"));
+    assert!(!html.contains(&token));
+}
+
+#[tokio::test]
+async fn requests_without_a_valid_token_are_rejected_with_a_401() {
+    // Arrange
+    let app = spawn_app().await;
+    // A session cookie isn't enough.
+    app.log_in().await;
+
+    for authorization in [None, Some("Bearer zp_not-a-token"), Some("Basic YTpi")] {
+        // Act
+        let response = api_create_issue(&app, authorization).await;
+
+        // Assert
+        assert_eq!(response.status().as_u16(), 401, "{:?}", authorization);
+        let challenge = response.headers()["WWW-Authenticate"].to_str().unwrap();
+        assert!(challenge.starts_with("Bearer"));
+        let body: serde_json::Value = response.json().await.unwrap();
+        assert!(body["error"]["code"].is_string());
+        assert!(body["error"]["request_id"].is_string());
+    }
+    assert_eq!(count_issues(&app).await, 0);
+}
+
+#[tokio::test]
+async fn tokens_without_the_scope_are_rejected_with_a_403() {
+    // Arrange
+    let app = spawn_app().await;
+    app.log_in().await;
+    let token = app.create_api_token(&["issues:read"]).await;
+
+    // Act
+    let response = api_create_issue(&app, Some(&format!("Bearer {}", token))).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 403);
+    let challenge = response.headers()["WWW-Authenticate"].to_str().unwrap();
+    assert!(challenge.contains(r#"scope="issues:write""#));
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["error"]["code"], "insufficient_scope");
+    assert!(body["error"]["request_id"].is_string());
+    assert_eq!(count_issues(&app).await, 0);
+}
+
+#[tokio::test]
+async fn requests_rejected_for_a_missing_scope_can_be_retried_with_the_same_key() {
+    // Arrange
+    let app = spawn_app().await;
+    app.log_in().await;
+    let read_token = app.create_api_token(&["issues:read"]).await;
+    let write_token = app.create_api_token(&["issues:write"]).await;
+    let key = Uuid::new_v4().to_string();
+    let create_issue = |token: &str| {
+        app.api_client
+            .post(format!("{}/api/v1/issues", &app.address))
+            .header("Idempotency-Key", &key)
+            .header("Authorization", format!("Bearer {}", token))
+            .json(&serde_json::json!({
+                "title": "Newsletter title",
+                "text_content": "Newsletter body as plain text",
+                "html_content": "<p>Newsletter body as HTML</p>",
+            }))
+            .send()
+    };
+
+    // Act - Part 1 - Without the scope
+    let response = create_issue(&read_token).await.unwrap();
+    assert_eq!(response.status().as_u16(), 403);
+
+    // Act - Part 2 - Retried with it
+    let response = create_issue(&write_token).await.unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 201);
+    assert_eq!(count_issues(&app).await, 1);
+}
+
+#[tokio::test]
+async fn expired_tokens_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    app.log_in().await;
+    let token = app.create_api_token(&["issues:write"]).await;
+    sqlx::query!("UPDATE api_tokens SET expires_at = now() - interval '1 second'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    // Act
+    let response = api_create_issue(&app, Some(&format!("Bearer {}", token))).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn revoked_tokens_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    app.log_in().await;
+    let token = app.create_api_token(&["issues:write"]).await;
+    let token_id: Uuid = sqlx::query_scalar!("SELECT token_id FROM api_tokens")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+
+    // Act - Part 1 - Revoke
+    let response = app
+        .api_client
+        .post(format!(
+            "{}/admin/api_tokens/{}/revoke",
+            &app.address, token_id
+        ))
+        .form(&app.with_csrf_token(&serde_json::json!({})).await)
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    assert_is_redirect_to(&response, "/admin/api_tokens");
+
+    // Act - Part 2 - Use
+    let response = api_create_issue(&app, Some(&format!("Bearer {}", token))).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 401);
+    let html = get_api_tokens_html(&app).await;
+    assert!(html.contains("The API token has been revoked."));
+}
+
+#[tokio::test]
+async fn token_changes_that_cannot_be_audited_are_rolled_back() {
+    // Arrange
+    let app = spawn_app().await;
+    app.log_in().await;
+    app.create_api_token(&["issues:read"]).await;
+    let token_id: Uuid = sqlx::query_scalar!("SELECT token_id FROM api_tokens")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    sqlx::query!(
+        "ALTER TABLE audit_log ADD CONSTRAINT no_token_changes \
+         CHECK (action NOT IN ('api_token_create', 'api_token_revoke')) NOT VALID"
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    // Act - Part 1 - Create
+    let response = app.post_api_token("CMS", &["issues:read"]).await;
+    assert_eq!(response.status().as_u16(), 500);
+
+    // Act - Part 2 - Revoke
+    let response = app
+        .api_client
+        .post(format!(
+            "{}/admin/api_tokens/{}/revoke",
+            &app.address, token_id
+        ))
+        .form(&app.with_csrf_token(&serde_json::json!({})).await)
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    assert_eq!(response.status().as_u16(), 500);
+
+    // Assert
+    let token_ids: Vec<Uuid> = sqlx::query_scalar!("SELECT token_id FROM api_tokens")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(token_ids, vec![token_id]);
+}
+
+#[tokio::test]
+async fn the_last_use_of_tokens_is_tracked() {
+    // Arrange
+    let app = spawn_app().await;
+    app.log_in().await;
+    let token = app.create_api_token(&["issues:read"]).await;
+
+    // Act
+    let response = app
+        .api_client
+        .get(format!("{}/api/v1/issues", &app.address))
+        .bearer_auth(&token)
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let last_used_at = sqlx::query_scalar!("SELECT last_used_at FROM api_tokens")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(last_used_at.is_some());
+}
+
+#[tokio::test]
+async fn tokens_need_a_name_and_a_scope() {
+    // Arrange
+    let app = spawn_app().await;
+    app.log_in().await;
+
+    for (name, scopes, message) in [
+        ("", &["issues:read"][..], "The token needs a name"),
+        ("CMS", &[][..], "Pick at least one scope for the token."),
+        ("CMS", &["everything"][..], "Unknown scope everything."),
+    ] {
+        // Act
+        let response = app.post_api_token(name, scopes).await;
+
+        // Assert
+        assert_is_redirect_to(&response, "/admin/api_tokens");
+        let html = get_api_tokens_html(&app).await;
+        assert!(html.contains(message), "{}", message);
+    }
+    let n_tokens = sqlx::query_scalar!(r#"SELECT COUNT(*) AS "count!" FROM api_tokens"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(n_tokens, 0);
+}
+
+#[tokio::test]
+async fn publishing_tokens_require_a_recent_password() {
+    // Arrange
+    let app = spawn_app_with(|c| {
+        c.session.store = SessionStoreKind::Postgres;
+        c.session.reauthentication_timeout_seconds = 60;
+    })
+    .await;
+    app.log_in().await;
+    app.backdate_session(Duration::from_secs(61)).await;
+
+    // Act - Part 1 - Try to create the token
+    let response = app.post_api_token("CMS", &["newsletters:publish"]).await;
+
+    // Assert - Part 1
+    assert_is_redirect_to(&response, "/admin/reauthenticate?return_to=api_tokens");
+    let n_tokens = sqlx::query_scalar!(r#"SELECT COUNT(*) AS "count!" FROM api_tokens"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(n_tokens, 0);
+
+    // Act - Part 2 - Confirm the password, then create it again
+    let response = app
+        .api_client
+        .post(format!("{}/admin/reauthenticate", &app.address))
+        .form(
+            &app.with_csrf_token(&serde_json::json!({
+                "password": &app.test_user.password,
+                "return_to": "api_tokens",
+            }))
+            .await,
+        )
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    assert_is_redirect_to(&response, "/admin/api_tokens");
+    let token = app.create_api_token(&["newsletters:publish"]).await;
+
+    // Assert - Part 2
+    assert!(token.starts_with("zp_"));
+}
+
+#[tokio::test]
+async fn tokens_are_revoked_when_the_password_changes() {
+    // Arrange
+    let app = spawn_app().await;
+    app.log_in().await;
+    let token = app.create_api_token(&["issues:write"]).await;
+
+    // Act
+    let new_password = Uuid::new_v4().to_string();
+    let response = app
+        .post_change_password(&serde_json::json!({
+            "current_password": &app.test_user.password,
+            "new_password": &new_password,
+            "new_password_check": &new_password,
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/password");
+
+    // Assert
+    let response = api_create_issue(&app, Some(&format!("Bearer {}", token))).await;
+    assert_eq!(response.status().as_u16(), 401);
+}
@@ -0,0 +1,282 @@
+use uuid::Uuid;
+
+use crate::spawn_app::{assert_is_redirect_to, spawn_app, TestApp};
+
+/// An audit log row, with the actor's username.
+struct AuditRow {
+    actor: Option<String>,
+    action: String,
+    target: Option<String>,
+    ip_address: Option<String>,
+    request_id: Option<String>,
+}
+
+async fn audit_rows(app: &TestApp) -> Vec<AuditRow> {
+    sqlx::query_as!(
+        AuditRow,
+        r#"
+        SELECT u.username AS "actor?", a.action, a.target, a.ip_address, a.request_id
+        FROM audit_log a
+        LEFT JOIN users u ON u.user_id = a.actor_id
+        ORDER BY a.id
+        "#,
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap()
+}
+
+async fn last_audit_row(app: &TestApp) -> AuditRow {
+    audit_rows(app)
+        .await
+        .pop()
+        .expect("No audit event recorded.")
+}
+
+async fn get_audit(app: &TestApp, path_and_query: &str) -> reqwest::Response {
+    app.api_client
+        .get(format!("{}{}", &app.address, path_and_query))
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+#[tokio::test]
+async fn logins_are_recorded_with_their_ip_address_and_request_id() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    app.api_client
+        .post(format!("{}/login", &app.address))
+        .header("X-Request-Id", "audited-login")
+        .form(&serde_json::json!({
+            "username": &app.test_user.username,
+            "password": &app.test_user.password,
+        }))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    let row = last_audit_row(&app).await;
+    assert_eq!(row.action, "login");
+    assert_eq!(row.actor.as_deref(), Some(app.test_user.username.as_str()));
+    assert_eq!(row.ip_address.as_deref(), Some("127.0.0.1"));
+    assert_eq!(row.request_id.as_deref(), Some("audited-login"));
+}
+
+#[tokio::test]
+async fn failed_logins_are_recorded_with_the_username_tried() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    app.post_login(&serde_json::json!({
+        "username": "not-a-user",
+        "password": "wrong-password",
+    }))
+    .await;
+
+    // Assert
+    let row = last_audit_row(&app).await;
+    assert_eq!(row.action, "failed_login");
+    assert_eq!(row.actor, None);
+    assert_eq!(row.target.as_deref(), Some("not-a-user"));
+}
+
+#[tokio::test]
+async fn logouts_and_password_changes_are_recorded() {
+    // Arrange
+    let app = spawn_app().await;
+    app.log_in().await;
+    let new_password = Uuid::new_v4().to_string();
+
+    // Act
+    let response = app
+        .post_change_password(&serde_json::json!({
+            "current_password": &app.test_user.password,
+            "new_password": &new_password,
+            "new_password_check": &new_password,
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/password");
+    app.post_logout().await;
+
+    // Assert
+    let actions: Vec<String> = audit_rows(&app)
+        .await
+        .into_iter()
+        .map(|row| row.action)
+        .collect();
+    assert_eq!(actions, ["login", "password_change", "logout"]);
+}
+
+#[tokio::test]
+async fn publishes_are_recorded_with_the_issue_id() {
+    // Arrange
+    let app = spawn_app().await;
+    app.log_in().await;
+
+    // Act
+    let response = app
+        .post_newsletters(&serde_json::json!({
+            "title": "Newsletter title",
+            "text_content": "Newsletter body as plain text",
+            "html_content": "<p>Newsletter body as HTML</p>",
+            "idempotency_key": Uuid::new_v4().to_string(),
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/newsletter/published");
+
+    // Assert
+    let issue_id: Uuid = sqlx::query_scalar!("SELECT newsletter_issue_id FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    let row = last_audit_row(&app).await;
+    assert_eq!(row.action, "newsletter_publish");
+    assert_eq!(row.target, Some(issue_id.to_string()));
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_see_the_audit_log() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let page = get_audit(&app, "/admin/audit").await;
+    let export = get_audit(&app, "/admin/audit.csv").await;
+
+    // Assert
+    assert_is_redirect_to(&page, "/login");
+    assert_is_redirect_to(&export, "/login");
+}
+
+#[tokio::test]
+async fn the_audit_log_can_be_filtered_by_action() {
+    // Arrange
+    let app = spawn_app().await;
+    app.post_login(&serde_json::json!({
+        "username": "intruder",
+        "password": "wrong-password",
+    }))
+    .await;
+    app.log_in().await;
+
+    // Act - Part 1 - Unfiltered
+    let html = get_audit(&app, "/admin/audit").await.text().await.unwrap();
+
+    // Assert - Part 1
+    assert!(html.contains("<td>failed_login</td>"));
+    assert!(html.contains("<td>intruder</td>"));
+    assert!(html.contains("<td>login</td>"));
+
+    // Act - Part 2 - Logins only, the other fields left blank
+    let html = get_audit(&app, "/admin/audit?action=login&actor=&since=&until=")
+        .await
+        .text()
+        .await
+        .unwrap();
+
+    // Assert - Part 2
+    assert!(!html.contains("<td>failed_login</td>"));
+    assert!(html.contains("<td>login</td>"));
+}
+
+#[tokio::test]
+async fn the_audit_log_can_be_filtered_by_day() {
+    // Arrange
+    let app = spawn_app().await;
+    app.log_in().await;
+
+    // Act
+    let html = get_audit(&app, "/admin/audit?until=2000-01-01")
+        .await
+        .text()
+        .await
+        .unwrap();
+
+    // Assert
+    assert!(!html.contains("<td>login</td>"));
+}
+
+#[tokio::test]
+async fn invalid_filters_are_rejected_with_a_400() {
+    // Arrange
+    let app = spawn_app().await;
+    app.log_in().await;
+
+    for query in ["?since=yesterday", "?action=delete_everything"] {
+        // Act
+        let response = get_audit(&app, &format!("/admin/audit{}", query)).await;
+
+        // Assert
+        assert_eq!(response.status().as_u16(), 400, "query {}", query);
+    }
+}
+
+#[tokio::test]
+async fn the_audit_log_can_be_exported_as_csv() {
+    // Arrange
+    let app = spawn_app().await;
+    app.post_login(&serde_json::json!({
+        "username": "=cmd|' /C calc'!A0",
+        "password": "wrong-password",
+    }))
+    .await;
+    app.log_in().await;
+
+    // Act
+    let response = get_audit(&app, "/admin/audit.csv?action=failed_login").await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(
+        response.headers()["Content-Type"],
+        "text/csv; charset=utf-8"
+    );
+    assert!(response.headers()["Content-Disposition"]
+        .to_str()
+        .unwrap()
+        .starts_with("attachment"));
+    let csv = response.text().await.unwrap();
+    let lines: Vec<&str> = csv.lines().collect();
+    assert_eq!(
+        lines[0],
+        "occurred_at,actor,action,target,ip_address,request_id"
+    );
+    assert_eq!(lines.len(), 2);
+    assert!(lines[1].contains(",failed_login,'=cmd|' /C calc'!A0,127.0.0.1,"));
+}
+
+#[tokio::test]
+async fn the_csv_export_has_every_event() {
+    // Arrange - more events than are read from the database at once.
+    let app = spawn_app().await;
+    sqlx::query!(
+        r#"
+        INSERT INTO audit_log (occurred_at, action, target)
+        SELECT now() - n * interval '1 second', 'failed_login', n::text
+        FROM generate_series(1, 2500) AS n
+        "#
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    app.log_in().await;
+
+    // Act
+    let response = get_audit(&app, "/admin/audit.csv?action=failed_login").await;
+
+    // Assert - all of them, the most recent first.
+    assert_eq!(response.status().as_u16(), 200);
+    let csv = response.text().await.unwrap();
+    let targets: Vec<&str> = csv
+        .lines()
+        .skip(1)
+        .map(|line| line.split(',').nth(3).unwrap())
+        .collect();
+    let expected: Vec<String> = (1..=2500).map(|n| n.to_string()).collect();
+    assert_eq!(targets, expected);
+}
@@ -0,0 +1,185 @@
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+
+use crate::spawn_app::{spawn_app, spawn_app_with};
+
+#[tokio::test]
+async fn the_home_page_serves_a_subscription_form_with_a_form_token() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let html = app.get_home_html().await;
+
+    // Assert
+    assert!(html.contains(r#"<form action="/subscriptions" method="post">"#));
+    assert!(html.contains(r#"name="website""#));
+    assert!(!app.get_form_token().await.is_empty());
+}
+
+#[tokio::test]
+async fn subscriptions_with_the_honeypot_filled_in_are_silently_ignored() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(path("/v3/mail/send"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_subscriptions(
+            "name=bot&email=victim%40example.com&website=http%3A%2F%2Fspam.example".into(),
+        )
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let n_subscriptions: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(n_subscriptions, 0);
+}
+
+#[tokio::test]
+async fn subscriptions_without_a_valid_form_token_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(path("/v3/mail/send"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+    let tampered_token = format!("0{}", app.form_token);
+
+    for body in [
+        "name=bot&email=victim%40example.com".to_string(),
+        format!(
+            "name=bot&email=victim%40example.com&form_token={}",
+            tampered_token
+        ),
+    ] {
+        // Act
+        let response = app
+            .api_client
+            .post(format!("{}/subscriptions", &app.address))
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .body(body)
+            .send()
+            .await
+            .expect("Failed to execute request.");
+
+        // Assert
+        assert_eq!(response.status().as_u16(), 400);
+        assert!(response
+            .text()
+            .await
+            .unwrap()
+            .contains("The form is invalid"));
+    }
+}
+
+#[tokio::test]
+async fn subscriptions_submitted_right_after_loading_the_form_are_rejected() {
+    // Arrange
+    let app = spawn_app_with(|c| c.subscriptions.bot_protection.min_form_age_seconds = 60).await;
+    Mock::given(path("/v3/mail/send"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_subscriptions("name=bot&email=victim%40example.com".into())
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+    assert!(response.text().await.unwrap().contains("too quickly"));
+}
+
+#[tokio::test]
+async fn subscription_attempts_are_rate_limited_per_client() {
+    // Arrange
+    let app = spawn_app_with(|c| c.subscriptions.bot_protection.per_ip.max_hits = 2).await;
+    Mock::given(path("/v3/mail/send"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let mut statuses = vec![];
+    for email in ["a%40example.com", "b%40example.com", "c%40example.com"] {
+        let response = app
+            .post_subscriptions(format!("name=bot&email={}", email))
+            .await;
+        statuses.push(response.status().as_u16());
+    }
+
+    // Assert
+    assert_eq!(statuses, vec![200, 200, 429]);
+}
+
+#[tokio::test]
+async fn confirmation_emails_are_rate_limited_per_address() {
+    // Arrange
+    let app = spawn_app_with(|c| c.subscriptions.bot_protection.per_email.max_hits = 1).await;
+    Mock::given(path("/v3/mail/send"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act - the local part is case sensitive, these are different subscribers.
+    let first = app
+        .post_subscriptions("name=victim&email=victim%40example.com".into())
+        .await;
+    let second = app
+        .post_subscriptions("name=victim&email=VICTIM%40example.com".into())
+        .await;
+
+    // Assert
+    assert_eq!(first.status().as_u16(), 200);
+    assert_eq!(second.status().as_u16(), 429);
+}
+
+#[tokio::test]
+async fn forwarding_headers_from_untrusted_peers_do_not_dodge_the_rate_limit() {
+    // Arrange
+    let app = spawn_app_with(|c| c.subscriptions.bot_protection.per_ip.max_hits = 1).await;
+    Mock::given(path("/v3/mail/send"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act - a different made up address on each attempt.
+    let mut statuses = vec![];
+    for (i, email) in ["a%40example.com", "b%40example.com"].iter().enumerate() {
+        let response = app
+            .api_client
+            .post(format!("{}/subscriptions", &app.address))
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .header("X-Forwarded-For", format!("198.51.100.{}", i + 1))
+            .body(format!(
+                "name=bot&email={}&form_token={}",
+                email, app.form_token
+            ))
+            .send()
+            .await
+            .expect("Failed to execute request.");
+        statuses.push(response.status().as_u16());
+    }
+
+    // Assert
+    assert_eq!(statuses, vec![200, 429]);
+}
@@ -1,6 +1,20 @@
-use crate::spawn_app::{assert_is_redirect_to, spawn_app};
+use crate::spawn_app::{assert_is_redirect_to, spawn_app, spawn_app_with, TestApp};
 use uuid::Uuid;
 
+/// Changes the password from `current_password` to `new_password` and returns the flash
+/// message shown on the form.
+async fn change_password_to(app: &TestApp, current_password: &str, new_password: &str) -> String {
+    let response = app
+        .post_change_password(&serde_json::json!({
+            "current_password": current_password,
+            "new_password": new_password,
+            "new_password_check": new_password,
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/password");
+    app.get_change_password_html().await
+}
+
 #[tokio::test]
 async fn you_must_be_logged_in_to_see_the_change_password_form() {
     // Arrange
@@ -40,11 +54,7 @@ async fn new_password_fields_must_match() {
     let another_new_password = Uuid::new_v4().to_string();
 
     // Act - Part 1 Login
-    app.post_login(&serde_json::json!({
-        "username": &app.test_user.username,
-        "password": &app.test_user.password,
-    }))
-    .await;
+    app.log_in().await;
 
     // Act - Part 2 Change Password
     let response = app
@@ -72,11 +82,7 @@ async fn current_password_must_be_valid() {
     let wrong_password = Uuid::new_v4().to_string();
 
     // Act - Part 1 Login
-    app.post_login(&serde_json::json!({
-        "username": &app.test_user.username,
-        "password": &app.test_user.password,
-    }))
-    .await;
+    app.log_in().await;
 
     // Act - Part 2 Change Password
     let response = app
@@ -101,11 +107,7 @@ async fn changing_password_works() {
     let app = spawn_app().await;
 
     // Login
-    app.post_login(&serde_json::json!({
-        "username": &app.test_user.username,
-        "password": &app.test_user.password,
-    }))
-    .await;
+    app.log_in().await;
 
     // Act - Change the password
     let new_password = Uuid::new_v4().to_string();
@@ -137,3 +139,131 @@ async fn changing_password_works() {
         .await;
     assert_is_redirect_to(&response, "/admin/dashboard");
 }
+
+#[tokio::test]
+async fn password_changes_that_cannot_be_audited_are_rolled_back() {
+    // Arrange
+    let app = spawn_app().await;
+    app.log_in().await;
+    sqlx::query!(
+        "ALTER TABLE audit_log ADD CONSTRAINT no_password_changes \
+         CHECK (action <> 'password_change')"
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    // Act
+    let new_password = Uuid::new_v4().to_string();
+    let response = app
+        .post_change_password(&serde_json::json!({
+            "current_password": &app.test_user.password,
+            "new_password": &new_password,
+            "new_password_check": &new_password,
+        }))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 500);
+    app.post_logout().await;
+    app.log_in().await;
+}
+
+#[tokio::test]
+async fn new_password_must_be_long_enough() {
+    // Arrange
+    let app = spawn_app().await;
+    app.log_in().await;
+
+    // Act
+    let html_page = change_password_to(&app, &app.test_user.password, "x7!Kq").await;
+
+    // Assert
+    assert!(html_page.contains("The new password must be at least 12 characters long."));
+}
+
+#[tokio::test]
+async fn new_password_must_not_be_too_long() {
+    // Arrange
+    let app = spawn_app().await;
+    app.log_in().await;
+    let new_password = Uuid::new_v4().to_string().repeat(4);
+
+    // Act
+    let html_page = change_password_to(&app, &app.test_user.password, &new_password).await;
+
+    // Assert
+    assert!(html_page.contains("The new password must be at most 128 characters long."));
+}
+
+#[tokio::test]
+async fn weak_passwords_are_rejected_with_feedback() {
+    // Arrange
+    let app = spawn_app().await;
+    app.log_in().await;
+
+    // Act
+    let html_page = change_password_to(&app, &app.test_user.password, "aaaaaaabcdefgh").await;
+
+    // Assert
+    assert!(html_page.contains("The new password is too weak."));
+    assert!(html_page.contains("Avoid repeated characters"));
+}
+
+#[tokio::test]
+async fn breached_passwords_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    app.log_in().await;
+
+    // Act
+    let html_page = change_password_to(&app, &app.test_user.password, "Password1234").await;
+
+    // Assert
+    assert!(html_page.contains("appears in lists of breached passwords"));
+}
+
+#[tokio::test]
+async fn the_current_password_cannot_be_reused() {
+    // Arrange
+    let app = spawn_app().await;
+    app.log_in().await;
+
+    // Act
+    let html_page =
+        change_password_to(&app, &app.test_user.password, &app.test_user.password).await;
+
+    // Assert
+    assert!(html_page.contains("The new password must differ from your last 5 passwords."));
+}
+
+#[tokio::test]
+async fn a_recent_password_cannot_be_reused() {
+    // Arrange
+    let app = spawn_app().await;
+    app.log_in().await;
+    let new_password = Uuid::new_v4().to_string();
+    change_password_to(&app, &app.test_user.password, &new_password).await;
+
+    // Act
+    let html_page = change_password_to(&app, &new_password, &app.test_user.password).await;
+
+    // Assert
+    assert!(html_page.contains("The new password must differ from your last 5 passwords."));
+}
+
+#[tokio::test]
+async fn passwords_older_than_the_history_can_be_reused() {
+    // Arrange
+    let app = spawn_app_with(|c| c.password_policy.history_size = 2).await;
+    app.log_in().await;
+    let passwords = [Uuid::new_v4().to_string(), Uuid::new_v4().to_string()];
+    change_password_to(&app, &app.test_user.password, &passwords[0]).await;
+    change_password_to(&app, &passwords[0], &passwords[1]).await;
+
+    // Act
+    let html_page = change_password_to(&app, &passwords[1], &app.test_user.password).await;
+
+    // Assert
+    assert!(html_page.contains("Your password has been changed."));
+}
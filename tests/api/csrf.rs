@@ -0,0 +1,143 @@
+use uuid::Uuid;
+
+use crate::spawn_app::{assert_is_redirect_to, spawn_app};
+
+fn with_token(mut body: serde_json::Value, csrf_token: Option<&str>) -> serde_json::Value {
+    if let Some(csrf_token) = csrf_token {
+        body["csrf_token"] = csrf_token.into();
+    }
+    body
+}
+
+#[tokio::test]
+async fn admin_forms_embed_the_session_csrf_token() {
+    // Arrange
+    let app = spawn_app().await;
+    app.log_in().await;
+    let csrf_token = app.get_csrf_token().await.unwrap();
+    let hidden_field = format!(r#"name="csrf_token" value="{}""#, csrf_token);
+
+    // Act
+    let pages = [
+        app.get_admin_dashboard_html().await,
+        app.get_change_password_html().await,
+        app.get_publish_newsletter_html().await,
+    ];
+
+    // Assert - the token is stable for the session, the logout form in the nav
+    // carries it on every page.
+    for html in pages {
+        assert!(html.contains(&hidden_field));
+    }
+    assert_eq!(app.get_csrf_token().await.unwrap(), csrf_token);
+}
+
+#[tokio::test]
+async fn admin_posts_without_a_valid_csrf_token_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    app.log_in().await;
+    let new_password = Uuid::new_v4().to_string();
+
+    for csrf_token in [None, Some("not-the-session-token")] {
+        let mut requests = vec![
+            app.api_client
+                .post(format!("{}/admin/logout", &app.address))
+                .form(&with_token(serde_json::json!({}), csrf_token)),
+            app.api_client
+                .post(format!("{}/admin/password", &app.address))
+                .form(&with_token(
+                    serde_json::json!({
+                        "current_password": &app.test_user.password,
+                        "new_password": &new_password,
+                        "new_password_check": &new_password,
+                    }),
+                    csrf_token,
+                )),
+            app.api_client
+                .post(format!("{}/admin/newsletter", &app.address))
+                .form(&with_token(
+                    serde_json::json!({
+                        "title": "Newsletter title",
+                        "text_content": "Newsletter body as plain text",
+                        "html_content": "<p>Newsletter body as HTML</p>",
+                        "idempotency_key": Uuid::new_v4().to_string(),
+                    }),
+                    csrf_token,
+                )),
+        ];
+        if let Some(csrf_token) = csrf_token {
+            requests.push(
+                app.api_client
+                    .put(format!("{}/admin/log_filter", &app.address))
+                    .header("X-CSRF-Token", csrf_token)
+                    .json(&serde_json::json!({ "directives": "debug" })),
+            );
+        }
+
+        for request in requests {
+            // Act
+            let response = request.send().await.expect("Failed to execute request.");
+
+            // Assert
+            assert_eq!(response.status().as_u16(), 403);
+        }
+    }
+    // Still logged in, with the old password.
+    assert_eq!(app.get_admin_dashboard().await.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn anonymous_posts_are_redirected_to_login_before_the_csrf_check() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app
+        .api_client
+        .post(format!("{}/admin/logout", &app.address))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn the_csrf_token_is_rotated_on_login() {
+    // Arrange
+    let app = spawn_app().await;
+    app.log_in().await;
+    let first_token = app.get_csrf_token().await.unwrap();
+
+    // Act
+    app.log_in().await;
+
+    // Assert
+    assert_ne!(app.get_csrf_token().await.unwrap(), first_token);
+}
+
+#[tokio::test]
+async fn the_session_cookie_is_same_site_strict() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": &app.test_user.username,
+            "password": &app.test_user.password,
+        }))
+        .await;
+
+    // Assert
+    let session_cookie = response
+        .headers()
+        .get_all("Set-Cookie")
+        .iter()
+        .map(|value| value.to_str().unwrap())
+        .find(|value| value.starts_with("id="))
+        .expect("No session cookie was set.");
+    assert!(session_cookie.contains("SameSite=Strict"));
+}
@@ -0,0 +1,73 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::str::FromStr;
+
+use hickory_proto::op::{Message, MessageType, ResponseCode};
+use hickory_proto::rr::rdata::MX;
+use hickory_proto::rr::{Name, RData, Record, RecordType};
+use tokio::net::UdpSocket;
+
+/// Nameserver answering MX queries for a fixed set of domains, and NXDOMAIN for
+/// everything else.
+pub struct DnsStub {
+    pub address: SocketAddr,
+}
+
+impl DnsStub {
+    /// `mx` maps domains to their mail servers, "." being a null MX.
+    pub async fn start(mx: &[(&str, &str)]) -> Self {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let address = socket.local_addr().unwrap();
+        let mx: HashMap<Name, Name> = mx
+            .iter()
+            .map(|(domain, exchange)| (fqdn(domain), fqdn(exchange)))
+            .collect();
+        tokio::spawn(async move {
+            let mut buffer = [0; 512];
+            loop {
+                let (len, peer) = socket.recv_from(&mut buffer).await.unwrap();
+                let request = Message::from_vec(&buffer[..len]).unwrap();
+                let response = answer(&request, &mx);
+                socket
+                    .send_to(&response.to_vec().unwrap(), peer)
+                    .await
+                    .unwrap();
+            }
+        });
+        Self { address }
+    }
+}
+
+fn answer(request: &Message, mx: &HashMap<Name, Name>) -> Message {
+    let mut response = Message::new();
+    response
+        .set_id(request.id())
+        .set_message_type(MessageType::Response)
+        .set_op_code(request.op_code())
+        .set_recursion_desired(request.recursion_desired())
+        .set_recursion_available(true)
+        .add_queries(request.queries().to_vec());
+    let Some(query) = request.queries().first() else {
+        return response;
+    };
+    match mx.get(query.name()) {
+        Some(exchange) if query.query_type() == RecordType::MX => {
+            response.add_answer(Record::from_rdata(
+                query.name().clone(),
+                60,
+                RData::MX(MX::new(10, exchange.clone())),
+            ));
+        }
+        Some(_) => {}
+        None => {
+            response.set_response_code(ResponseCode::NXDomain);
+        }
+    }
+    response
+}
+
+fn fqdn(name: &str) -> Name {
+    let mut name = Name::from_str(name).unwrap();
+    name.set_fqdn(true);
+    name
+}
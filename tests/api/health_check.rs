@@ -1,4 +1,12 @@
-use crate::spawn_app::spawn_app;
+use crate::spawn_app::{spawn_app, spawn_app_with, TestApp};
+
+async fn get_health_ready(app: &TestApp) -> reqwest::Response {
+    app.api_client
+        .get(format!("{}/health/ready", &app.address))
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
 
 #[tokio::test]
 async fn health_check_works() {
@@ -9,7 +17,7 @@ async fn health_check_works() {
 
     // Act
     let response = client
-        .get(&format!("{}/health_check", &app.address))
+        .get(format!("{}/health_check", &app.address))
         .send()
         .await
         .expect("Failed to execute request.");
@@ -18,3 +26,77 @@ async fn health_check_works() {
     assert!(response.status().is_success());
     assert_eq!(Some(0), response.content_length());
 }
+
+#[tokio::test]
+async fn health_live_returns_200() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app
+        .api_client
+        .get(format!("{}/health/live", &app.address))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn health_ready_returns_200_when_dependencies_are_up() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = get_health_ready(&app).await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["status"], "ok");
+    assert_eq!(body["checks"]["postgres"]["status"], "ok");
+    assert_eq!(body["checks"]["redis"]["status"], "ok");
+    assert!(body["checks"]["postgres"]["latency_ms"].is_number());
+    // Not checked by default.
+    assert!(body["checks"]["email_provider"].is_null());
+}
+
+#[tokio::test]
+async fn health_ready_returns_503_when_postgres_is_down() {
+    // Arrange
+    let app = spawn_app().await;
+    app.drop_database().await;
+
+    // Act
+    let response = get_health_ready(&app).await;
+
+    // Assert
+    assert_eq!(503, response.status().as_u16());
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["status"], "unavailable");
+    assert_eq!(body["checks"]["postgres"]["status"], "unavailable");
+    // Error details are logged, not served.
+    assert!(body["checks"]["postgres"].get("error").is_none());
+    assert_eq!(body["checks"]["redis"]["status"], "ok");
+}
+
+#[tokio::test]
+async fn an_unreachable_email_provider_is_reported_but_not_critical() {
+    // Arrange - nothing listens on port 1.
+    let app = spawn_app_with(|c| {
+        c.health.check_email_provider = true;
+        c.email_client.base_url = "http://127.0.0.1:1".into();
+    })
+    .await;
+
+    // Act
+    let response = get_health_ready(&app).await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["checks"]["email_provider"]["status"], "unavailable");
+    assert_eq!(body["checks"]["email_provider"]["critical"], false);
+}
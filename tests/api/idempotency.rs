@@ -0,0 +1,226 @@
+use std::time::Duration;
+
+use actix_web::HttpResponse;
+use sqlx::PgPool;
+use uuid::Uuid;
+use zero2prod2::configuration::IdempotencySettings;
+use zero2prod2::idempotency::{
+    delete_expired_keys, save_response, try_processing, IdempotencyError, IdempotencyKey,
+    IdempotencyScope, NextAction, RequestFingerprint,
+};
+
+use crate::spawn_app::spawn_app;
+
+const TTL: Duration = Duration::from_secs(60 * 60);
+
+fn settings() -> IdempotencySettings {
+    IdempotencySettings {
+        ttl_seconds: TTL.as_secs(),
+        cleanup_interval_seconds: 60,
+        in_progress_timeout_milliseconds: 100,
+    }
+}
+
+fn idempotency_key() -> IdempotencyKey {
+    Uuid::new_v4().to_string().try_into().unwrap()
+}
+
+fn fingerprint() -> RequestFingerprint {
+    RequestFingerprint::new(&[b"payload"])
+}
+
+/// Claims `key` like a request would.
+async fn claim(
+    pool: &PgPool,
+    key: &IdempotencyKey,
+    scope: &IdempotencyScope,
+) -> Result<NextAction, IdempotencyError> {
+    try_processing(pool, key, scope, &fingerprint(), &settings()).await
+}
+
+/// Runs a full try_processing -> save_response cycle for `key`.
+async fn process(pool: &PgPool, key: &IdempotencyKey, scope: &IdempotencyScope) {
+    match claim(pool, key, scope).await.unwrap() {
+        NextAction::StartProcessing(transaction) => {
+            save_response(transaction, key, scope, HttpResponse::Ok().finish())
+                .await
+                .unwrap();
+        }
+        NextAction::ReturnSavedResponse(_) => panic!("Expected a fresh key."),
+    }
+}
+
+/// Moves the creation time of `key` `age` into the past.
+async fn set_age(pool: &PgPool, key: &IdempotencyKey, age: Duration) {
+    sqlx::query!(
+        "UPDATE idempotency SET created_at = now() - $1::interval WHERE idempotency_key = $2",
+        age as Duration,
+        key.as_ref(),
+    )
+    .execute(pool)
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn keys_within_the_ttl_return_the_saved_response() {
+    // Arrange
+    let app = spawn_app().await;
+    let scope = IdempotencyScope::user(app.test_user.user_id);
+    let key = idempotency_key();
+    process(&app.db_pool, &key, &scope).await;
+    set_age(&app.db_pool, &key, TTL - Duration::from_secs(60)).await;
+
+    // Act
+    let outcome = claim(&app.db_pool, &key, &scope).await.unwrap();
+
+    // Assert
+    assert!(matches!(outcome, NextAction::ReturnSavedResponse(_)));
+}
+
+#[tokio::test]
+async fn expired_keys_are_treated_as_fresh() {
+    // Arrange
+    let app = spawn_app().await;
+    let scope = IdempotencyScope::user(app.test_user.user_id);
+    let key = idempotency_key();
+    process(&app.db_pool, &key, &scope).await;
+    set_age(&app.db_pool, &key, TTL + Duration::from_secs(1)).await;
+
+    // Act
+    let outcome = claim(&app.db_pool, &key, &scope).await.unwrap();
+
+    // Assert
+    assert!(matches!(outcome, NextAction::StartProcessing(_)));
+}
+
+#[tokio::test]
+async fn an_expired_key_is_only_reclaimed_once() {
+    // Arrange
+    let app = spawn_app().await;
+    let scope = IdempotencyScope::user(app.test_user.user_id);
+    let key = idempotency_key();
+    process(&app.db_pool, &key, &scope).await;
+    set_age(&app.db_pool, &key, TTL + Duration::from_secs(1)).await;
+
+    // Act - reclaim the expired key, then retry it.
+    process(&app.db_pool, &key, &scope).await;
+    let outcome = claim(&app.db_pool, &key, &scope).await.unwrap();
+
+    // Assert
+    assert!(matches!(outcome, NextAction::ReturnSavedResponse(_)));
+}
+
+#[tokio::test]
+async fn cleanup_deletes_expired_keys_only() {
+    // Arrange
+    let app = spawn_app().await;
+    let scope = IdempotencyScope::user(app.test_user.user_id);
+    let fresh_key = idempotency_key();
+    let almost_expired_key = idempotency_key();
+    let expired_key = idempotency_key();
+    for key in [&fresh_key, &almost_expired_key, &expired_key] {
+        process(&app.db_pool, key, &scope).await;
+    }
+    set_age(
+        &app.db_pool,
+        &almost_expired_key,
+        TTL - Duration::from_secs(60),
+    )
+    .await;
+    set_age(&app.db_pool, &expired_key, TTL + Duration::from_secs(1)).await;
+
+    // Act
+    let n_deleted_keys = delete_expired_keys(&app.db_pool, TTL).await.unwrap();
+
+    // Assert
+    assert_eq!(n_deleted_keys, 1);
+    let remaining_keys: Vec<String> =
+        sqlx::query_scalar!("SELECT idempotency_key FROM idempotency")
+            .fetch_all(&app.db_pool)
+            .await
+            .unwrap();
+    assert_eq!(remaining_keys.len(), 2);
+    assert!(!remaining_keys.contains(&expired_key.as_ref().to_owned()));
+}
+
+#[tokio::test]
+async fn a_key_held_by_an_in_flight_request_is_in_progress() {
+    // Arrange - a request claimed the key and is still processing.
+    let app = spawn_app().await;
+    let scope = IdempotencyScope::user(app.test_user.user_id);
+    let key = idempotency_key();
+    let in_flight = claim(&app.db_pool, &key, &scope).await.unwrap();
+
+    // Act
+    let outcome = claim(&app.db_pool, &key, &scope).await;
+
+    // Assert
+    assert!(matches!(outcome, Err(IdempotencyError::InProgress { .. })));
+    drop(in_flight);
+}
+
+#[tokio::test]
+async fn a_key_without_a_saved_response_is_in_progress() {
+    // Arrange - the key was committed without a response.
+    let app = spawn_app().await;
+    let scope = IdempotencyScope::user(app.test_user.user_id);
+    let key = idempotency_key();
+    let fingerprint = fingerprint();
+    sqlx::query!(
+        r#"
+        INSERT INTO idempotency (scope, idempotency_key, request_fingerprint, created_at)
+        VALUES ($1, $2, $3, now())
+        "#,
+        scope.as_ref(),
+        key.as_ref(),
+        fingerprint.as_ref(),
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    // Act
+    let outcome = claim(&app.db_pool, &key, &scope).await;
+
+    // Assert
+    assert!(matches!(outcome, Err(IdempotencyError::InProgress { .. })));
+}
+
+#[tokio::test]
+async fn reusing_a_key_for_a_different_payload_is_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    let scope = IdempotencyScope::user(app.test_user.user_id);
+    let key = idempotency_key();
+    process(&app.db_pool, &key, &scope).await;
+
+    // Act
+    let outcome = try_processing(
+        &app.db_pool,
+        &key,
+        &scope,
+        &RequestFingerprint::new(&[b"another payload"]),
+        &settings(),
+    )
+    .await;
+
+    // Assert
+    assert!(matches!(outcome, Err(IdempotencyError::PayloadMismatch)));
+}
+
+#[tokio::test]
+async fn the_same_key_in_different_scopes_is_independent() {
+    // Arrange
+    let app = spawn_app().await;
+    let key = idempotency_key();
+    let user_scope = IdempotencyScope::user(app.test_user.user_id);
+    let client_scope = IdempotencyScope::client("127.0.0.1", "curl/8.0");
+    process(&app.db_pool, &key, &user_scope).await;
+
+    // Act
+    let outcome = claim(&app.db_pool, &key, &client_scope).await.unwrap();
+
+    // Assert
+    assert!(matches!(outcome, NextAction::StartProcessing(_)));
+}
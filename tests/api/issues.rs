@@ -0,0 +1,439 @@
+use uuid::Uuid;
+use wiremock::matchers::any;
+use wiremock::{Mock, ResponseTemplate};
+
+use zero2prod2::newsletter_issues::publish_due_issues;
+
+use crate::spawn_app::{spawn_app, TestApp};
+
+struct ApiClient<'a> {
+    app: &'a TestApp,
+    token: String,
+}
+
+impl ApiClient<'_> {
+    fn request(&self, method: reqwest::Method, route: &str) -> reqwest::RequestBuilder {
+        self.app
+            .api_client
+            .request(method, format!("{}/api/v1{}", &self.app.address, route))
+            .bearer_auth(&self.token)
+    }
+
+    async fn get(&self, route: &str) -> reqwest::Response {
+        self.request(reqwest::Method::GET, route)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    /// Sends `body` with a fresh idempotency key.
+    async fn send(
+        &self,
+        method: reqwest::Method,
+        route: &str,
+        body: &serde_json::Value,
+    ) -> reqwest::Response {
+        self.request(method, route)
+            .header("Idempotency-Key", Uuid::new_v4().to_string())
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    async fn create_issue(&self, title: &str) -> serde_json::Value {
+        let response = self
+            .send(
+                reqwest::Method::POST,
+                "/issues",
+                &serde_json::json!({
+                    "title": title,
+                    "text_content": "Newsletter body as plain text",
+                    "html_content": "<p>Newsletter body as HTML</p>",
+                }),
+            )
+            .await;
+        assert_eq!(response.status().as_u16(), 201);
+        response.json().await.unwrap()
+    }
+}
+
+async fn api_client(app: &TestApp) -> ApiClient<'_> {
+    app.log_in().await;
+    let token = app
+        .create_api_token(&["issues:read", "issues:write", "newsletters:publish"])
+        .await;
+    ApiClient { app, token }
+}
+
+#[tokio::test]
+async fn drafts_can_be_edited_then_published() {
+    // Arrange
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+    let api = api_client(&app).await;
+
+    // Act - Part 1 - Create a draft
+    let response = api
+        .send(
+            reqwest::Method::POST,
+            "/issues",
+            &serde_json::json!({
+                "title": "Draft title",
+                "text_content": "Newsletter body as plain text",
+                "html_content": "<p>Newsletter body as HTML</p>",
+            }),
+        )
+        .await;
+    assert_eq!(response.status().as_u16(), 201);
+    let location = response.headers()["Location"].to_str().unwrap().to_owned();
+    let issue: serde_json::Value = response.json().await.unwrap();
+    let issue_id = issue["id"].as_str().unwrap();
+    assert_eq!(location, format!("/api/v1/issues/{}", issue_id));
+    assert_eq!(issue["status"], "draft");
+    assert!(issue["published_at"].is_null());
+
+    // Act - Part 2 - Edit it
+    let response = api
+        .send(
+            reqwest::Method::PATCH,
+            &format!("/issues/{}", issue_id),
+            &serde_json::json!({ "title": "Final title" }),
+        )
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+    let issue: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(issue["title"], "Final title");
+    assert_eq!(issue["text_content"], "Newsletter body as plain text");
+
+    // Act - Part 3 - Publish it
+    let response = api
+        .send(
+            reqwest::Method::POST,
+            &format!("/issues/{}/publish", issue_id),
+            &serde_json::json!({}),
+        )
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+    let issue: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(issue["status"], "published");
+    assert!(issue["published_at"].is_string());
+    assert_eq!(issue["delivery"]["recipients"], 1);
+    assert_eq!(issue["delivery"]["pending"], 1);
+
+    // Act - Part 4 - Deliver it
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.dispatch_all_pending_emails().await;
+    let issue: serde_json::Value = api
+        .get(&format!("/issues/{}", issue_id))
+        .await
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(issue["delivery"]["pending"], 0);
+}
+
+#[tokio::test]
+async fn published_issues_cannot_be_published_or_edited_again() {
+    // Arrange
+    let app = spawn_app().await;
+    let api = api_client(&app).await;
+    let issue = api.create_issue("Title").await;
+    let issue_id = issue["id"].as_str().unwrap();
+    let publish = format!("/issues/{}/publish", issue_id);
+    let response = api
+        .send(reqwest::Method::POST, &publish, &serde_json::json!({}))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    for (method, route, body) in [
+        (
+            reqwest::Method::POST,
+            publish.clone(),
+            serde_json::json!({}),
+        ),
+        (
+            reqwest::Method::PATCH,
+            format!("/issues/{}", issue_id),
+            serde_json::json!({ "title": "Too late" }),
+        ),
+        (
+            reqwest::Method::POST,
+            format!("/issues/{}/schedule", issue_id),
+            serde_json::json!({ "publish_at": "2100-01-01T09:00:00Z" }),
+        ),
+    ] {
+        // Act
+        let response = api.send(method, &route, &body).await;
+
+        // Assert
+        assert_eq!(response.status().as_u16(), 409, "{}", route);
+        let body: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(body["error"]["code"], "conflict");
+    }
+    let n_queued = sqlx::query_scalar!(r#"SELECT COUNT(*) AS "count!" FROM issue_delivery_queue"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(n_queued, 0);
+}
+
+#[tokio::test]
+async fn scheduled_issues_are_published_once_due() {
+    // Arrange
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+    let api = api_client(&app).await;
+    let issue = api.create_issue("Title").await;
+    let issue_id = issue["id"].as_str().unwrap();
+
+    // Act - Part 1 - Schedule
+    let response = api
+        .send(
+            reqwest::Method::POST,
+            &format!("/issues/{}/schedule", issue_id),
+            &serde_json::json!({ "publish_at": "2100-01-01T09:00:00+02:00" }),
+        )
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+    let issue: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(issue["status"], "scheduled");
+    assert_eq!(issue["scheduled_for"], "2100-01-01T07:00:00+00:00");
+
+    // Act - Part 2 - Not due yet
+    assert_eq!(publish_due_issues(&app.db_pool).await.unwrap(), 0);
+
+    // Act - Part 3 - Due
+    sqlx::query!("UPDATE newsletter_issues SET scheduled_for = now() - interval '1 second'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(publish_due_issues(&app.db_pool).await.unwrap(), 1);
+
+    // Assert
+    let issue: serde_json::Value = api
+        .get(&format!("/issues/{}", issue_id))
+        .await
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(issue["status"], "published");
+    assert!(issue["scheduled_for"].is_null());
+    assert_eq!(issue["delivery"]["recipients"], 1);
+    assert_eq!(publish_due_issues(&app.db_pool).await.unwrap(), 0);
+}
+
+#[tokio::test]
+async fn issues_must_be_scheduled_in_the_future() {
+    // Arrange
+    let app = spawn_app().await;
+    let api = api_client(&app).await;
+    let issue = api.create_issue("Title").await;
+    let route = format!("/issues/{}/schedule", issue["id"].as_str().unwrap());
+
+    for publish_at in ["2000-01-01T09:00:00Z", "next tuesday"] {
+        // Act
+        let response = api
+            .send(
+                reqwest::Method::POST,
+                &route,
+                &serde_json::json!({ "publish_at": publish_at }),
+            )
+            .await;
+
+        // Assert
+        assert_eq!(response.status().as_u16(), 422, "{}", publish_at);
+        let body: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(body["error"]["code"], "validation_failed");
+    }
+}
+
+#[tokio::test]
+async fn invalid_requests_get_a_structured_error() {
+    // Arrange
+    let app = spawn_app().await;
+    let api = api_client(&app).await;
+    let test_cases = [
+        (
+            api.request(reqwest::Method::POST, "/issues")
+                .header("Idempotency-Key", Uuid::new_v4().to_string())
+                .header("Content-Type", "application/json")
+                .body("{\"title\": "),
+            400,
+            "invalid_request",
+        ),
+        (
+            api.request(reqwest::Method::POST, "/issues")
+                .header("Idempotency-Key", Uuid::new_v4().to_string())
+                .json(&serde_json::json!({"title": "Title"})),
+            400,
+            "invalid_request",
+        ),
+        (
+            api.request(reqwest::Method::POST, "/issues")
+                .header("Idempotency-Key", Uuid::new_v4().to_string())
+                .json(&serde_json::json!({
+                    "title": " ",
+                    "text_content": "text",
+                    "html_content": "html",
+                })),
+            422,
+            "validation_failed",
+        ),
+        (
+            api.request(reqwest::Method::GET, "/issues?status=sent"),
+            400,
+            "invalid_request",
+        ),
+        (
+            api.request(reqwest::Method::GET, "/issues?limit=1000"),
+            422,
+            "validation_failed",
+        ),
+        (
+            api.request(reqwest::Method::GET, &format!("/issues/{}", Uuid::new_v4())),
+            404,
+            "not_found",
+        ),
+        (
+            api.request(reqwest::Method::GET, "/issues/not-a-uuid"),
+            404,
+            "not_found",
+        ),
+    ];
+
+    for (request, status, code) in test_cases {
+        // Act
+        let response = request.send().await.expect("Failed to execute request.");
+
+        // Assert
+        assert_eq!(response.status().as_u16(), status, "{}", code);
+        let body: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(body["error"]["code"], code);
+        assert!(body["error"]["message"].is_string());
+        assert!(body["error"]["request_id"].is_string());
+    }
+}
+
+#[tokio::test]
+async fn issues_can_be_listed_by_status() {
+    // Arrange
+    let app = spawn_app().await;
+    let api = api_client(&app).await;
+    api.create_issue("First draft").await;
+    let published = api.create_issue("Published").await;
+    let route = format!("/issues/{}/publish", published["id"].as_str().unwrap());
+    api.send(reqwest::Method::POST, &route, &serde_json::json!({}))
+        .await;
+    api.create_issue("Second draft").await;
+
+    // Act
+    let all: serde_json::Value = api.get("/issues").await.json().await.unwrap();
+    let drafts: serde_json::Value = api.get("/issues?status=draft").await.json().await.unwrap();
+    let page: serde_json::Value = api
+        .get("/issues?limit=1&offset=1")
+        .await
+        .json()
+        .await
+        .unwrap();
+
+    // Assert
+    let titles = |list: &serde_json::Value| -> Vec<String> {
+        list["issues"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|issue| issue["title"].as_str().unwrap().to_owned())
+            .collect()
+    };
+    assert_eq!(titles(&all), ["Second draft", "Published", "First draft"]);
+    assert_eq!(titles(&drafts), ["Second draft", "First draft"]);
+    assert_eq!(titles(&page), ["Published"]);
+    assert!(all["issues"][0].get("html_content").is_none());
+}
+
+#[tokio::test]
+async fn writes_need_an_idempotency_key_and_replay_on_retries() {
+    // Arrange
+    let app = spawn_app().await;
+    let api = api_client(&app).await;
+    let body = serde_json::json!({
+        "title": "Title",
+        "text_content": "text",
+        "html_content": "html",
+    });
+
+    // Act - Part 1 - Without a key
+    let response = api
+        .request(reqwest::Method::POST, "/issues")
+        .json(&body)
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    assert_eq!(response.status().as_u16(), 400);
+    let error: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(error["error"]["code"], "invalid_idempotency_key");
+    assert!(error["error"]["request_id"].is_string());
+
+    // Act - Part 2 - Retried with the same key
+    let key = Uuid::new_v4().to_string();
+    let mut ids = Vec::new();
+    for _ in 0..2 {
+        let response = api
+            .request(reqwest::Method::POST, "/issues")
+            .header("Idempotency-Key", &key)
+            .json(&body)
+            .send()
+            .await
+            .expect("Failed to execute request.");
+        assert_eq!(response.status().as_u16(), 201);
+        let issue: serde_json::Value = response.json().await.unwrap();
+        ids.push(issue["id"].as_str().unwrap().to_owned());
+    }
+
+    // Act - Part 3 - Same key, different payload
+    let response = api
+        .request(reqwest::Method::POST, "/issues")
+        .header("Idempotency-Key", &key)
+        .json(&serde_json::json!({
+            "title": "Another title",
+            "text_content": "text",
+            "html_content": "html",
+        }))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    assert_eq!(response.status().as_u16(), 422);
+    let error: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(error["error"]["code"], "idempotency_key_reused");
+    assert!(error["error"]["request_id"].is_string());
+
+    // Assert
+    assert_eq!(ids[0], ids[1]);
+    let n_issues = sqlx::query_scalar!(r#"SELECT COUNT(*) AS "count!" FROM newsletter_issues"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(n_issues, 1);
+}
+
+#[tokio::test]
+async fn reading_issues_needs_the_read_scope() {
+    // Arrange
+    let app = spawn_app().await;
+    app.log_in().await;
+    let token = app.create_api_token(&["issues:write"]).await;
+    let api = ApiClient { app: &app, token };
+
+    // Act
+    let response = api.get("/issues").await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 403);
+    let challenge = response.headers()["WWW-Authenticate"].to_str().unwrap();
+    assert!(challenge.contains(r#"scope="issues:read""#));
+}
@@ -0,0 +1,69 @@
+use crate::spawn_app::{assert_is_redirect_to, spawn_app, TestApp};
+
+async fn put_log_filter(app: &TestApp, directives: &str) -> reqwest::Response {
+    let csrf_token = app.get_csrf_token().await.unwrap_or_default();
+    app.api_client
+        .put(format!("{}/admin/log_filter", &app.address))
+        .header("X-CSRF-Token", csrf_token)
+        .json(&serde_json::json!({ "directives": directives }))
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+async fn get_log_filter(app: &TestApp) -> reqwest::Response {
+    app.api_client
+        .get(format!("{}/admin/log_filter", &app.address))
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_change_the_log_filter() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = put_log_filter(&app, "debug").await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+}
+
+// The filter is process wide: only ever widen it, other tests rely on info spans.
+#[tokio::test]
+async fn the_log_filter_can_be_changed_at_runtime() {
+    // Arrange
+    let app = spawn_app().await;
+    app.log_in().await;
+    let response = get_log_filter(&app).await;
+    assert_eq!(200, response.status().as_u16());
+    let original: serde_json::Value = response.json().await.unwrap();
+
+    // Act
+    let response = put_log_filter(&app, "info,sqlx=debug").await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    let body: serde_json::Value = get_log_filter(&app).await.json().await.unwrap();
+    let directives = body["directives"].as_str().unwrap();
+    assert!(directives.contains("sqlx=debug"));
+
+    // Restore the filter for the other tests.
+    let response = put_log_filter(&app, original["directives"].as_str().unwrap()).await;
+    assert_eq!(200, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn invalid_log_filter_directives_are_rejected_with_400() {
+    // Arrange
+    let app = spawn_app().await;
+    app.log_in().await;
+
+    // Act
+    let response = put_log_filter(&app, "info,sqlx=loud").await;
+
+    // Assert
+    assert_eq!(400, response.status().as_u16());
+}
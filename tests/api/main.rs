@@ -1,8 +1,24 @@
 mod admin_dashboard;
+mod api_tokens;
+mod audit;
+mod bot_protection;
 mod change_password;
+mod csrf;
+mod dns_stub;
 mod health_check;
+mod idempotency;
+mod issues;
+mod log_filter;
 mod login;
+mod metrics;
+mod migrations;
 mod newsletter;
+mod password_hashing;
+mod request_id;
+mod security_headers;
+mod session_lifetime;
+mod session_store;
+mod sessions;
 mod spawn_app;
 mod subscriptions;
 mod subscriptions_confirm;
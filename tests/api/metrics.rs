@@ -0,0 +1,88 @@
+use crate::spawn_app::{spawn_app, spawn_app_with};
+
+// Metrics are process wide and shared by all the tests, only check for presence.
+
+#[tokio::test]
+async fn metrics_are_served_in_the_prometheus_text_format() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app
+        .api_client
+        .get(format!("{}/metrics", app.metrics_address.as_ref().unwrap()))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    let content_type = response.headers().get("Content-Type").unwrap();
+    assert!(content_type.to_str().unwrap().starts_with("text/plain"));
+    let body = response.text().await.unwrap();
+    assert!(body.contains("delivery_queue_depth"));
+    assert!(body.contains("db_pool_max_connections"));
+}
+
+#[tokio::test]
+async fn http_requests_are_counted_by_route() {
+    // Arrange
+    let app = spawn_app().await;
+    app.api_client
+        .get(format!("{}/health_check", &app.address))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Act
+    let body = app
+        .api_client
+        .get(format!("{}/metrics", app.metrics_address.as_ref().unwrap()))
+        .send()
+        .await
+        .expect("Failed to execute request.")
+        .text()
+        .await
+        .unwrap();
+
+    // Assert
+    assert!(
+        body.contains(r#"http_requests_total{method="GET",route="/health_check",status="200"}"#)
+    );
+    assert!(body.contains("http_request_duration_seconds_bucket"));
+}
+
+#[tokio::test]
+async fn metrics_are_not_served_on_the_application_port_by_default() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app
+        .api_client
+        .get(format!("{}/metrics", &app.address))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert_eq!(404, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn metrics_can_be_served_on_the_application_port() {
+    // Arrange
+    let app = spawn_app_with(|c| c.metrics.port = None).await;
+
+    // Act
+    let response = app
+        .api_client
+        .get(format!("{}/metrics", &app.address))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert!(app.metrics_address.is_none());
+    assert_eq!(200, response.status().as_u16());
+}
@@ -0,0 +1,241 @@
+use std::borrow::Cow;
+
+use claims::{assert_err, assert_ok};
+use sqlx::migrate::Migrator;
+use sqlx::{Executor, PgPool};
+use zero2prod2::domain::EmailNormalization;
+use zero2prod2::migrations::{apply_email_normalization, run_migrations};
+use zero2prod2::startup::{get_connection_pool, Application};
+
+use crate::spawn_app::{configure_database, create_database, test_configuration};
+
+#[tokio::test]
+async fn migrations_are_applied_on_startup() {
+    // Arrange - an empty database.
+    let mut configuration = test_configuration();
+    configuration.database.migrate_on_startup = true;
+    create_database(&configuration.database).await;
+
+    // Act
+    let application = Application::build(configuration.clone()).await;
+
+    // Assert
+    assert_ok!(application);
+    let pool = get_connection_pool(&configuration.database);
+    let n_subscriptions: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM subscriptions")
+        .fetch_one(&pool)
+        .await
+        .expect("The subscriptions table was not created.");
+    assert_eq!(n_subscriptions, 0);
+}
+
+#[tokio::test]
+async fn migrations_are_not_applied_when_disabled() {
+    // Arrange - an empty database.
+    let mut configuration = test_configuration();
+    configuration.database.migrate_on_startup = false;
+    create_database(&configuration.database).await;
+
+    // Act
+    let application = Application::build(configuration.clone()).await;
+
+    // Assert
+    assert_ok!(application);
+    let pool = get_connection_pool(&configuration.database);
+    let result = sqlx::query("SELECT 1 FROM subscriptions")
+        .execute(&pool)
+        .await;
+    assert_err!(result);
+}
+
+#[tokio::test]
+async fn startup_is_idempotent_when_the_schema_is_up_to_date() {
+    // Arrange - a fully migrated database.
+    let mut configuration = test_configuration();
+    configuration.database.migrate_on_startup = true;
+    configure_database(&configuration.database).await;
+
+    // Act
+    let application = Application::build(configuration).await;
+
+    // Assert
+    assert_ok!(application);
+}
+
+#[tokio::test]
+async fn startup_fails_if_the_schema_is_newer_than_the_binary() {
+    for migrate_on_startup in [true, false] {
+        // Arrange - pretend a newer binary already migrated the database.
+        let mut configuration = test_configuration();
+        configuration.database.migrate_on_startup = migrate_on_startup;
+        let pool = configure_database(&configuration.database).await;
+        sqlx::query(
+            r#"
+            INSERT INTO _sqlx_migrations (version, description, success, checksum, execution_time)
+            VALUES (99990101000000, 'from the future', true, '\x00', 0)
+            "#,
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        // Act
+        let application = Application::build(configuration).await;
+
+        // Assert
+        assert!(
+            application.is_err(),
+            "The application started on a newer schema (migrate_on_startup = {}).",
+            migrate_on_startup
+        );
+    }
+}
+
+/// Applies the migrations up to, and including, `version`.
+async fn migrate_up_to(pool: &PgPool, version: i64) {
+    let all = sqlx::migrate!("./migrations");
+    let migrator = Migrator {
+        migrations: Cow::Owned(
+            all.iter()
+                .filter(|m| m.version <= version)
+                .cloned()
+                .collect(),
+        ),
+        ..Migrator::DEFAULT
+    };
+    migrator.run(pool).await.expect("Failed to migrate.");
+}
+
+#[tokio::test]
+async fn normalizing_emails_keeps_one_subscriber_per_address() {
+    // Arrange - the same person subscribed twice, before emails were normalized.
+    let configuration = test_configuration();
+    create_database(&configuration.database).await;
+    let pool = get_connection_pool(&configuration.database);
+    migrate_up_to(&pool, 20261019170000).await;
+    pool.execute(
+        r#"
+        INSERT INTO subscriptions (id, email, name, subscribed_at, status) VALUES
+            ('00000000-0000-0000-0000-000000000001', 'Ursula@EXAMPLE.com', 'ursula', now() - interval '1 day', 'pending'),
+            ('00000000-0000-0000-0000-000000000002', 'Ursula@example.com', 'ursula', now(), 'confirmed'),
+            ('00000000-0000-0000-0000-000000000003', 'Le.Guin@Example.com', 'le guin', now(), 'pending');
+        INSERT INTO subscription_tokens (subscription_token, subscriber_id) VALUES
+            ('token1', '00000000-0000-0000-0000-000000000001'),
+            ('token2', '00000000-0000-0000-0000-000000000002');
+        "#,
+    )
+    .await
+    .unwrap();
+
+    // Act
+    run_migrations(&pool).await.unwrap();
+
+    // Assert - the confirmed subscription was kept, the other domains lowercased.
+    let subscriptions: Vec<(String, String)> =
+        sqlx::query_as("SELECT email, status::text FROM subscriptions ORDER BY email")
+            .fetch_all(&pool)
+            .await
+            .unwrap();
+    assert_eq!(
+        subscriptions,
+        vec![
+            ("Le.Guin@example.com".to_string(), "pending".to_string()),
+            ("Ursula@example.com".to_string(), "confirmed".to_string()),
+        ]
+    );
+    let tokens: Vec<String> =
+        sqlx::query_scalar("SELECT subscription_token FROM subscription_tokens")
+            .fetch_all(&pool)
+            .await
+            .unwrap();
+    assert_eq!(tokens, vec!["token2".to_string()]);
+}
+
+#[tokio::test]
+async fn folding_the_local_part_case_folds_existing_subscribers_and_enforces_it() {
+    // Arrange - the same person subscribed twice while folding was off.
+    let configuration = test_configuration();
+    let pool = configure_database(&configuration.database).await;
+    pool.execute(
+        r#"
+        INSERT INTO subscriptions (id, email, name, subscribed_at, status) VALUES
+            ('00000000-0000-0000-0000-000000000001', 'Ursula@example.com', 'ursula', now() - interval '1 day', 'pending'),
+            ('00000000-0000-0000-0000-000000000002', 'ursula@example.com', 'ursula', now(), 'confirmed'),
+            ('00000000-0000-0000-0000-000000000003', 'Le.Guin@example.com', 'le guin', now(), 'pending');
+        "#,
+    )
+    .await
+    .unwrap();
+    let folded = EmailNormalization {
+        fold_local_part_case: true,
+    };
+
+    // Act - Part 1 - Turn folding on
+    let report = apply_email_normalization(&pool, folded).await.unwrap();
+
+    // Assert
+    assert_eq!(
+        report.deleted_subscribers,
+        vec!["Ursula@example.com".to_string()]
+    );
+    assert_eq!(report.folded_subscribers, 1);
+    let subscriptions: Vec<(String, String)> =
+        sqlx::query_as("SELECT email, status::text FROM subscriptions ORDER BY email")
+            .fetch_all(&pool)
+            .await
+            .unwrap();
+    assert_eq!(
+        subscriptions,
+        vec![
+            ("le.guin@example.com".to_string(), "pending".to_string()),
+            ("ursula@example.com".to_string(), "confirmed".to_string()),
+        ]
+    );
+    let insert_unfolded = r#"
+        INSERT INTO subscriptions (id, email, name, subscribed_at, status)
+        VALUES (gen_random_uuid(), 'URSULA@example.com', 'ursula', now(), 'pending')
+        "#;
+    assert_err!(pool.execute(insert_unfolded).await);
+
+    // Act - Part 2 - Turn folding off again
+    apply_email_normalization(&pool, EmailNormalization::default())
+        .await
+        .unwrap();
+
+    // Assert
+    assert_ok!(pool.execute(insert_unfolded).await);
+}
+
+#[tokio::test]
+async fn turning_folding_on_does_not_touch_subscribers_on_startup() {
+    // Arrange - the same person subscribed twice while folding was off.
+    let mut configuration = test_configuration();
+    configuration.subscriptions.fold_email_local_part_case = true;
+    let pool = configure_database(&configuration.database).await;
+    pool.execute(
+        r#"
+        INSERT INTO subscriptions (id, email, name, subscribed_at, status) VALUES
+            ('00000000-0000-0000-0000-000000000001', 'Ursula@example.com', 'ursula', now(), 'pending'),
+            ('00000000-0000-0000-0000-000000000002', 'ursula@example.com', 'ursula', now(), 'confirmed');
+        "#,
+    )
+    .await
+    .unwrap();
+
+    // Act
+    let application = Application::build(configuration).await;
+
+    // Assert
+    assert_ok!(application);
+    let emails: Vec<String> = sqlx::query_scalar("SELECT email FROM subscriptions ORDER BY email")
+        .fetch_all(&pool)
+        .await
+        .unwrap();
+    assert_eq!(
+        emails,
+        vec![
+            "Ursula@example.com".to_string(),
+            "ursula@example.com".to_string()
+        ]
+    );
+}
@@ -1,35 +1,22 @@
 use std::time::Duration;
 
 // e2e test for newsletter.
-use fake::faker::internet::en::SafeEmail;
-use fake::faker::name::en::Name;
-use fake::Fake;
 use wiremock::MockBuilder;
 use wiremock::{
     matchers::{any, method, path},
     Mock, ResponseTemplate,
 };
 
-use crate::spawn_app::{assert_is_redirect_to, spawn_app, ConfirmationLinks, TestApp};
+use zero2prod2::idempotency::IdempotencyScope;
+
+use crate::spawn_app::{assert_is_redirect_to, spawn_app};
 
-async fn create_confirmed_subscriber(app: &TestApp) {
-    let confirmation_link = create_unconfirmed_subscriber(app).await;
-    reqwest::get(confirmation_link.html)
-        .await
-        .unwrap()
-        .error_for_status()
-        .unwrap();
-}
 #[tokio::test]
 async fn newsletters_are_not_delivered_to_unconfirmed_subscribers() {
     // Arrange
     let app = spawn_app().await;
-    create_unconfirmed_subscriber(&app).await;
-    app.post_login(&serde_json::json!({
-        "username": &app.test_user.username,
-        "password": &app.test_user.password,
-    }))
-    .await;
+    app.create_unconfirmed_subscriber().await;
+    app.log_in().await;
 
     // Expect
     Mock::given(any())
@@ -50,7 +37,7 @@ async fn newsletters_are_not_delivered_to_unconfirmed_subscribers() {
     let response = app.post_newsletters(&newsletter_request_body).await;
 
     // Assert
-    assert_is_redirect_to(&response, "/admin/newsletter");
+    assert_is_redirect_to(&response, "/admin/newsletter/published");
     app.dispatch_all_pending_emails().await;
     // Mock verifies on drop.
 }
@@ -59,12 +46,8 @@ async fn newsletters_are_not_delivered_to_unconfirmed_subscribers() {
 async fn newsletters_are_delivered_to_confirmed_subscribers() {
     // arrange
     let app = spawn_app().await;
-    create_confirmed_subscriber(&app).await;
-    app.post_login(&serde_json::json!({
-        "username": &app.test_user.username,
-        "password": &app.test_user.password,
-    }))
-    .await;
+    app.create_confirmed_subscriber().await;
+    app.log_in().await;
 
     // expect
     Mock::given(any())
@@ -85,7 +68,7 @@ async fn newsletters_are_delivered_to_confirmed_subscribers() {
     dbg!(&response);
 
     // assert
-    assert_is_redirect_to(&response, "/admin/newsletter");
+    assert_is_redirect_to(&response, "/admin/newsletter/published");
     app.dispatch_all_pending_emails().await;
     // mock verifies on drop.
 }
@@ -94,11 +77,7 @@ async fn newsletters_are_delivered_to_confirmed_subscribers() {
 async fn newsletters_returns_400_for_invalid_data() {
     // arrange
     let app = spawn_app().await;
-    app.post_login(&serde_json::json!({
-        "username": &app.test_user.username,
-        "password": &app.test_user.password,
-    }))
-    .await;
+    app.log_in().await;
 
     let test_cases = vec![
         (
@@ -139,38 +118,6 @@ async fn newsletters_returns_400_for_invalid_data() {
     }
 }
 
-async fn create_unconfirmed_subscriber(app: &TestApp) -> ConfirmationLinks {
-    // Some tests use multiple subscribers -- randomise to avoid conflicts.
-    let name: String = Name().fake();
-    let email: String = SafeEmail().fake();
-    let body = serde_urlencoded::to_string(&serde_json::json!({
-        "name": name,
-        "email": email
-    }))
-    .unwrap();
-    let _mock_guard = Mock::given(path("/v3/mail/send"))
-        .and(method("POST"))
-        .respond_with(ResponseTemplate::new(200))
-        .named("Create unconfirmed subscriber")
-        .expect(1)
-        .mount_as_scoped(&app.email_server)
-        .await;
-    app.post_subscriptions(body.into())
-        .await
-        .error_for_status()
-        .unwrap();
-
-    let email_request = &app
-        .email_server
-        .received_requests()
-        .await
-        .unwrap()
-        .pop()
-        .unwrap();
-
-    app.get_confirmation_links(&email_request)
-}
-
 #[tokio::test]
 async fn post_newsletters_redirects_to_login_if_not_logged_in() {
     // arrange
@@ -194,15 +141,10 @@ async fn post_newsletters_redirects_to_login_if_not_logged_in() {
 async fn newsletter_creation_is_idempotent() {
     // arrange
     let app = spawn_app().await;
-    create_confirmed_subscriber(&app).await;
-    app.post_login(&serde_json::json!({
-        "username": &app.test_user.username,
-        "password": &app.test_user.password,
-    }))
-    .await;
+    app.create_confirmed_subscriber().await;
+    app.log_in().await;
 
-    Mock::given(path("/v3/mail/send"))
-        .and(method("POST"))
+    when_sending_an_email()
         .respond_with(ResponseTemplate::new(200))
         .expect(1)
         .mount(&app.email_server)
@@ -219,17 +161,17 @@ async fn newsletter_creation_is_idempotent() {
     });
 
     let response = app.post_newsletters(&request_body).await;
-    assert_is_redirect_to(&response, "/admin/newsletter");
+    assert_is_redirect_to(&response, "/admin/newsletter/published");
 
     // Act - Part 2 - Follow the redirect
-    let html_page = app.get_publish_newsletter_html().await;
+    let html_page = app.get_newsletter_published_html().await;
     dbg!(&html_page);
     assert!(html_page.contains("The newsletter issue has been accepted"));
 
     // Act - Part 3 - Submit the same requst again
     let response = app.post_newsletters(&request_body).await;
-    assert_is_redirect_to(&response, "/admin/newsletter");
-    let html_page = app.get_publish_newsletter_html().await;
+    assert_is_redirect_to(&response, "/admin/newsletter/published");
+    let html_page = app.get_newsletter_published_html().await;
     dbg!(&html_page);
 
     assert!(html_page.contains("The newsletter issue has been accepted"));
@@ -242,12 +184,8 @@ async fn newsletter_creation_is_idempotent() {
 async fn concurrent_form_submission_is_handled_ok() {
     // arrange
     let app = spawn_app().await;
-    create_confirmed_subscriber(&app).await;
-    app.post_login(&serde_json::json!({
-        "username": &app.test_user.username,
-        "password": &app.test_user.password,
-    }))
-    .await;
+    app.create_confirmed_subscriber().await;
+    app.log_in().await;
 
     // The first email request will have long delay.
     Mock::given(path("/v3/mail/send"))
@@ -282,6 +220,148 @@ async fn concurrent_form_submission_is_handled_ok() {
     // Mock verifies on Drop that we have sent the email one.
 }
 
+#[tokio::test]
+async fn reusing_an_idempotency_key_with_a_different_body_returns_422() {
+    // arrange
+    let app = spawn_app().await;
+    app.log_in().await;
+    let idempotency_key = uuid::Uuid::new_v4().to_string();
+    let request_body = serde_json::json!({
+        "title": "Newsletter title",
+        "text_content": "Newsletter body as plain text",
+        "html_content": "<p>Newsletter body as HTML</p>",
+        "idempotency_key": idempotency_key,
+    });
+    let response = app.post_newsletters(&request_body).await;
+    assert_is_redirect_to(&response, "/admin/newsletter/published");
+
+    // Act - same key, different title.
+    let request_body = serde_json::json!({
+        "title": "Another newsletter title",
+        "text_content": "Newsletter body as plain text",
+        "html_content": "<p>Newsletter body as HTML</p>",
+        "idempotency_key": idempotency_key,
+    });
+    let response = app.post_newsletters(&request_body).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 422);
+}
+
+#[tokio::test]
+async fn an_in_progress_idempotency_key_returns_409_with_retry_after() {
+    // arrange
+    let app = spawn_app().await;
+    app.log_in().await;
+    // A key that was claimed but never got a response.
+    let idempotency_key = uuid::Uuid::new_v4().to_string();
+    let scope = IdempotencyScope::user(app.test_user.user_id);
+    sqlx::query!(
+        r#"
+        INSERT INTO idempotency (scope, idempotency_key, created_at)
+        VALUES ($1, $2, now())
+        "#,
+        scope.as_ref(),
+        idempotency_key,
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    // Act
+    let request_body = serde_json::json!({
+        "title": "Newsletter title",
+        "text_content": "Newsletter body as plain text",
+        "html_content": "<p>Newsletter body as HTML</p>",
+        "idempotency_key": idempotency_key,
+    });
+    let response = app.post_newsletters(&request_body).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 409);
+    assert!(response.headers().get("Retry-After").is_some());
+}
+
+#[tokio::test]
+async fn delivery_tasks_carry_the_trace_context_of_the_publish_request() {
+    // Arrange
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+    app.log_in().await;
+    let trace_id = "4bf92f3577b34da6a3ce929d0e0e4736";
+
+    // Act
+    let response = app
+        .api_client
+        .post(format!("{}/admin/newsletter", &app.address))
+        .header("X-CSRF-Token", app.get_csrf_token().await.unwrap())
+        .header(
+            "traceparent",
+            format!("00-{}-00f067aa0ba902b7-01", trace_id),
+        )
+        .form(&serde_json::json!({
+            "title": "Newsletter title",
+            "text_content": "Newsletter body as plain text",
+            "html_content": "<p>Newsletter body as HTML</p>",
+            "idempotency_key": uuid::Uuid::new_v4().to_string(),
+        }))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/newsletter/published");
+    let trace_context = sqlx::query_scalar!("SELECT trace_context FROM issue_delivery_queue")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .expect("The delivery task has no trace context.");
+    // Same trace, the span id is the one of the span enqueueing the task.
+    assert!(trace_context.starts_with(&format!("00-{}-", trace_id)));
+}
+
+#[tokio::test]
+async fn delivery_emails_carry_the_request_id_of_the_publish_request() {
+    // Arrange
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+    app.log_in().await;
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    app.api_client
+        .post(format!("{}/admin/newsletter", &app.address))
+        .header("X-CSRF-Token", app.get_csrf_token().await.unwrap())
+        .header("X-Request-Id", "publish-1234")
+        .form(&serde_json::json!({
+            "title": "Newsletter title",
+            "text_content": "Newsletter body as plain text",
+            "html_content": "<p>Newsletter body as HTML</p>",
+            "idempotency_key": uuid::Uuid::new_v4().to_string(),
+        }))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    assert_eq!(
+        email_request.headers.get("X-Request-Id").unwrap(),
+        "publish-1234"
+    );
+}
+
 // Short-hand for a common mocking setup
 fn when_sending_an_email() -> MockBuilder {
     Mock::given(path("/v3/mail/send")).and(method("POST"))
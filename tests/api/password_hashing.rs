@@ -0,0 +1,150 @@
+use argon2::password_hash::SaltString;
+use argon2::{Algorithm, Argon2, Params, PasswordHasher, Version};
+
+use crate::spawn_app::{assert_is_redirect_to, spawn_app, spawn_app_with, TestApp};
+
+/// Replaces the test user's hash with one computed with `argon2`.
+async fn store_password_hash_with(app: &TestApp, argon2: Argon2<'_>) -> String {
+    let salt = SaltString::generate(&mut rand::thread_rng());
+    let password_hash = argon2
+        .hash_password(app.test_user.password.as_bytes(), &salt)
+        .unwrap()
+        .to_string();
+    sqlx::query!(
+        "UPDATE users SET password_hash = $1 WHERE user_id = $2",
+        password_hash,
+        app.test_user.user_id,
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    password_hash
+}
+
+/// The hashing parameters `compute_password_hash` used to have.
+fn legacy_argon2() -> Argon2<'static> {
+    Argon2::new(
+        Algorithm::Argon2d,
+        Version::V0x13,
+        Params::new(15000, 2, 1, None).unwrap(),
+    )
+}
+
+async fn stored_password_hash(app: &TestApp) -> String {
+    sqlx::query_scalar!(
+        "SELECT password_hash FROM users WHERE user_id = $1",
+        app.test_user.user_id,
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+}
+
+async fn log_in_with(app: &TestApp, password: &str) -> reqwest::Response {
+    app.post_login(&serde_json::json!({
+        "username": &app.test_user.username,
+        "password": password,
+    }))
+    .await
+}
+
+#[tokio::test]
+async fn legacy_hashes_are_upgraded_on_login() {
+    // Arrange
+    let app = spawn_app().await;
+    store_password_hash_with(&app, legacy_argon2()).await;
+
+    // Act
+    let response = log_in_with(&app, &app.test_user.password).await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/dashboard");
+    assert!(stored_password_hash(&app)
+        .await
+        .starts_with("$argon2id$v=19$m=19456,t=2,p=1$"));
+}
+
+#[tokio::test]
+async fn the_upgraded_hash_still_matches_the_password() {
+    // Arrange
+    let app = spawn_app().await;
+    store_password_hash_with(&app, legacy_argon2()).await;
+    log_in_with(&app, &app.test_user.password).await;
+    app.post_logout().await;
+
+    // Act
+    let response = log_in_with(&app, &app.test_user.password).await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
+
+#[tokio::test]
+async fn hashes_are_upgraded_to_the_configured_parameters() {
+    // Arrange
+    let app = spawn_app_with(|c| {
+        c.password_hashing.memory_kib = 8192;
+        c.password_hashing.iterations = 3;
+    })
+    .await;
+
+    // Act
+    log_in_with(&app, &app.test_user.password).await;
+
+    // Assert
+    assert!(stored_password_hash(&app)
+        .await
+        .starts_with("$argon2id$v=19$m=8192,t=3,p=1$"));
+}
+
+#[tokio::test]
+async fn hashes_following_the_policy_are_left_alone() {
+    // Arrange
+    let app = spawn_app().await;
+    let password_hash = store_password_hash_with(&app, Argon2::default()).await;
+
+    // Act
+    log_in_with(&app, &app.test_user.password).await;
+
+    // Assert
+    assert_eq!(stored_password_hash(&app).await, password_hash);
+}
+
+#[tokio::test]
+async fn failed_logins_do_not_upgrade_hashes() {
+    // Arrange
+    let app = spawn_app().await;
+    let password_hash = store_password_hash_with(&app, legacy_argon2()).await;
+
+    // Act
+    let response = log_in_with(&app, "wrong-password").await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+    assert_eq!(stored_password_hash(&app).await, password_hash);
+}
+
+#[tokio::test]
+async fn changed_passwords_are_hashed_with_the_configured_parameters() {
+    // Arrange
+    let app = spawn_app_with(|c| c.password_hashing.memory_kib = 8192).await;
+    log_in_with(&app, &app.test_user.password).await;
+    let old_password_hash = stored_password_hash(&app).await;
+    let new_password = uuid::Uuid::new_v4().to_string();
+
+    // Act
+    app.post_change_password(&serde_json::json!({
+        "current_password": &app.test_user.password,
+        "new_password": &new_password,
+        "new_password_check": &new_password,
+    }))
+    .await;
+
+    // Assert
+    let password_hash = stored_password_hash(&app).await;
+    assert_ne!(password_hash, old_password_hash);
+    assert!(password_hash.starts_with("$argon2id$v=19$m=8192,t=2,p=1$"));
+    app.post_logout().await;
+    let response = log_in_with(&app, &new_password).await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
@@ -0,0 +1,121 @@
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+use crate::spawn_app::spawn_app;
+
+#[tokio::test]
+async fn responses_carry_a_generated_request_id() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app
+        .api_client
+        .get(format!("{}/health_check", &app.address))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    let request_id = response.headers().get("X-Request-Id").unwrap();
+    assert!(uuid::Uuid::parse_str(request_id.to_str().unwrap()).is_ok());
+}
+
+#[tokio::test]
+async fn a_client_request_id_is_echoed_back() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app
+        .api_client
+        .get(format!("{}/health_check", &app.address))
+        .header("X-Request-Id", "client-request-1234")
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert_eq!(
+        response.headers().get("X-Request-Id").unwrap(),
+        "client-request-1234"
+    );
+}
+
+#[tokio::test]
+async fn an_invalid_client_request_id_is_replaced() {
+    // Arrange
+    let app = spawn_app().await;
+    let overlong_request_id = "a".repeat(200);
+
+    // Act
+    let response = app
+        .api_client
+        .get(format!("{}/health_check", &app.address))
+        .header("X-Request-Id", &overlong_request_id)
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    let request_id = response.headers().get("X-Request-Id").unwrap();
+    assert_ne!(request_id, overlong_request_id.as_str());
+    assert!(uuid::Uuid::parse_str(request_id.to_str().unwrap()).is_ok());
+}
+
+#[tokio::test]
+async fn confirmation_emails_carry_the_request_id() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(path("/v3/mail/send"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    app.api_client
+        .post(format!("{}/subscriptions", &app.address))
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .header("X-Request-Id", "subscribe-1234")
+        .body(format!(
+            "name=le%20guin&email=ursula_le_guin%40gmail.com&form_token={}",
+            app.form_token
+        ))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    assert_eq!(
+        email_request.headers.get("X-Request-Id").unwrap(),
+        "subscribe-1234"
+    );
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert_eq!(body["custom_args"]["request_id"], "subscribe-1234");
+}
+
+#[tokio::test]
+async fn error_pages_show_the_request_id() {
+    // Arrange
+    let app = spawn_app().await;
+    app.log_in().await;
+    app.drop_database().await;
+
+    // Act - the session check of the admin middleware fails, middleware errors
+    // don't get the X-Request-Id header.
+    let response = app
+        .api_client
+        .get(format!("{}/admin/dashboard", &app.address))
+        .header("X-Request-Id", "dashboard-1234")
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert_eq!(500, response.status().as_u16());
+    let body = response.text().await.unwrap();
+    assert!(body.contains("Request id: dashboard-1234"));
+}
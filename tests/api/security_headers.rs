@@ -0,0 +1,162 @@
+use zero2prod2::configuration::Environment;
+
+use crate::spawn_app::{spawn_app, spawn_app_with};
+
+fn content_security_policy(response: &reqwest::Response) -> &str {
+    response
+        .headers()
+        .get("Content-Security-Policy")
+        .expect("No Content-Security-Policy header.")
+        .to_str()
+        .unwrap()
+}
+
+#[tokio::test]
+async fn public_pages_carry_the_security_headers() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app
+        .api_client
+        .get(format!("{}/login", &app.address))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert!(content_security_policy(&response).contains("frame-ancestors 'none'"));
+    assert_eq!(response.headers().get("X-Frame-Options").unwrap(), "DENY");
+    assert_eq!(
+        response.headers().get("X-Content-Type-Options").unwrap(),
+        "nosniff"
+    );
+    assert_eq!(
+        response.headers().get("Referrer-Policy").unwrap(),
+        "no-referrer"
+    );
+}
+
+#[tokio::test]
+async fn admin_pages_get_a_stricter_policy_than_public_ones() {
+    // Arrange
+    let app = spawn_app().await;
+    app.log_in().await;
+
+    // Act
+    let public_page = app
+        .api_client
+        .get(format!("{}/", &app.address))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    let admin_page = app.get_admin_dashboard().await;
+
+    // Assert
+    assert_eq!(admin_page.status().as_u16(), 200);
+    assert!(content_security_policy(&admin_page).starts_with("default-src 'none'"));
+    assert_ne!(
+        content_security_policy(&admin_page),
+        content_security_policy(&public_page)
+    );
+}
+
+#[tokio::test]
+async fn the_issue_preview_renders_the_issue_html_under_a_relaxed_policy() {
+    // Arrange
+    let app = spawn_app().await;
+    app.log_in().await;
+    let body = serde_json::json!({
+        "title": "Newsletter title",
+        "text_content": "Newsletter body as plain text",
+        "html_content": r#"<p style="color: red">Newsletter body as HTML</p>"#,
+    });
+
+    // Act
+    let response = app
+        .api_client
+        .post(format!("{}/admin/newsletter/preview", &app.address))
+        .form(&app.with_csrf_token(&body).await)
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let policy = content_security_policy(&response).to_owned();
+    assert!(policy.contains("img-src https: data:"));
+    assert!(!policy.contains("script-src"));
+    let html = response.text().await.unwrap();
+    assert!(html.contains(r#"<p style="color: red">Newsletter body as HTML</p>"#));
+}
+
+#[tokio::test]
+async fn hsts_is_not_sent_outside_of_production() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app
+        .api_client
+        .get(format!("{}/login", &app.address))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert!(response
+        .headers()
+        .get("Strict-Transport-Security")
+        .is_none());
+}
+
+#[tokio::test]
+async fn hsts_is_sent_in_production() {
+    // Arrange
+    let app = spawn_app_with(|c| c.environment = Environment::Production).await;
+
+    // Act
+    let response = app
+        .api_client
+        .get(format!("{}/login", &app.address))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert_eq!(
+        response.headers().get("Strict-Transport-Security").unwrap(),
+        "max-age=31536000"
+    );
+}
+
+#[tokio::test]
+async fn error_responses_carry_the_security_headers() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act - a redirect to the login page, an API 401 and a CSRF 403.
+    let redirect = app.get_admin_dashboard().await;
+    let unauthorized = app
+        .api_client
+        .get(format!("{}/api/v1/issues", &app.address))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    app.log_in().await;
+    let forbidden = app
+        .api_client
+        .post(format!("{}/admin/logout", &app.address))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert_eq!(redirect.status().as_u16(), 303);
+    assert_eq!(unauthorized.status().as_u16(), 401);
+    assert_eq!(forbidden.status().as_u16(), 403);
+    for response in [&redirect, &unauthorized, &forbidden] {
+        assert!(!content_security_policy(response).is_empty());
+        assert_eq!(response.headers().get("X-Frame-Options").unwrap(), "DENY");
+    }
+}
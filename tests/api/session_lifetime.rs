@@ -0,0 +1,176 @@
+use std::time::Duration;
+
+use zero2prod2::configuration::{SessionStoreKind, Settings};
+
+use crate::spawn_app::{assert_is_redirect_to, spawn_app, spawn_app_with, TestApp};
+
+/// Stores sessions in postgres, where tests can move their clock back.
+async fn spawn_app_with_postgres_sessions(customise: impl FnOnce(&mut Settings)) -> TestApp {
+    spawn_app_with(|c| {
+        c.session.store = SessionStoreKind::Postgres;
+        customise(c);
+    })
+    .await
+}
+
+#[tokio::test]
+async fn idle_sessions_are_logged_out() {
+    // Arrange
+    let app = spawn_app_with_postgres_sessions(|c| c.session.idle_timeout_seconds = 60).await;
+    app.log_in().await;
+
+    // Act
+    app.backdate_session(Duration::from_secs(61)).await;
+    let response = app.get_admin_dashboard().await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+    let html = app.get_login_html().await;
+    assert!(html.contains("Your session has expired, please log in again."));
+}
+
+#[tokio::test]
+async fn the_login_form_has_a_remember_me_checkbox() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let html = app.get_login_html().await;
+
+    // Assert
+    assert!(html.contains(r#"<input type="checkbox" name="remember_me">"#));
+}
+
+/// Confirmed subscribers, without going through the confirmation emails.
+async fn create_confirmed_subscribers(app: &TestApp, n: usize) {
+    for i in 0..n {
+        sqlx::query(
+            r#"
+            INSERT INTO subscriptions (id, email, name, subscribed_at, status)
+            VALUES (gen_random_uuid(), $1, 'Subscriber', now(), 'confirmed')
+            "#,
+        )
+        .bind(format!("subscriber-{}@example.com", i))
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    }
+}
+
+async fn publish_newsletter(app: &TestApp) -> reqwest::Response {
+    app.post_newsletters(&serde_json::json!({
+        "title": "Newsletter title",
+        "text_content": "Newsletter body as plain text",
+        "html_content": "<p>Newsletter body as HTML</p>",
+        "idempotency_key": uuid::Uuid::new_v4().to_string(),
+    }))
+    .await
+}
+
+async fn count_issues(app: &TestApp) -> i64 {
+    sqlx::query_scalar!(r#"SELECT COUNT(*) AS "count!" FROM newsletter_issues"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+}
+
+async fn post_reauthenticate(app: &TestApp, password: &str) -> reqwest::Response {
+    app.api_client
+        .post(format!("{}/admin/reauthenticate", &app.address))
+        .form(
+            &app.with_csrf_token(&serde_json::json!({ "password": password }))
+                .await,
+        )
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+async fn get_reauthenticate_html(app: &TestApp) -> String {
+    app.api_client
+        .get(format!("{}/admin/reauthenticate", &app.address))
+        .send()
+        .await
+        .expect("Failed to execute request.")
+        .text()
+        .await
+        .unwrap()
+}
+
+/// Publishing to more than one subscriber needs a password typed in the last minute.
+async fn spawn_app_with_reauthentication() -> TestApp {
+    spawn_app_with_postgres_sessions(|c| {
+        c.session.reauthentication_subscriber_threshold = 1;
+        c.session.reauthentication_timeout_seconds = 60;
+    })
+    .await
+}
+
+#[tokio::test]
+async fn large_publishes_require_a_recent_password() {
+    // Arrange
+    let app = spawn_app_with_reauthentication().await;
+    create_confirmed_subscribers(&app, 2).await;
+    app.log_in().await;
+    app.backdate_session(Duration::from_secs(61)).await;
+
+    // Act
+    let response = publish_newsletter(&app).await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/reauthenticate");
+    assert_eq!(count_issues(&app).await, 0);
+    let html = get_reauthenticate_html(&app).await;
+    assert!(html.contains("Publishing to more than 1 subscribers requires your password."));
+}
+
+#[tokio::test]
+async fn small_publishes_do_not_require_a_recent_password() {
+    // Arrange
+    let app = spawn_app_with_reauthentication().await;
+    create_confirmed_subscribers(&app, 1).await;
+    app.log_in().await;
+    app.backdate_session(Duration::from_secs(61)).await;
+
+    // Act
+    let response = publish_newsletter(&app).await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/newsletter/published");
+    assert_eq!(count_issues(&app).await, 1);
+}
+
+#[tokio::test]
+async fn large_publishes_go_through_after_reauthenticating() {
+    // Arrange
+    let app = spawn_app_with_reauthentication().await;
+    create_confirmed_subscribers(&app, 2).await;
+    app.log_in().await;
+    app.backdate_session(Duration::from_secs(61)).await;
+
+    // Act - Part 1 - Reauthenticate
+    let response = post_reauthenticate(&app, &app.test_user.password).await;
+    assert_is_redirect_to(&response, "/admin/newsletter");
+
+    // Act - Part 2 - Publish
+    let response = publish_newsletter(&app).await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/newsletter/published");
+    assert_eq!(count_issues(&app).await, 1);
+}
+
+#[tokio::test]
+async fn reauthenticating_with_a_wrong_password_is_rejected() {
+    // Arrange
+    let app = spawn_app_with_reauthentication().await;
+    app.log_in().await;
+
+    // Act
+    let response = post_reauthenticate(&app, "wrong-password").await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/reauthenticate");
+    let html = get_reauthenticate_html(&app).await;
+    assert!(html.contains("The password is incorrect."));
+}
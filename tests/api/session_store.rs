@@ -0,0 +1,125 @@
+use secrecy::Secret;
+use zero2prod2::configuration::SessionStoreKind;
+use zero2prod2::session_store::delete_expired_sessions;
+
+use crate::spawn_app::{assert_is_redirect_to, spawn_app_with, TestApp};
+
+/// Stores sessions in postgres, with a redis uri nothing listens on.
+async fn spawn_app_without_redis() -> TestApp {
+    spawn_app_with(|c| {
+        c.session.store = SessionStoreKind::Postgres;
+        c.redis_uri = Secret::new("redis://127.0.0.1:1".into());
+    })
+    .await
+}
+
+async fn count_sessions(app: &TestApp) -> i64 {
+    sqlx::query_scalar!(r#"SELECT COUNT(*) AS "count!" FROM sessions"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+}
+
+async fn log_in(app: &TestApp) -> reqwest::Response {
+    app.post_login(&serde_json::json!({
+        "username": &app.test_user.username,
+        "password": &app.test_user.password,
+    }))
+    .await
+}
+
+#[tokio::test]
+async fn admins_can_log_in_with_sessions_stored_in_postgres() {
+    // Arrange
+    let app = spawn_app_without_redis().await;
+
+    // Act
+    let response = log_in(&app).await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/dashboard");
+    let html = app.get_admin_dashboard_html().await;
+    assert!(html.contains(&format!("Welcome {}", app.test_user.username)));
+    assert_eq!(count_sessions(&app).await, 1);
+}
+
+#[tokio::test]
+async fn session_keys_are_not_stored_in_clear() {
+    // Arrange
+    let app = spawn_app_without_redis().await;
+
+    // Act
+    let response = log_in(&app).await;
+
+    // Assert
+    let session_key = response
+        .cookies()
+        .find(|c| c.name() == "id")
+        .expect("No session cookie.")
+        .value()
+        .to_owned();
+    let key_hash = sqlx::query_scalar!("SELECT key_hash FROM sessions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(!key_hash.contains(&session_key));
+}
+
+#[tokio::test]
+async fn logging_out_deletes_the_postgres_session() {
+    // Arrange
+    let app = spawn_app_without_redis().await;
+    log_in(&app).await;
+
+    // Act
+    let response = app.post_logout().await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+    assert_eq!(count_sessions(&app).await, 0);
+    let response = app.get_admin_dashboard().await;
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn redis_is_not_checked_for_readiness_with_sessions_in_postgres() {
+    // Arrange
+    let app = spawn_app_without_redis().await;
+
+    // Act
+    let response = app
+        .api_client
+        .get(format!("{}/health/ready", &app.address))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert!(body["checks"]["redis"].is_null());
+}
+
+#[tokio::test]
+async fn expired_sessions_are_ignored_then_deleted() {
+    // Arrange
+    let app = spawn_app_without_redis().await;
+    log_in(&app).await;
+    sqlx::query!("UPDATE sessions SET expires_at = now() - interval '1 second'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    // Act - Part 1 - The expired session is gone
+    let response = app.get_admin_dashboard().await;
+
+    // Assert - Part 1
+    assert_is_redirect_to(&response, "/login");
+
+    // Act - Part 2 - Cleanup
+    let n_deleted_sessions = delete_expired_sessions(&app.db_pool).await.unwrap();
+
+    // Assert - Part 2
+    assert!(n_deleted_sessions >= 1);
+    assert_eq!(count_sessions(&app).await, 0);
+}
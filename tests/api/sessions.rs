@@ -0,0 +1,169 @@
+use uuid::Uuid;
+
+use crate::spawn_app::{api_client, assert_is_redirect_to, spawn_app, TestApp};
+
+/// Logs the test user in from `client`, with `user_agent` as its browser.
+async fn log_in_with(app: &TestApp, client: &reqwest::Client, user_agent: &str) {
+    let response = client
+        .post(format!("{}/login", &app.address))
+        .header("User-Agent", user_agent)
+        .form(&serde_json::json!({
+            "username": &app.test_user.username,
+            "password": &app.test_user.password,
+        }))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
+
+async fn get_dashboard_with(app: &TestApp, client: &reqwest::Client) -> reqwest::Response {
+    client
+        .get(format!("{}/admin/dashboard", &app.address))
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+async fn get_sessions_html(app: &TestApp) -> String {
+    app.api_client
+        .get(format!("{}/admin/sessions", &app.address))
+        .send()
+        .await
+        .expect("Failed to execute request.")
+        .text()
+        .await
+        .unwrap()
+}
+
+/// Ids of the sessions that have a revoke button, i.e. all but the current one.
+fn revocable_session_ids(html: &str) -> Vec<Uuid> {
+    html.split(r#"action="/admin/sessions/"#)
+        .skip(1)
+        .filter_map(|rest| rest.split_once("/revoke").map(|(id, _)| id))
+        .filter_map(|id| Uuid::parse_str(id).ok())
+        .collect()
+}
+
+async fn post_with_csrf_token(app: &TestApp, path: &str) -> reqwest::Response {
+    app.api_client
+        .post(format!("{}{}", &app.address, path))
+        .form(&app.with_csrf_token(&serde_json::json!({})).await)
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+#[tokio::test]
+async fn the_sessions_page_lists_the_logged_in_sessions_of_the_user() {
+    // Arrange
+    let app = spawn_app().await;
+    let other_browser = api_client();
+    log_in_with(&app, &app.api_client, "first-browser").await;
+    log_in_with(&app, &other_browser, "second-browser").await;
+
+    // Act
+    let html = get_sessions_html(&app).await;
+
+    // Assert
+    assert!(html.contains("first-browser"));
+    assert!(html.contains("second-browser"));
+    assert_eq!(html.matches("This session").count(), 1);
+    assert_eq!(revocable_session_ids(&html).len(), 1);
+}
+
+#[tokio::test]
+async fn a_revoked_session_is_logged_out() {
+    // Arrange
+    let app = spawn_app().await;
+    let other_browser = api_client();
+    log_in_with(&app, &app.api_client, "first-browser").await;
+    log_in_with(&app, &other_browser, "second-browser").await;
+    let session_ids = revocable_session_ids(&get_sessions_html(&app).await);
+
+    // Act
+    let response =
+        post_with_csrf_token(&app, &format!("/admin/sessions/{}/revoke", session_ids[0])).await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/sessions");
+    let response = get_dashboard_with(&app, &other_browser).await;
+    assert_is_redirect_to(&response, "/login");
+    assert!(!get_sessions_html(&app).await.contains("second-browser"));
+}
+
+#[tokio::test]
+async fn logging_out_everywhere_else_keeps_the_current_session() {
+    // Arrange
+    let app = spawn_app().await;
+    let other_browsers = [api_client(), api_client()];
+    log_in_with(&app, &app.api_client, "first-browser").await;
+    for browser in &other_browsers {
+        log_in_with(&app, browser, "another-browser").await;
+    }
+
+    // Act
+    let response = post_with_csrf_token(&app, "/admin/sessions/revoke_others").await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/sessions");
+    for browser in &other_browsers {
+        let response = get_dashboard_with(&app, browser).await;
+        assert_is_redirect_to(&response, "/login");
+    }
+    assert_eq!(app.get_admin_dashboard().await.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn changing_password_logs_out_the_other_sessions() {
+    // Arrange
+    let app = spawn_app().await;
+    let other_browser = api_client();
+    log_in_with(&app, &app.api_client, "first-browser").await;
+    log_in_with(&app, &other_browser, "second-browser").await;
+    let new_password = Uuid::new_v4().to_string();
+
+    // Act
+    let response = app
+        .post_change_password(&serde_json::json!({
+            "current_password": &app.test_user.password,
+            "new_password": &new_password,
+            "new_password_check": &new_password,
+        }))
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/password");
+    let response = get_dashboard_with(&app, &other_browser).await;
+    assert_is_redirect_to(&response, "/login");
+    assert_eq!(app.get_admin_dashboard().await.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn logging_out_removes_the_session_from_the_list() {
+    // Arrange
+    let app = spawn_app().await;
+    let other_browser = api_client();
+    log_in_with(&app, &app.api_client, "first-browser").await;
+    log_in_with(&app, &other_browser, "second-browser").await;
+
+    // Act
+    let html = get_dashboard_with(&app, &other_browser)
+        .await
+        .text()
+        .await
+        .unwrap();
+    let (_, rest) = html
+        .split_once(r#"<meta name="csrf-token" content=""#)
+        .unwrap();
+    let (csrf_token, _) = rest.split_once('"').unwrap();
+    other_browser
+        .post(format!("{}/admin/logout", &app.address))
+        .form(&serde_json::json!({ "csrf_token": csrf_token }))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert!(!get_sessions_html(&app).await.contains("second-browser"));
+}
@@ -1,16 +1,24 @@
 use argon2::password_hash::SaltString;
 use argon2::{Argon2, PasswordHasher};
+use fake::faker::internet::en::SafeEmail;
+use fake::faker::name::en::Name;
+use fake::Fake;
 use once_cell::sync::Lazy;
 use reqwest::Url;
 use sqlx::{Connection, Executor, PgConnection, PgPool};
+use std::time::Duration;
 use uuid::Uuid;
-use wiremock::MockServer;
-use zero2prod2::configuration::get_configuration;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+use zero2prod2::configuration::{get_configuration, DatabaseSettings, Settings, TelemetrySettings};
 use zero2prod2::email_client::EmailClient;
 use zero2prod2::issue_delivery_worker::{try_execute_task, ExecutionOutcome};
 use zero2prod2::startup::{get_connection_pool, Application};
 use zero2prod2::telemetry::{get_subscriber, init_subscriber};
 
+/// Accept header sent by browsers when navigating.
+pub const BROWSER_ACCEPT: &str = "text/html,application/xhtml+xml,application/xml;q=0.9,*/*;q=0.8";
+
 pub struct TestUser {
     pub user_id: Uuid,
     pub username: String,
@@ -61,6 +69,10 @@ pub struct TestApp {
     pub test_user: TestUser,
     /// Email client used to send notifcations.
     pub email_client: EmailClient,
+    /// Address /metrics is served from, None when it's served on `address`.
+    pub metrics_address: Option<String>,
+    /// Form token from the home page, sent along with the subscriptions.
+    pub form_token: String,
 }
 
 /// Confirmation links embedded inthe email API.
@@ -82,10 +94,26 @@ impl TestApp {
             }
         }
     }
+    /// Drops the application's database from under it, to simulate postgres being down.
+    pub async fn drop_database(&self) {
+        let database_name: String = sqlx::query_scalar("SELECT current_database()")
+            .fetch_one(&self.db_pool)
+            .await
+            .unwrap();
+        let configuration = get_configuration().unwrap();
+        let mut connection = PgConnection::connect_with(&configuration.database.without_db())
+            .await
+            .unwrap();
+        connection
+            .execute(format!(r#"DROP DATABASE "{}" WITH (FORCE);"#, database_name).as_str())
+            .await
+            .expect("Failed to drop the database.");
+    }
+
     /// Fetches the /login html.
     pub async fn get_login_html(&self) -> String {
         self.api_client
-            .get(&format!("{}/login", &self.address))
+            .get(format!("{}/login", &self.address))
             .send()
             .await
             .expect("Failed to get login html.")
@@ -102,7 +130,7 @@ impl TestApp {
     /// Fetches the /admin/dashboard page.
     pub async fn get_admin_dashboard(&self) -> reqwest::Response {
         self.api_client
-            .get(&format!("{}/admin/dashboard", &self.address))
+            .get(format!("{}/admin/dashboard", &self.address))
             .send()
             .await
             .expect("Failed to get admin dashboard.")
@@ -116,7 +144,7 @@ impl TestApp {
     /// Fetches the /admin/password page.
     pub async fn get_change_password(&self) -> reqwest::Response {
         self.api_client
-            .get(&format!("{}/admin/password", &self.address))
+            .get(format!("{}/admin/password", &self.address))
             .send()
             .await
             .expect("Failed to get admin dashboard.")
@@ -128,8 +156,8 @@ impl TestApp {
         Body: serde::Serialize,
     {
         self.api_client
-            .post(&format!("{}/admin/password", &self.address))
-            .form(body)
+            .post(format!("{}/admin/password", &self.address))
+            .form(&self.with_csrf_token(body).await)
             .send()
             .await
             .expect("Failed to post request.")
@@ -141,28 +169,113 @@ impl TestApp {
         Body: serde::Serialize,
     {
         self.api_client
-            .post(&format!("{}/login", &self.address))
+            .post(format!("{}/login", &self.address))
             .form(body)
             .send()
             .await
             .expect("Failed to login post request.")
     }
 
+    /// Logs in as the test user.
+    pub async fn log_in(&self) {
+        let response = self
+            .post_login(&serde_json::json!({
+                "username": &self.test_user.username,
+                "password": &self.test_user.password,
+            }))
+            .await;
+        assert_is_redirect_to(&response, "/admin/dashboard");
+    }
+
     /// Sends a POST /admin/logout.
     pub async fn post_logout(&self) -> reqwest::Response {
         self.api_client
-            .post(&format!("{}/admin/logout", &self.address))
+            .post(format!("{}/admin/logout", &self.address))
+            .form(&self.with_csrf_token(&serde_json::json!({})).await)
             .send()
             .await
             .expect("Failed to logout post request.")
     }
 
-    /// Sends a POST /subscriptions with the given body.
+    /// The CSRF token of the current session, read from the admin dashboard.
+    /// `None` if not logged in.
+    pub async fn get_csrf_token(&self) -> Option<String> {
+        let response = self.get_admin_dashboard().await;
+        if response.status().as_u16() != 200 {
+            return None;
+        }
+        let html = response.text().await.unwrap();
+        let (_, rest) = html.split_once(r#"<meta name="csrf-token" content=""#)?;
+        rest.split_once('"').map(|(token, _)| token.to_owned())
+    }
+
+    /// Adds the session's CSRF token to a form body, if logged in.
+    pub async fn with_csrf_token<Body>(&self, body: &Body) -> serde_json::Value
+    where
+        Body: serde::Serialize,
+    {
+        let mut body = serde_json::to_value(body).unwrap();
+        if let Some(token) = self.get_csrf_token().await {
+            body["csrf_token"] = token.into();
+        }
+        body
+    }
+
+    /// Fetches the / html.
+    pub async fn get_home_html(&self) -> String {
+        self.api_client
+            .get(&self.address)
+            .send()
+            .await
+            .expect("Failed to get home html.")
+            .text()
+            .await
+            .unwrap()
+    }
+
+    /// Fetches a fresh form token from the home page.
+    pub async fn get_form_token(&self) -> String {
+        let html = self.get_home_html().await;
+        let (_, rest) = html
+            .split_once(r#"name="form_token" value=""#)
+            .expect("No form token on the home page.");
+        rest.split('"').next().unwrap().to_owned()
+    }
+
+    /// Sends a POST /subscriptions with the given body, and the form token.
     pub async fn post_subscriptions(&self, body: String) -> reqwest::Response {
         self.api_client
-            .post(&format!("{}/subscriptions", &self.address))
+            .post(format!("{}/subscriptions", &self.address))
             .header("Content-Type", "application/x-www-form-urlencoded")
-            .body(body)
+            .body(format!("{}&form_token={}", body, self.form_token))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    /// Sends a POST /subscriptions with the given body and the form token, like a browser would.
+    pub async fn post_subscriptions_from_browser(&self, body: String) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/subscriptions", &self.address))
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .header("Accept", BROWSER_ACCEPT)
+            .body(format!("{}&form_token={}", body, self.form_token))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    /// Sends a POST /subscriptions with the given body, the form token and Idempotency-Key header.
+    pub async fn post_subscriptions_with_idempotency_key(
+        &self,
+        body: String,
+        idempotency_key: &str,
+    ) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/subscriptions", &self.address))
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .header("Idempotency-Key", idempotency_key)
+            .body(format!("{}&form_token={}", body, self.form_token))
             .send()
             .await
             .expect("Failed to execute request.")
@@ -171,8 +284,8 @@ impl TestApp {
     /// Sends a POST /admin/newsletter with the given body.
     pub async fn post_newsletters(&self, body: &serde_json::Value) -> reqwest::Response {
         self.api_client
-            .post(&format!("{}/admin/newsletter", &self.address))
-            .form(&body)
+            .post(format!("{}/admin/newsletter", &self.address))
+            .form(&self.with_csrf_token(body).await)
             .send()
             .await
             .expect("Failed to execute request.")
@@ -183,15 +296,124 @@ impl TestApp {
         self.get_publish_newsletter().await.text().await.unwrap()
     }
 
+    /// Follows the redirect of a successful publish, returns the /admin/newsletter html
+    /// it ends up on.
+    pub async fn get_newsletter_published_html(&self) -> String {
+        let response = self
+            .api_client
+            .get(format!("{}/admin/newsletter/published", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.");
+        assert_is_redirect_to(&response, "/admin/newsletter");
+        self.get_publish_newsletter_html().await
+    }
+
     /// Fetches the /admin/newsletter page.
     pub async fn get_publish_newsletter(&self) -> reqwest::Response {
         self.api_client
-            .get(&format!("{}/admin/newsletter", &self.address))
+            .get(format!("{}/admin/newsletter", &self.address))
             .send()
             .await
             .expect("Failed to get admin dashboard.")
     }
 
+    /// Moves the times recorded in the session states back by `by`, as if that long had
+    /// passed since the last request. Sessions must be stored in postgres.
+    pub async fn backdate_session(&self, by: Duration) {
+        for key in ["logged_in_at", "last_seen_at", "authenticated_at"] {
+            sqlx::query(
+                r#"
+                UPDATE sessions
+                SET state = jsonb_set(
+                    state::jsonb,
+                    ARRAY[$1],
+                    to_jsonb(((state::jsonb ->> $1)::bigint - $2)::text)
+                )::text
+                "#,
+            )
+            .bind(key)
+            .bind(by.as_millis() as i64)
+            .execute(&self.db_pool)
+            .await
+            .unwrap();
+        }
+    }
+
+    /// Sends a POST /admin/api_tokens asking for a token named `name` with `scopes`.
+    pub async fn post_api_token(&self, name: &str, scopes: &[&str]) -> reqwest::Response {
+        let mut form = vec![
+            (
+                "csrf_token",
+                self.get_csrf_token().await.unwrap_or_default(),
+            ),
+            ("name", name.to_owned()),
+            ("expires_in_days", "30".to_owned()),
+        ];
+        form.extend(scopes.iter().map(|scope| ("scope", scope.to_string())));
+        self.api_client
+            .post(format!("{}/admin/api_tokens", &self.address))
+            .form(&form)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    /// Creates a token through /admin/api_tokens and returns it, as shown on the page.
+    /// Must be logged in.
+    pub async fn create_api_token(&self, scopes: &[&str]) -> String {
+        let response = self.post_api_token("CMS", scopes).await;
+        assert_eq!(response.status().as_u16(), 200);
+        let html = response.text().await.unwrap();
+        let (_, rest) = html
+            .split_once(r#"<code id="new-token">"#)
+            .expect("The new token isn't shown.");
+        rest.split_once('<').unwrap().0.to_owned()
+    }
+
+    /// Subscribes a random subscriber, returns the links of their confirmation email.
+    pub async fn create_unconfirmed_subscriber(&self) -> ConfirmationLinks {
+        // Some tests use multiple subscribers -- randomise to avoid conflicts.
+        let name: String = Name().fake();
+        let email: String = SafeEmail().fake();
+        let body = serde_urlencoded::to_string(serde_json::json!({
+            "name": name,
+            "email": email
+        }))
+        .unwrap();
+        let _mock_guard = Mock::given(path("/v3/mail/send"))
+            .and(method("POST"))
+            .respond_with(ResponseTemplate::new(200))
+            .named("Create unconfirmed subscriber")
+            .expect(1)
+            .mount_as_scoped(&self.email_server)
+            .await;
+        self.post_subscriptions(body)
+            .await
+            .error_for_status()
+            .unwrap();
+
+        let email_request = &self
+            .email_server
+            .received_requests()
+            .await
+            .unwrap()
+            .pop()
+            .unwrap();
+
+        self.get_confirmation_links(email_request)
+    }
+
+    /// Subscribes a random subscriber and confirms them.
+    pub async fn create_confirmed_subscriber(&self) {
+        let confirmation_link = self.create_unconfirmed_subscriber().await;
+        reqwest::get(confirmation_link.html)
+            .await
+            .unwrap()
+            .error_for_status()
+            .unwrap();
+    }
+
     /// Extract  the confirmation links embedded in the request to the email API.
     pub fn get_confirmation_links(&self, email_request: &wiremock::Request) -> ConfirmationLinks {
         let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
@@ -210,8 +432,8 @@ impl TestApp {
             link
         };
 
-        let html = get_link(&body["content"][0]["value"].as_str().unwrap());
-        let plain_text = get_link(&body["content"][1]["value"].as_str().unwrap());
+        let html = get_link(body["content"][0]["value"].as_str().unwrap());
+        let plain_text = get_link(body["content"][1]["value"].as_str().unwrap());
         ConfirmationLinks { html, plain_text }
     }
 }
@@ -219,17 +441,33 @@ impl TestApp {
 static TRACING: Lazy<()> = Lazy::new(|| {
     let default_filter_level = "info".to_string();
     let subscriber_name = "test".to_string();
+    let settings = TelemetrySettings::default();
     if std::env::var("TEST_LOG").is_ok() {
-        let subscriber = get_subscriber(subscriber_name, default_filter_level, std::io::stdout);
-        init_subscriber(subscriber);
+        let (subscriber, log_filter) = get_subscriber(
+            subscriber_name,
+            default_filter_level,
+            &settings,
+            std::io::stdout,
+        );
+        init_subscriber(subscriber, log_filter);
     } else {
-        let subscriber = get_subscriber(subscriber_name, default_filter_level, std::io::sink);
-        init_subscriber(subscriber);
+        let (subscriber, log_filter) = get_subscriber(
+            subscriber_name,
+            default_filter_level,
+            &settings,
+            std::io::sink,
+        );
+        init_subscriber(subscriber, log_filter);
     }
 });
 
 /// Launch our application in the background and returns address
 pub async fn spawn_app() -> TestApp {
+    spawn_app_with(|_| {}).await
+}
+
+/// Same as `spawn_app`, with `customise` applied to the test configuration.
+pub async fn spawn_app_with(customise: impl FnOnce(&mut Settings)) -> TestApp {
     Lazy::force(&TRACING);
 
     // Launch a fake email server to stand in for SendGrid.
@@ -237,55 +475,29 @@ pub async fn spawn_app() -> TestApp {
 
     // Create a fresh DB for this test run.
     let configuration = {
-        let mut configuration = get_configuration().expect("failed to get configuration");
-        // Use a random name to make tests hermetic.
-        configuration.database.database_name = Uuid::new_v4().to_string();
-        // Use a random OS port.
-        configuration.application.port = 0;
+        let mut configuration = test_configuration();
         // Use fake mail server
         configuration.email_client.base_url = email_server.uri();
+        customise(&mut configuration);
         configuration
     };
 
     // Configure DB pool connection.
-    let _ = {
-        let config = &configuration.database;
-        // Create the database
-        let mut connection = PgConnection::connect_with(&config.without_db())
-            .await
-            .expect("failed to connect to postgres");
-
-        connection
-            .execute(format!(r#"CREATE DATABASE "{}";"#, config.database_name).as_str())
-            .await
-            .expect("Failed to create ephemeral database.");
-
-        // migrate the database
-        let connection_pool = PgPool::connect_with(config.with_db())
-            .await
-            .expect("Failed to connect to postgres after creating ephemeral db");
-        sqlx::migrate!("./migrations")
-            .run(&connection_pool)
-            .await
-            .expect("failed to run DB migration ./migrations");
-        connection_pool
-    };
+    configure_database(&configuration.database).await;
 
     let application = Application::build(configuration.clone())
         .await
         .expect("failed to build application.");
     let application_port = application.port();
     let address = format!("http://localhost:{}", application_port);
-    let _ = tokio::spawn(application.run_until_stopped());
+    let metrics_address = application
+        .metrics_port()
+        .map(|port| format!("http://localhost:{}", port));
+    tokio::spawn(application.run_until_stopped());
 
-    // Setup client with cookie store.
-    let client = reqwest::Client::builder()
-        .redirect(reqwest::redirect::Policy::none())
-        .cookie_store(true)
-        .build()
-        .unwrap();
+    let client = api_client();
 
-    let test_app = TestApp {
+    let mut test_app = TestApp {
         address,
         db_pool: get_connection_pool(&configuration.database),
         email_server,
@@ -293,12 +505,67 @@ pub async fn spawn_app() -> TestApp {
         api_client: client,
         test_user: TestUser::generate(),
         email_client: configuration.email_client.client(),
+        metrics_address,
+        form_token: String::new(),
     };
 
     test_app.test_user.store(&test_app.db_pool).await;
+    test_app.form_token = test_app.get_form_token().await;
     test_app
 }
 
+/// Returns a client with its own cookie store, like a separate browser.
+pub fn api_client() -> reqwest::Client {
+    reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .cookie_store(true)
+        .build()
+        .unwrap()
+}
+
+/// Returns the app configuration pointed at a fresh, randomly named database.
+pub fn test_configuration() -> Settings {
+    let mut configuration = get_configuration().expect("failed to get configuration");
+    // Use a random name to make tests hermetic.
+    configuration.database.database_name = Uuid::new_v4().to_string();
+    // Use a random OS port.
+    configuration.application.port = 0;
+    configuration.metrics.port = Some(0);
+    // Tests submit forms straight away.
+    configuration
+        .subscriptions
+        .bot_protection
+        .min_form_age_seconds = 0;
+    configuration
+}
+
+/// Creates the database described by `config`, without any tables.
+pub async fn create_database(config: &DatabaseSettings) {
+    let mut connection = PgConnection::connect_with(&config.without_db())
+        .await
+        .expect("failed to connect to postgres");
+
+    connection
+        .execute(format!(r#"CREATE DATABASE "{}";"#, config.database_name).as_str())
+        .await
+        .expect("Failed to create ephemeral database.");
+}
+
+/// Creates the database described by `config` and migrates it.
+pub async fn configure_database(config: &DatabaseSettings) -> PgPool {
+    create_database(config).await;
+
+    // migrate the database
+    let connection_pool = PgPool::connect_with(config.with_db())
+        .await
+        .expect("Failed to connect to postgres after creating ephemeral db");
+    sqlx::migrate!("./migrations")
+        .run(&connection_pool)
+        .await
+        .expect("failed to run DB migration ./migrations");
+    connection_pool
+}
+
 /// asserts that response is a redirect (303) to location.
 pub fn assert_is_redirect_to(response: &reqwest::Response, location: &str) {
     assert_eq!(response.status().as_u16(), 303);
@@ -1,9 +1,14 @@
+use std::time::Duration;
+
 use wiremock::{
     matchers::{method, path},
     Mock, ResponseTemplate,
 };
 
-use crate::spawn_app::spawn_app;
+use zero2prod2::domain::SubscriptionStatus;
+
+use crate::dns_stub::DnsStub;
+use crate::spawn_app::{spawn_app, spawn_app_with};
 
 #[tokio::test]
 async fn subscribe_returns_a_200_for_a_valid_form_data() {
@@ -41,14 +46,16 @@ async fn subscribe_persists_new_subscriber() {
     // Assert
     assert_eq!(response.status().as_u16(), 200);
 
-    let saved = sqlx::query!("SELECT email, name, status from subscriptions")
-        .fetch_one(&app.db_pool)
-        .await
-        .expect("Failed to fetch saved subscription.");
+    let saved = sqlx::query!(
+        r#"SELECT email, name, status as "status: SubscriptionStatus" from subscriptions"#
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .expect("Failed to fetch saved subscription.");
 
     assert_eq!(saved.email, "stan@gmail.com");
     assert_eq!(saved.name, "stanley the third");
-    assert_eq!(saved.status, "pending_confirmation");
+    assert_eq!(saved.status, SubscriptionStatus::Pending);
 }
 
 #[tokio::test]
@@ -161,3 +168,360 @@ async fn subscribe_fails_if_there_is_a_fatal_database_error() {
     // Assert
     assert_eq!(response.status().as_u16(), 500);
 }
+
+#[tokio::test]
+async fn retrying_a_subscription_with_the_same_idempotency_key_sends_one_email() {
+    // Arrange
+    let app = spawn_app().await;
+    let body = "name=stanley%20the%20human&email=stan%40ley.com";
+    let idempotency_key = uuid::Uuid::new_v4().to_string();
+
+    Mock::given(path("/v3/mail/send"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response1 = app
+        .post_subscriptions_with_idempotency_key(body.into(), &idempotency_key)
+        .await;
+    let response2 = app
+        .post_subscriptions_with_idempotency_key(body.into(), &idempotency_key)
+        .await;
+
+    // Assert
+    assert_eq!(response1.status().as_u16(), 200);
+    assert_eq!(response2.status().as_u16(), 200);
+    // mock asserts on drop.
+}
+
+#[tokio::test]
+async fn idempotency_keys_of_anonymous_clients_are_scoped_per_client() {
+    // Arrange
+    let app = spawn_app().await;
+    let idempotency_key = uuid::Uuid::new_v4().to_string();
+
+    Mock::given(path("/v3/mail/send"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    // Act - two clients happen to pick the same key.
+    let mut statuses = vec![];
+    for (user_agent, body) in [
+        ("client-a", "name=stanley&email=stan%40ley.com"),
+        ("client-b", "name=ursula&email=ursula%40domain.com"),
+    ] {
+        let response = reqwest::Client::new()
+            .post(format!("{}/subscriptions", &app.address))
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .header("Idempotency-Key", &idempotency_key)
+            .header("User-Agent", user_agent)
+            .body(format!("{}&form_token={}", body, app.form_token))
+            .send()
+            .await
+            .expect("Failed to execute request.");
+        statuses.push(response.status().as_u16());
+    }
+
+    // Assert
+    assert_eq!(statuses, vec![200, 200]);
+    // mock asserts on drop.
+}
+
+#[tokio::test]
+async fn subscribe_stores_the_email_with_a_normalized_domain() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(path("/v3/mail/send"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let body = "name=ursula&email=Ursula%40B%C3%BCcher.Example.COM";
+    let response = app.post_subscriptions(body.into()).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let saved = sqlx::query!("SELECT email FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscription.");
+    assert_eq!(saved.email, "Ursula@xn--bcher-kva.example.com");
+}
+
+#[tokio::test]
+async fn subscribing_an_already_subscribed_normalized_email_looks_like_a_new_subscription() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(path("/v3/mail/send"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let response = app
+        .post_subscriptions("name=foo&email=foo%40example.com".into())
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    // Act
+    let response = app
+        .post_subscriptions("name=foo&email=foo%40Example.COM".into())
+        .await;
+
+    // Assert - same answer as for a new address, no second email.
+    assert_eq!(response.status().as_u16(), 200);
+    let n_subscriptions: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(n_subscriptions, 1);
+}
+
+#[tokio::test]
+async fn unsubscribed_and_bounced_addresses_can_subscribe_again() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(path("/v3/mail/send"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(4)
+        .mount(&app.email_server)
+        .await;
+
+    for (email, status) in [
+        ("le@guin.com", SubscriptionStatus::Unsubscribed),
+        ("ursula@guin.com", SubscriptionStatus::Bounced),
+    ] {
+        let body = format!("name=le%20guin&email={}", urlencoding::encode(email));
+        app.post_subscriptions(body.clone()).await;
+        let email_requests = app.email_server.received_requests().await.unwrap();
+        let old_links = app.get_confirmation_links(email_requests.last().unwrap());
+        sqlx::query!(
+            "UPDATE subscriptions SET status = $1 WHERE email = $2",
+            status as SubscriptionStatus,
+            email
+        )
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+        // Act
+        let response = app.post_subscriptions(body).await;
+
+        // Assert - a new confirmation email, the old link doesn't confirm anymore.
+        assert_eq!(response.status().as_u16(), 200, "{:?}", status);
+        let saved = sqlx::query!(
+            r#"SELECT status AS "status: SubscriptionStatus" FROM subscriptions WHERE email = $1"#,
+            email
+        )
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+        assert_eq!(saved.status, SubscriptionStatus::Pending);
+        let email_requests = app.email_server.received_requests().await.unwrap();
+        let new_links = app.get_confirmation_links(email_requests.last().unwrap());
+        assert_ne!(new_links.html, old_links.html);
+        let response = reqwest::get(old_links.html).await.unwrap();
+        assert_eq!(response.status().as_u16(), 401, "{:?}", status);
+        let response = reqwest::get(new_links.html).await.unwrap();
+        assert_eq!(response.status().as_u16(), 200, "{:?}", status);
+    }
+}
+
+#[tokio::test]
+async fn the_case_of_the_local_part_is_folded_when_configured() {
+    // Arrange
+    let app = spawn_app_with(|c| c.subscriptions.fold_email_local_part_case = true).await;
+    Mock::given(path("/v3/mail/send"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let response = app
+        .post_subscriptions("name=foo&email=Foo%40example.com".into())
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    // Act
+    let response = app
+        .post_subscriptions("name=foo&email=FOO%40example.com".into())
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let saved = sqlx::query!("SELECT email FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscription.");
+    assert_eq!(saved.email, "foo@example.com");
+}
+
+#[tokio::test]
+async fn subscribe_rejects_disposable_email_domains() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(path("/v3/mail/send"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    for email in [
+        "throwaway%40mailinator.com",
+        "throwaway%40inbox.Mailinator.com",
+    ] {
+        // Act
+        let body = format!("name=throwaway&email={}", email);
+        let response = app.post_subscriptions(body).await;
+
+        // Assert
+        assert_eq!(response.status().as_u16(), 400);
+        assert!(response
+            .text()
+            .await
+            .unwrap()
+            .contains("is a disposable email domain"));
+    }
+}
+
+#[tokio::test]
+async fn disposable_domains_are_reloaded_from_their_file() {
+    // Arrange
+    let file = std::env::temp_dir().join(format!("disposable-{}.txt", uuid::Uuid::new_v4()));
+    std::fs::write(&file, "# Nothing yet.\n").unwrap();
+    let app = spawn_app_with(|c| {
+        c.subscriptions.disposable_domains.file = Some(file.clone());
+        c.subscriptions
+            .disposable_domains
+            .reload_interval_milliseconds = 50;
+    })
+    .await;
+    Mock::given(path("/v3/mail/send"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    let response = app
+        .post_subscriptions("name=ursula&email=ursula%40throwaway.example.com".into())
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    // Act
+    std::fs::write(&file, "throwaway.example.com\n").unwrap();
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    // Assert
+    let response = app
+        .post_subscriptions("name=le%20guin&email=le.guin%40throwaway.example.com".into())
+        .await;
+    assert_eq!(response.status().as_u16(), 400);
+    std::fs::remove_file(&file).unwrap();
+}
+
+#[tokio::test]
+async fn subscribe_checks_that_the_email_domain_has_a_mail_server_when_enabled() {
+    // Arrange
+    let dns = DnsStub::start(&[("example.com", "mx.example.com"), ("null-mx.example", ".")]).await;
+    let app = spawn_app_with(|c| {
+        c.subscriptions.mx_lookup.enabled = true;
+        c.subscriptions.mx_lookup.nameserver = Some(dns.address);
+    })
+    .await;
+    Mock::given(path("/v3/mail/send"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let accepted = app
+        .post_subscriptions("name=ursula&email=ursula%40example.com".into())
+        .await;
+    let without_domain = app
+        .post_subscriptions("name=ursula&email=ursula%40nowhere.example".into())
+        .await;
+    let with_null_mx = app
+        .post_subscriptions("name=ursula&email=ursula%40null-mx.example".into())
+        .await;
+
+    // Assert
+    assert_eq!(accepted.status().as_u16(), 200);
+    for response in [without_domain, with_null_mx] {
+        assert_eq!(response.status().as_u16(), 400);
+        assert!(response
+            .text()
+            .await
+            .unwrap()
+            .contains("can't receive emails"));
+    }
+}
+
+#[tokio::test]
+async fn browsers_are_shown_a_check_your_inbox_page_after_subscribing() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(path("/v3/mail/send"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_subscriptions_from_browser("name=ursula&email=ursula%40example.com".into())
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(
+        response.headers().get("Content-Type").unwrap(),
+        "text/html; charset=utf-8"
+    );
+    let html = response.text().await.unwrap();
+    assert!(html.contains("Check your inbox"));
+    assert!(html.contains("ursula@example.com"));
+}
+
+#[tokio::test]
+async fn browsers_are_shown_an_error_page_when_subscribing_fails() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app
+        .post_subscriptions_from_browser("name=ursula&email=%3Cscript%3E".into())
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+    let html = response.text().await.unwrap();
+    assert!(html.contains("We couldn't subscribe you"));
+    assert!(html.contains("&lt;script&gt; invalid email"));
+    assert!(!html.contains("<script>"));
+}
+
+#[tokio::test]
+async fn programmatic_clients_get_a_bare_status_code_after_subscribing() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(path("/v3/mail/send"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_subscriptions("name=ursula&email=ursula%40example.com".into())
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response.text().await.unwrap().is_empty());
+}
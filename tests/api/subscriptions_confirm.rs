@@ -3,7 +3,9 @@ use wiremock::{
     Mock, ResponseTemplate,
 };
 
-use crate::spawn_app::spawn_app;
+use zero2prod2::domain::SubscriptionStatus;
+
+use crate::spawn_app::{spawn_app, BROWSER_ACCEPT};
 
 #[tokio::test]
 async fn confirmation_without_token_are_rejected_with_400() {
@@ -66,12 +68,140 @@ async fn clicking_on_the_confirmation_link_confirms_a_subscriber() {
         .unwrap();
 
     // Assert
-    let saved = sqlx::query!("SELECT email, name, status FROM subscriptions",)
-        .fetch_one(&app.db_pool)
-        .await
-        .expect("failed to fetch saved subscription");
+    let saved = sqlx::query!(
+        r#"SELECT email, name, status as "status: SubscriptionStatus" FROM subscriptions"#,
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .expect("failed to fetch saved subscription");
 
     assert_eq!(saved.email, "s@s.com");
     assert_eq!(saved.name, "stanley");
-    assert_eq!(saved.status, "confirmed");
+    assert_eq!(saved.status, SubscriptionStatus::Confirmed);
+}
+
+#[tokio::test]
+async fn confirming_after_unsubscribing_is_rejected_with_409() {
+    let app = spawn_app().await;
+    Mock::given(path("/v3/mail/send"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    app.post_subscriptions("name=stanley&email=s%40s.com".into())
+        .await;
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_links = app.get_confirmation_links(email_request);
+    sqlx::query!(
+        "UPDATE subscriptions SET status = $1",
+        SubscriptionStatus::Unsubscribed as SubscriptionStatus
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    // Act - visit the confirmation link.
+    let response = reqwest::get(confirmation_links.html).await.unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 409);
+    let saved = sqlx::query!(r#"SELECT status as "status: SubscriptionStatus" FROM subscriptions"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, SubscriptionStatus::Unsubscribed);
+}
+
+#[tokio::test]
+async fn browsers_are_shown_a_page_when_confirming() {
+    let app = spawn_app().await;
+    Mock::given(path("/v3/mail/send"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    app.post_subscriptions("name=stanley&email=s%40s.com".into())
+        .await;
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_links = app.get_confirmation_links(email_request);
+
+    // Act
+    let response = app
+        .api_client
+        .get(confirmation_links.html)
+        .header("Accept", BROWSER_ACCEPT)
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response
+        .text()
+        .await
+        .unwrap()
+        .contains("You&#x27;re subscribed!"));
+}
+
+#[tokio::test]
+async fn browsers_are_shown_a_page_for_invalid_confirmation_links() {
+    let app = spawn_app().await;
+
+    // Act
+    let response = app
+        .api_client
+        .get(format!(
+            "{}/subscriptions/confirm?subscription_token=invalid",
+            app.address
+        ))
+        .header("Accept", BROWSER_ACCEPT)
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 401);
+    assert!(response
+        .text()
+        .await
+        .unwrap()
+        .contains("Invalid confirmation link"));
+}
+
+#[tokio::test]
+async fn expired_confirmation_links_are_rejected_and_the_address_can_subscribe_again() {
+    let app = spawn_app().await;
+    Mock::given(path("/v3/mail/send"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+    app.post_subscriptions("name=stanley&email=s%40s.com".into())
+        .await;
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_links = app.get_confirmation_links(email_request);
+    sqlx::query!("UPDATE subscription_tokens SET created_at = now() - interval '30 days'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    // Act - visit the expired link.
+    let response = app
+        .api_client
+        .get(confirmation_links.html)
+        .header("Accept", BROWSER_ACCEPT)
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 410);
+    let html = response.text().await.unwrap();
+    assert!(html.contains("This link has expired"));
+    assert!(html.contains(r#"<a href="/">Subscribe again</a>"#));
+    let response = app
+        .post_subscriptions("name=stanley&email=s%40s.com".into())
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
 }
@@ -0,0 +1,68 @@
+//! Spans export, in its own test binary as the tracer provider is process wide.
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+use zero2prod2::configuration::{LogFileSettings, LogFormat, LogRotation, TelemetrySettings};
+use zero2prod2::telemetry::{get_subscriber, shutdown_tracing};
+
+#[tokio::test(flavor = "multi_thread")]
+async fn spans_are_exported_to_the_otlp_collector() {
+    // Arrange - stand in for the collector.
+    let collector = MockServer::start().await;
+    Mock::given(path("/v1/traces"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1..)
+        .mount(&collector)
+        .await;
+    let settings = TelemetrySettings {
+        otlp_endpoint: Some(collector.uri()),
+        ..Default::default()
+    };
+    let (subscriber, _) = get_subscriber("test".into(), "info".into(), &settings, std::io::sink);
+
+    // Act
+    tracing::subscriber::with_default(subscriber, || {
+        tracing::info_span!("Exported span").in_scope(|| tracing::info!("Hello"));
+    });
+    // Flushes the batch of pending spans.
+    tokio::task::spawn_blocking(shutdown_tracing).await.unwrap();
+
+    // Assert - mock verifies on drop.
+}
+
+#[test]
+fn logs_are_written_to_the_log_file_in_the_configured_format() {
+    // Arrange
+    let directory = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+    let settings = TelemetrySettings {
+        format: LogFormat::Compact,
+        log_file: Some(LogFileSettings {
+            directory: directory.to_string_lossy().into_owned(),
+            prefix: "test.log".into(),
+            rotation: LogRotation::Daily,
+        }),
+        ..Default::default()
+    };
+    let (subscriber, _) = get_subscriber("test".into(), "info".into(), &settings, std::io::sink);
+
+    // Act
+    tracing::subscriber::with_default(subscriber, || tracing::info!("Written to a file"));
+
+    // Assert
+    let files: Vec<_> = std::fs::read_dir(&directory)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .collect();
+    assert_eq!(files.len(), 1);
+    assert!(files[0]
+        .file_name()
+        .unwrap()
+        .to_string_lossy()
+        .starts_with("test.log."));
+    let logs = std::fs::read_to_string(&files[0]).unwrap();
+    assert!(logs.contains("INFO"));
+    assert!(logs.contains("Written to a file"));
+    // Compact lines, not bunyan JSON.
+    assert!(!logs.starts_with('{'));
+    std::fs::remove_dir_all(directory).unwrap();
+}